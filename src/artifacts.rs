@@ -0,0 +1,284 @@
+//! Housekeeping for archived program artifacts.
+use crate::config::{ArtifactPaths, Config, Network};
+use crate::idl;
+use crate::keypair;
+use crate::rpc::RpcClient;
+use anyhow::{anyhow, format_err, Result};
+use semver::Version;
+use solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Lists the archived versions of a program with their artifact paths,
+/// oldest first.
+///
+/// Directories are matched against `paths.artifact_layout`, so directories
+/// that aren't named after a semver version are ignored. A network is
+/// required if the layout contains `{network}`.
+pub fn archived(
+    config: &Config,
+    program: &str,
+    network: Option<&Network>,
+) -> Result<Vec<(Version, ArtifactPaths)>> {
+    let layout = config.paths.artifact_layout();
+    let network = match network {
+        Some(network) => network.to_string(),
+        None if layout.contains("{network}") => {
+            return Err(anyhow!(
+                "paths.artifact_layout {} contains {{network}}, so a network is required",
+                layout
+            ))
+        }
+        None => String::new(),
+    };
+    let layout = layout
+        .replace("{program}", program)
+        .replace("{network}", &network);
+    let components: Vec<&str> = layout.split('/').filter(|c| !c.is_empty()).collect();
+    let mut archived = vec![];
+    find_archived(
+        &config.paths.artifacts.0,
+        &components,
+        &Captures::default(),
+        &mut archived,
+    )?;
+    archived.sort_by(|(a, _), (b, _)| a.cmp(b));
+    archived.dedup_by(|(a, _), (b, _)| a == b);
+    Ok(archived)
+}
+
+/// Lists the archived versions of a program, oldest first.
+pub fn versions(config: &Config, program: &str, network: Option<&Network>) -> Result<Vec<Version>> {
+    Ok(archived(config, program, network)?
+        .into_iter()
+        .map(|(version, _)| version)
+        .collect())
+}
+
+/// What the `{version}` and `{major}` placeholders of an artifact layout
+/// matched so far.
+#[derive(Debug, Clone, Default)]
+struct Captures {
+    version: Option<Version>,
+    major: Option<u64>,
+}
+
+/// Walks the directories under `dir` that match the remaining layout
+/// components, collecting the versions whose directory matches all of them.
+fn find_archived(
+    dir: &Path,
+    components: &[&str],
+    captures: &Captures,
+    archived: &mut Vec<(Version, ArtifactPaths)>,
+) -> Result<()> {
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => {
+            if let Some(version) = &captures.version {
+                let major_matches = match captures.major {
+                    Some(major) => major == version.major,
+                    None => true,
+                };
+                if major_matches {
+                    archived.push((version.clone(), ArtifactPaths::from_root(dir.to_path_buf())));
+                }
+            }
+            return Ok(());
+        }
+    };
+    if !component.contains('{') {
+        let child = dir.join(component);
+        if child.is_dir() {
+            find_archived(&child, rest, captures, archived)?;
+        }
+        return Ok(());
+    }
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name();
+        let mut matched = vec![];
+        if !name
+            .to_str()
+            .map(|name| match_component(component, name, &mut matched))
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        let mut captures = captures.clone();
+        let mut valid = true;
+        for (placeholder, value) in matched {
+            match placeholder {
+                "version" => match Version::parse(value) {
+                    Ok(version) if captures.version.is_none() => captures.version = Some(version),
+                    Ok(version) => valid &= captures.version.as_ref() == Some(&version),
+                    Err(_) => valid = false,
+                },
+                _ => match value.parse::<u64>() {
+                    Ok(major) if captures.major.is_none() => captures.major = Some(major),
+                    Ok(major) => valid &= captures.major == Some(major),
+                    Err(_) => valid = false,
+                },
+            }
+        }
+        if valid {
+            find_archived(&entry.path(), rest, &captures, archived)?;
+        }
+    }
+    Ok(())
+}
+
+/// Matches a directory name against one component of an artifact layout,
+/// recording what each `{version}` and `{major}` placeholder stands for.
+fn match_component<'a>(
+    pattern: &str,
+    name: &'a str,
+    matched: &mut Vec<(&'static str, &'a str)>,
+) -> bool {
+    for placeholder in &["version", "major"] {
+        if let Some(rest) = pattern.strip_prefix(&format!("{{{}}}", placeholder)) {
+            for end in (1..=name.len()).filter(|end| name.is_char_boundary(*end)) {
+                matched.push((placeholder, &name[..end]));
+                if match_component(rest, &name[end..], matched) {
+                    return true;
+                }
+                matched.pop();
+            }
+            return false;
+        }
+    }
+    match (pattern.chars().next(), name.chars().next()) {
+        (None, None) => true,
+        (Some(p), Some(n)) if p == n => {
+            match_component(&pattern[p.len_utf8()..], &name[n.len_utf8()..], matched)
+        }
+        _ => false,
+    }
+}
+
+/// Finds which of the given archived versions is currently deployed on a
+/// network, by comparing the on-chain program data with each binary.
+pub fn deployed_version(
+    config: &Config,
+    program: &str,
+    network: &Network,
+    archived: &[(Version, ArtifactPaths)],
+) -> Result<Option<Version>> {
+    let network_config = config.network_config(network)?;
+    let client = RpcClient::new(&network_config.network_url(network))?
+        .with_commitment(config.deploy_config(network)?.commitment());
+    let offset = UpgradeableLoaderState::programdata_data_offset()?;
+    let versions: Vec<Version> = archived
+        .iter()
+        .map(|(version, _)| version.clone())
+        .collect();
+
+    for program_id in program_ids(config, program, &versions)? {
+        let (programdata, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        let data = match client.get_account_data(&programdata)? {
+            Some(data) if data.len() >= offset => data,
+            _ => continue,
+        };
+        let deployed = &data[offset..];
+        for (version, paths) in archived.iter().rev() {
+            let bin = match fs::read(&paths.bin) {
+                Ok(bin) => bin,
+                Err(_) => continue,
+            };
+            if deployed.len() >= bin.len()
+                && deployed[..bin.len()] == bin[..]
+                && deployed[bin.len()..].iter().all(|b| *b == 0)
+            {
+                return Ok(Some(version.clone()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Finds the version of a program deployed on a network: the archived
+/// version whose binary is on chain, or else the `version` of the Anchor IDL
+/// on chain, if the program has one.
+pub fn onchain_version(
+    config: &Config,
+    root: &Path,
+    program: &str,
+    network: &Network,
+) -> Result<Option<Version>> {
+    let archived = archived(config, program, Some(network))?;
+    if let Some(version) = deployed_version(config, program, network, &archived)? {
+        return Ok(Some(version));
+    }
+    let versions: Vec<Version> = archived.into_iter().map(|(version, _)| version).collect();
+    if !root.join("Anchor.toml").exists() {
+        return Ok(None);
+    }
+    let url = config.network_config(network)?.network_url(network);
+    for program_id in program_ids(config, program, &versions)? {
+        if let Some(version) = idl::fetch(config, root, &url, &program_id)
+            .as_ref()
+            .and_then(idl::version)
+        {
+            return Ok(Some(version));
+        }
+    }
+    Ok(None)
+}
+
+/// The distinct addresses of the given versions of a program, skipping
+/// versions without a program keypair.
+fn program_ids(config: &Config, program: &str, versions: &[Version]) -> Result<Vec<Pubkey>> {
+    let mut program_ids: Vec<Pubkey> = vec![];
+    for version in versions {
+        let kp_path = config.program_kp_path(version, program);
+        if !kp_path.exists() {
+            continue;
+        }
+        let program_id = keypair::read_keypair(&kp_path)?.pubkey();
+        if !program_ids.contains(&program_id) {
+            program_ids.push(program_id);
+        }
+    }
+    Ok(program_ids)
+}
+
+/// Deletes the artifacts of all but the `keep` most recent versions of a
+/// program, returning the versions and artifact directories that were (or,
+/// on a dry run, would be) deleted.
+///
+/// If a network is given, the version deployed there is never deleted.
+pub fn gc(
+    config: &Config,
+    program: &str,
+    keep: usize,
+    network: Option<&Network>,
+    dry_run: bool,
+) -> Result<Vec<(Version, PathBuf)>> {
+    let archived = archived(config, program, network)?;
+    let deployed = match network {
+        Some(network) => deployed_version(config, program, network, &archived)?,
+        None => None,
+    };
+    let stale: Vec<(Version, PathBuf)> = archived
+        .into_iter()
+        .rev()
+        .skip(keep)
+        .filter(|(version, _)| Some(version) != deployed.as_ref())
+        .map(|(version, paths)| (version, paths.root))
+        .collect();
+    if !dry_run {
+        for (_, root) in &stale {
+            fs::remove_dir_all(root)
+                .map_err(|e| format_err!("could not remove {}: {}", root.display(), e))?;
+        }
+    }
+    Ok(stale)
+}
@@ -0,0 +1,128 @@
+//! Resetting a workspace to a known state, for `captain clean`.
+use crate::artifacts;
+use crate::config::Config;
+use anyhow::{format_err, Result};
+use serde::Serialize;
+use solana_sdk::signature::Signer;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the temporary keypair files of upgrade buffers, so leftovers
+/// can be told apart from other temporary files.
+pub const BUFFER_KEYPAIR_PREFIX: &str = "captain-buffer-";
+
+/// A file or directory removed by [clean].
+#[derive(Debug, Clone, Serialize)]
+pub struct Removed {
+    pub path: PathBuf,
+    /// Address of the buffer, if the path is a leftover buffer keypair.
+    pub buffer: Option<String>,
+}
+
+/// Removes the build outputs of a program, or of all programs, from
+/// `target/deploy` and `target/idl`, and the buffer keypairs left in the
+/// temporary directory by upgrades that didn't clean up after themselves.
+/// Program keypairs in `target/deploy` are kept. Archived artifacts are only
+/// removed if `artifacts` is set.
+///
+/// Returns what was (or, on a dry run, would be) removed.
+pub fn clean(
+    config: &Config,
+    root: &Path,
+    program: Option<&str>,
+    artifacts: bool,
+    dry_run: bool,
+) -> Result<Vec<Removed>> {
+    let mut removed = vec![];
+    let target = root.join("target");
+    for path in build_outputs(&target.join("deploy"), program)?
+        .into_iter()
+        .chain(build_outputs(&target.join("idl"), program)?)
+    {
+        removed.push(Removed { path, buffer: None });
+    }
+    removed.extend(buffer_keypairs(&env::temp_dir())?);
+    if artifacts {
+        let roots = match program {
+            Some(program) => artifacts::archived(config, program, None)?
+                .into_iter()
+                .map(|(_, paths)| paths.root)
+                .collect(),
+            None if config.paths.artifacts.0.exists() => vec![config.paths.artifacts.0.clone()],
+            None => vec![],
+        };
+        for path in roots {
+            removed.push(Removed { path, buffer: None });
+        }
+    }
+    if !dry_run {
+        for Removed { path, .. } in &removed {
+            let result = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+            result.map_err(|e| format_err!("could not remove {}: {}", path.display(), e))?;
+        }
+    }
+    Ok(removed)
+}
+
+/// Lists the files in a build output directory that belong to `program`
+/// (under its dashed or underscored name), or all of them, except keypairs.
+fn build_outputs(dir: &Path, program: Option<&str>) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format_err!("could not list {}: {}", dir.display(), e)),
+    };
+    let mut outputs = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if !path.is_file() || name.ends_with("-keypair.json") {
+            continue;
+        }
+        let stem = name.split('.').next().unwrap_or_default().replace('-', "_");
+        let matches = match program {
+            Some(program) => stem == program.replace('-', "_"),
+            None => true,
+        };
+        if matches {
+            outputs.push(path);
+        }
+    }
+    outputs.sort();
+    Ok(outputs)
+}
+
+/// Lists the buffer keypairs left in `dir`, with the buffers' addresses.
+fn buffer_keypairs(dir: &Path) -> Result<Vec<Removed>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format_err!("could not list {}: {}", dir.display(), e)),
+    };
+    let mut keypairs = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        let is_buffer = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.starts_with(BUFFER_KEYPAIR_PREFIX))
+            .is_some();
+        if is_buffer && path.is_file() {
+            let buffer = solana_sdk::signer::keypair::read_keypair_file(&path)
+                .ok()
+                .map(|keypair| keypair.pubkey().to_string());
+            keypairs.push(Removed { path, buffer });
+        }
+    }
+    keypairs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(keypairs)
+}
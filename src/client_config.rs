@@ -0,0 +1,131 @@
+//! Client configs generated after a deploy, mapping programs to their
+//! addresses and IDLs for frontends.
+use crate::config::Network;
+use anyhow::{format_err, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A deployed program, as listed in a client config.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientProgram {
+    pub program_id: String,
+    /// Path of the released IDL, relative to the client config.
+    pub idl: Option<String>,
+}
+
+/// The programs of a deploy to one network.
+#[derive(Debug, Serialize)]
+pub struct ClientConfig {
+    pub network: Network,
+    pub programs: BTreeMap<String, ClientProgram>,
+}
+
+impl ClientConfig {
+    pub fn new(network: Network) -> Self {
+        ClientConfig {
+            network,
+            programs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a program, pointing to its IDL from the directory `path` will be
+    /// written to.
+    pub fn add(&mut self, program: &str, program_id: &str, idl: Option<&Path>, path: &Path) {
+        let idl = idl.map(|idl| {
+            let from = absolute(path.parent().unwrap_or_else(|| Path::new(".")));
+            let relative = relative_path(&absolute(idl), &from);
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            if relative.starts_with("../") {
+                relative
+            } else {
+                format!("./{}", relative)
+            }
+        });
+        self.programs.insert(
+            program.to_string(),
+            ClientProgram {
+                program_id: program_id.to_string(),
+                idl,
+            },
+        );
+    }
+
+    /// Renders the config as a TypeScript module if `path` ends in `.ts`,
+    /// and as JSON otherwise.
+    pub fn render(&self, path: &Path) -> Result<String> {
+        if path.extension().and_then(|e| e.to_str()) != Some("ts") {
+            return Ok(format!("{}\n", serde_json::to_string_pretty(self)?));
+        }
+        let mut out = String::from("// Generated by `captain deploy`. Do not edit.\n\n");
+        out.push_str(&format!(
+            "export const NETWORK = {:?} as const;\n\n",
+            self.network.to_string()
+        ));
+        out.push_str("export const PROGRAMS = {\n");
+        for (name, program) in &self.programs {
+            out.push_str(&format!("  {:?}: {{\n", name));
+            out.push_str(&format!("    programId: {:?},\n", program.program_id));
+            if let Some(idl) = &program.idl {
+                out.push_str(&format!("    idl: {:?},\n", idl));
+            }
+            out.push_str("  },\n");
+        }
+        out.push_str("} as const;\n");
+        Ok(out)
+    }
+
+    /// Writes the config to `path`, creating its directory.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| format_err!("could not create {}: {}", parent.display(), e))?;
+        }
+        fs::write(path, self.render(path)?)
+            .map_err(|e| format_err!("could not write {}: {}", path.display(), e))
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    match std::env::current_dir() {
+        Ok(dir) if path.is_relative() => normalize(&dir.join(path)),
+        _ => normalize(path),
+    }
+}
+
+/// Removes `.` and resolves `..` without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` relative to the directory `from`. Both must be absolute.
+fn relative_path(path: &Path, from: &Path) -> PathBuf {
+    let path: Vec<_> = path.components().collect();
+    let from: Vec<_> = from.components().collect();
+    let common = path.iter().zip(&from).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component);
+    }
+    relative
+}
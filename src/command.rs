@@ -1,20 +1,429 @@
+use crate::error::CaptainError;
+use crate::progress;
 use anyhow::{format_err, Result};
+use solana_sdk::pubkey::Pubkey;
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read};
 use std::process::Command;
 use std::process::Output;
 use std::process::Stdio;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 
-pub fn exec_unhandled(command: &mut Command) -> Result<Output> {
-    command
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()
-        .map_err(|e| format_err!("Error deploying: {}", e.to_string()))
+/// Whether a command's output is shown to the user or captured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    /// stdout and stderr are shown on Captain's stderr, keeping Captain's
+    /// stdout free for machine-readable output.
+    Inherit,
+    /// stdout and stderr are captured into the returned [Output].
+    Capture,
+    /// Like [OutputMode::Inherit], but stdout and stderr are also captured,
+    /// so failures can be classified and results such as transaction
+    /// signatures read.
+    Tee,
 }
 
-pub fn exec(command: &mut Command) -> Result<Output> {
-    let exit = exec_unhandled(command)?;
+/// How often a failed command is retried.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Immediate retries after the transaction's blockhash expired.
+    pub blockhash: u32,
+    /// Retries after any other failure, waiting twice as long each time.
+    pub backoff: u32,
+    /// Retries of anchor IDL commands after any failure, with backoff.
+    pub idl: u32,
+    /// Retries after a hardware wallet failed to sign, once the user has
+    /// reconnected it.
+    pub sign: u32,
+}
+
+impl RetryPolicy {
+    /// The policy for anchor commands. Anchor doesn't report errors in a
+    /// form that can be classified reliably, so every failure is retried
+    /// with backoff, up to [RetryPolicy::idl] times.
+    pub fn anchor(self) -> RetryPolicy {
+        RetryPolicy {
+            blockhash: 0,
+            backoff: self.idl,
+            idl: 0,
+            sign: 0,
+        }
+    }
+}
+
+/// Wait before the first retry of [RetryPolicy::backoff].
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Returns true if a solana command failed because its blockhash expired
+/// before the transaction landed, which is worth retrying right away.
+pub fn is_blockhash_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("blockhash not found") || stderr.contains("blockhash expired")
+}
+
+/// Returns true if a solana command failed because a hardware wallet
+/// couldn't sign, e.g. it was disconnected, locked or timed out, which is
+/// worth retrying once it is reconnected. Signatures the user rejected on
+/// the device are not.
+pub fn is_signer_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    if stderr.contains("rejected by the user") {
+        return false;
+    }
+    [
+        "remote wallet",
+        "ledger",
+        "hidapi",
+        "no device found",
+        "device type mismatch",
+        "locked device",
+        "protocol error",
+    ]
+    .iter()
+    .any(|pattern| stderr.contains(pattern))
+}
+
+/// Asks the user to reconnect their hardware wallet before signing again,
+/// waiting for Enter if stdin is a terminal.
+fn wait_for_signer(attempt: u32, retries: u32) {
+    eprintln!(
+        "Signing failed. Reconnect and unlock your hardware wallet, and open its Solana app ({}/{}).",
+        attempt, retries
+    );
+    if atty::is(atty::Stream::Stdin) {
+        eprint!("Press Enter to try again...");
+        let _ = io::stdin().lock().read_line(&mut String::new());
+    } else {
+        thread::sleep(INITIAL_BACKOFF);
+    }
+}
+
+/// Runs external commands, so that the solana and anchor invocations of a
+/// deploy can be replaced in tests.
+pub trait CommandRunner {
+    /// Runs `argv[0]` with the remaining arguments.
+    fn run(&self, argv: &[OsString], mode: OutputMode) -> Result<Output>;
+}
+
+/// Runs commands as real processes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, argv: &[OsString], mode: OutputMode) -> Result<Output> {
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| format_err!("cannot run an empty command"))?;
+        output(Command::new(program).args(args), mode)
+    }
+}
+
+/// Whether commands are printed before they run, set by `--verbose`.
+static ECHO_COMMANDS: AtomicBool = AtomicBool::new(false);
+/// Whether printed commands mask keypair paths, set by `--redact-secrets`.
+static REDACT_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// Flags whose value is a signer, which is masked when redacting secrets
+/// unless it is an address.
+const SIGNER_FLAGS: &[&str] = &[
+    "--keypair",
+    "--fee-payer",
+    "--program-id",
+    "--buffer",
+    "--provider.wallet",
+    "--upgrade-authority",
+    "--buffer-authority",
+    "--new-upgrade-authority",
+    "--new-buffer-authority",
+];
+
+/// Prints every command to stderr before running it, masking the paths of
+/// keypairs passed to it if `redact_secrets` is set.
+pub fn echo_commands(echo: bool, redact_secrets: bool) {
+    ECHO_COMMANDS.store(echo, Ordering::Relaxed);
+    REDACT_SECRETS.store(redact_secrets, Ordering::Relaxed);
+}
+
+/// Formats a command as a shell would read it, for echoing.
+fn display_command(command: &Command, redact_secrets: bool) -> String {
+    let mut redact_next = false;
+    argv(command)
+        .iter()
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            let redact = redact_next && Pubkey::from_str(&arg).is_err();
+            redact_next = redact_secrets && SIGNER_FLAGS.contains(&arg.as_ref());
+            if redact {
+                "<redacted>".to_string()
+            } else if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '\'') {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Prints a command about to run if commands are echoed.
+pub fn echo(command: &Command) {
+    if ECHO_COMMANDS.load(Ordering::Relaxed) {
+        eprintln!(
+            "+ {}",
+            display_command(command, REDACT_SECRETS.load(Ordering::Relaxed))
+        );
+    }
+}
+
+/// Spawns a command and waits for its output.
+fn output(command: &mut Command, mode: OutputMode) -> Result<Output> {
+    echo(command);
+    match mode {
+        OutputMode::Inherit => {
+            // with --quiet, only what the command reports on stderr is shown
+            if progress::is_quiet() {
+                command.stdout(Stdio::piped());
+            } else {
+                command.stdout(io::stderr());
+            }
+            command
+                .stderr(Stdio::inherit())
+                .output()
+                .map_err(|e| format_err!("Error deploying: {}", e.to_string()))
+        }
+        OutputMode::Capture => command
+            .output()
+            .map_err(|e| format_err!("Error running command: {}", e.to_string())),
+        OutputMode::Tee => {
+            let mut child = command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format_err!("Error deploying: {}", e.to_string()))?;
+            // read both pipes at once, so neither blocks the command when full
+            let echo_stdout = !progress::is_quiet();
+            let stdout = child
+                .stdout
+                .take()
+                .map(|pipe| thread::spawn(move || tee(pipe, echo_stdout)));
+            let stderr = match child.stderr.take() {
+                Some(pipe) => tee(pipe, true)?,
+                None => vec![],
+            };
+            let stdout = match stdout {
+                Some(reader) => reader
+                    .join()
+                    .map_err(|_| format_err!("could not read command output"))??,
+                None => vec![],
+            };
+            Ok(Output {
+                status: child.wait()?,
+                stdout,
+                stderr,
+            })
+        }
+    }
+}
+
+/// Copies a command's output to Captain's stderr line by line if `echo` is
+/// set, returning what was read.
+fn tee(pipe: impl Read, echo: bool) -> io::Result<Vec<u8>> {
+    let mut captured = vec![];
+    for line in BufReader::new(pipe).split(b'\n') {
+        let line = line?;
+        if echo {
+            eprintln!("{}", String::from_utf8_lossy(&line));
+        }
+        captured.extend_from_slice(&line);
+        captured.push(b'\n');
+    }
+    Ok(captured)
+}
+
+/// A canned response of a [RecordingRunner].
+struct Response {
+    pattern: String,
+    success: bool,
+    stdout: String,
+}
+
+/// Records the commands it is asked to run instead of running them.
+///
+/// Every command succeeds with empty output unless a response was registered
+/// with [RecordingRunner::respond]. Clones share their recorded calls, so a
+/// clone can be handed to a [crate::Workspace] and inspected afterwards.
+#[derive(Clone, Default)]
+pub struct RecordingRunner {
+    calls: Rc<RefCell<Vec<Vec<String>>>>,
+    responses: Rc<RefCell<Vec<Response>>>,
+}
+
+impl RecordingRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Responds to commands containing `pattern` (matched against the
+    /// space-joined arguments) with the given result and stdout.
+    ///
+    /// The first matching response wins.
+    pub fn respond(&self, pattern: &str, success: bool, stdout: &str) -> &Self {
+        self.responses.borrow_mut().push(Response {
+            pattern: pattern.to_string(),
+            success,
+            stdout: stdout.to_string(),
+        });
+        self
+    }
+
+    /// The commands run so far, as lists of arguments.
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.borrow().clone()
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run(&self, argv: &[OsString], _mode: OutputMode) -> Result<Output> {
+        let argv: Vec<String> = argv
+            .iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        let joined = argv.join(" ");
+        self.calls.borrow_mut().push(argv);
+        let responses = self.responses.borrow();
+        let response = responses
+            .iter()
+            .find(|response| joined.contains(&response.pattern));
+        let (success, stdout) = match response {
+            Some(response) => (response.success, response.stdout.clone().into_bytes()),
+            None => (true, vec![]),
+        };
+        Ok(Output {
+            status: exit_status(success),
+            stdout,
+            stderr: vec![],
+        })
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(success: bool) -> std::process::ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    // Wait statuses store the exit code in the second byte.
+    std::process::ExitStatus::from_raw(if success { 0 } else { 1 << 8 })
+}
+
+#[cfg(windows)]
+fn exit_status(success: bool) -> std::process::ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    std::process::ExitStatus::from_raw(if success { 0 } else { 1 })
+}
+
+/// The program and arguments of a command.
+fn argv(command: &Command) -> Vec<OsString> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_os_string())
+        .collect()
+}
+
+impl dyn CommandRunner + '_ {
+    /// Runs a command with inherited stdio, returning its output even if it
+    /// fails.
+    pub fn exec_unhandled(&self, command: &mut Command) -> Result<Output> {
+        self.run(&argv(command), OutputMode::Inherit)
+    }
+
+    /// Runs a command with inherited stdio, erroring if it fails.
+    pub fn exec(&self, command: &mut Command) -> Result<Output> {
+        let exit = self.exec_unhandled(command)?;
+        check_status(command, exit)
+    }
+
+    /// Runs a command and returns its trimmed stdout.
+    ///
+    /// Errors with the command's stderr if it fails.
+    pub fn exec_stdout(&self, command: &mut Command) -> Result<String> {
+        let output = self.run(&argv(command), OutputMode::Capture)?;
+        stdout(command, output)
+    }
+
+    /// Runs a command like [Self::exec], retrying it on failure as allowed by
+    /// the policy. Expired blockhashes are retried immediately, hardware
+    /// wallet failures once the user reconnected the device, and other
+    /// failures with exponential backoff.
+    pub fn exec_retrying(&self, command: &mut Command, policy: RetryPolicy) -> Result<Output> {
+        let argv = argv(command);
+        let (mut blockhash_retries, mut sign_retries, mut backoff_retries) = (0, 0, 0);
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let exit = self.run(&argv, OutputMode::Tee)?;
+            if exit.status.success() {
+                return Ok(exit);
+            }
+            let stderr = String::from_utf8_lossy(&exit.stderr);
+            if is_signer_error(&stderr) && sign_retries < policy.sign {
+                sign_retries += 1;
+                wait_for_signer(sign_retries, policy.sign);
+            } else if is_blockhash_error(&stderr) && blockhash_retries < policy.blockhash {
+                blockhash_retries += 1;
+                eprintln!(
+                    "Blockhash expired, retrying ({}/{})",
+                    blockhash_retries, policy.blockhash
+                );
+            } else if backoff_retries < policy.backoff {
+                backoff_retries += 1;
+                eprintln!(
+                    "Command failed, retrying in {}s ({}/{})",
+                    backoff.as_secs(),
+                    backoff_retries,
+                    policy.backoff
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+            } else {
+                return check_status(command, exit);
+            }
+        }
+    }
+}
+
+fn check_status(command: &Command, exit: Output) -> Result<Output> {
     if !exit.status.success() {
-        std::process::exit(exit.status.code().unwrap_or(1));
+        return Err(CaptainError::CommandFailed {
+            command: format!("{:?}", command),
+            code: exit.status.code().unwrap_or(1),
+            stderr: String::from_utf8_lossy(&exit.stderr).trim().to_string(),
+        }
+        .into());
     }
     Ok(exit)
 }
+
+fn stdout(command: &Command, output: Output) -> Result<String> {
+    let output = check_status(command, output)?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+pub fn exec_unhandled(command: &mut Command) -> Result<Output> {
+    output(command, OutputMode::Inherit)
+}
+
+/// Runs a command with inherited stdio, erroring if it fails.
+pub fn exec(command: &mut Command) -> Result<Output> {
+    let exit = exec_unhandled(command)?;
+    check_status(command, exit)
+}
+
+/// Runs a command and returns its trimmed stdout.
+///
+/// Errors with the command's stderr if it fails.
+pub fn exec_stdout(command: &mut Command) -> Result<String> {
+    let output = output(command, OutputMode::Capture)?;
+    stdout(command, output)
+}
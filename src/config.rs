@@ -1,14 +1,26 @@
+use crate::error::CaptainError;
+use crate::keypair::SignerSource;
+use crate::store::{self, ArtifactStore, LocalStore};
 use anyhow::{anyhow, format_err, Error, Result};
 use cargo_toml::Manifest;
+use reqwest::Url;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
 use semver::Version;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use serde_with::{DeserializeFromStr, SerializeDisplay};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
 use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use strum::VariantNames;
 use strum_macros::{AsRefStr, Display, EnumString, EnumVariantNames, IntoStaticStr};
 
 #[derive(
@@ -36,62 +48,613 @@ pub enum Network {
 }
 
 impl Network {
-    pub fn url(&self) -> &str {
+    /// The default RPC URL of the network. `debug` has none of its own: it
+    /// uses `$CAPTAIN_DEBUG_URL`, or else localnet's.
+    pub fn url(&self) -> String {
         match self {
-            Network::Devnet => "https://api.devnet.solana.com",
-            Network::Testnet => "https://api.testnet.solana.com",
-            Network::Mainnet => "https://api.mainnet-beta.solana.com",
-            Network::Localnet => "http://127.0.0.1:8899",
-            Network::Debug => "http://34.90.18.145:8899",
+            Network::Devnet => "https://api.devnet.solana.com".to_string(),
+            Network::Testnet => "https://api.testnet.solana.com".to_string(),
+            Network::Mainnet => "https://api.mainnet-beta.solana.com".to_string(),
+            Network::Localnet => "http://127.0.0.1:8899".to_string(),
+            Network::Debug => {
+                env::var("CAPTAIN_DEBUG_URL").unwrap_or_else(|_| Network::Localnet.url())
+            }
         }
     }
-    pub fn ws_url(&self) -> &str {
+
+    /// The default websocket URL of the network. `debug` uses
+    /// `$CAPTAIN_DEBUG_WS_URL`, else the one derived from
+    /// `$CAPTAIN_DEBUG_URL`, or else localnet's.
+    pub fn ws_url(&self) -> String {
         match self {
-            Network::Devnet => "wss://api.devnet.solana.com",
-            Network::Testnet => "wss://api.testnet.solana.com",
-            Network::Mainnet => "wss://api.mainnet-beta.solana.com",
-            Network::Localnet => "ws://127.0.0.1:9000",
-            Network::Debug => "ws://34.90.18.145:9000",
+            Network::Devnet => "wss://api.devnet.solana.com".to_string(),
+            Network::Testnet => "wss://api.testnet.solana.com".to_string(),
+            Network::Mainnet => "wss://api.mainnet-beta.solana.com".to_string(),
+            Network::Localnet => "ws://127.0.0.1:8900".to_string(),
+            Network::Debug => match (
+                env::var("CAPTAIN_DEBUG_WS_URL"),
+                env::var("CAPTAIN_DEBUG_URL"),
+            ) {
+                (Ok(ws_url), _) => ws_url,
+                (Err(_), Ok(url)) => derive_ws_url(&url),
+                (Err(_), Err(_)) => Network::Localnet.ws_url(),
+            },
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Other names networks go by on the command line.
+pub const BUILTIN_NETWORK_ALIASES: &[(&str, Network)] = &[
+    ("main", Network::Mainnet),
+    ("mainnet-beta", Network::Mainnet),
+    ("prod", Network::Mainnet),
+    ("dev", Network::Devnet),
+    ("test", Network::Testnet),
+    ("local", Network::Localnet),
+];
+
+/// A network as given on the command line: its name or an alias, resolved
+/// with [Config::resolve_network] once the config is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkName(pub String);
+
+impl FromStr for NetworkName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(NetworkName(s.to_string()))
+    }
+}
+
+impl fmt::Display for NetworkName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl JsonSchema for Network {
+    fn schema_name() -> String {
+        "Network".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(Network::VARIANTS.iter().map(|v| (*v).into()).collect()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Config {
+    /// Version of the config schema. Updated by `captain migrate-config`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     pub paths: Paths,
+    /// TOML file with `[networks.<name>]` tables shared across workspaces,
+    /// relative to Captain.toml. Networks configured here take precedence,
+    /// key by key. Overridden by `--network-config-file`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub networks_include: Option<CaptainPath>,
+    /// Deploy transaction settings for every network
+    #[serde(default, skip_serializing_if = "DeployConfig::is_empty")]
+    pub deploy: DeployConfig,
     /// Network configuration
+    #[schemars(schema_with = "networks_schema")]
+    #[serde(default, serialize_with = "serialize_networks")]
     pub networks: BTreeMap<Network, NetworkConfig>,
+    /// Overrides for the CLI tools Captain runs
+    #[serde(default, skip_serializing_if = "Tools::is_empty")]
+    pub tools: Tools,
+    /// Hooks run after deploys
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Signing of released artifacts
+    #[serde(default, skip_serializing_if = "Security::is_empty")]
+    pub security: Security,
+    /// Per-program configuration, keyed by program name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub programs: BTreeMap<String, ProgramConfig>,
+    /// Other names for networks, e.g. `prod = "mainnet"`, accepted wherever a
+    /// network is given on the command line. They take precedence over the
+    /// built-in aliases
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, Network>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            version: Some(CONFIG_VERSION),
+            networks_include: None,
+            deploy: DeployConfig::default(),
             paths: Paths {
                 artifacts: CaptainPath(PathBuf::from("./.captain/artifacts/")),
                 program_keypairs: CaptainPath(PathBuf::from("./.captain/program_keypairs")),
+                keypair_scheme: KeypairScheme::default(),
+                program_dir: None,
+                artifact_layout: None,
+                artifacts_url: None,
             },
             networks: BTreeMap::default(),
+            tools: Tools::default(),
+            hooks: Hooks::default(),
+            security: Security::default(),
+            programs: BTreeMap::default(),
+            aliases: BTreeMap::default(),
         }
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+/// Writes networks sorted by name rather than by [Network]'s declaration
+/// order, so written configs are stable and easy to diff.
+fn serialize_networks<S: Serializer>(
+    networks: &BTreeMap<Network, NetworkConfig>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    networks
+        .iter()
+        .map(|(network, config)| (network.to_string(), config))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// Schema of [Config::networks], which only allows known networks as keys.
+fn networks_schema(gen: &mut SchemaGenerator) -> Schema {
+    let network_config = gen.subschema_for::<NetworkConfig>();
+    let mut schema = SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        ..Default::default()
+    };
+    let object = schema.object();
+    for network in Network::VARIANTS {
+        object
+            .properties
+            .insert(network.to_string(), network_config.clone());
+    }
+    object.additional_properties = Some(Box::new(false.into()));
+    schema.into()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Paths {
     /// Where binaries are stored
     pub artifacts: CaptainPath,
     /// Where program address keypairs are stored
     pub program_keypairs: CaptainPath,
+    /// Which versions of a program share a keypair
+    #[serde(default)]
+    pub keypair_scheme: KeypairScheme,
+    /// Directory containing the program crates, relative to the workspace
+    /// root. Defaults to `programs`
+    #[serde(
+        default,
+        alias = "programs_dir",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub program_dir: Option<PathBuf>,
+    /// Directory of a version's artifacts under `artifacts`, with
+    /// `{program}`, `{version}`, `{network}` and `{major}` placeholders.
+    /// Defaults to `{program}/{version}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_layout: Option<String>,
+    /// Remote store for artifacts, e.g. `s3://bucket/prefix`. `artifacts`
+    /// then holds local copies of them. S3 requires the `s3` feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts_url: Option<String>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+/// Version of the config schema this Captain reads and writes. Configs
+/// without a `version` are version 0.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Value of a network's `deployer` that reads the keypair from stdin.
+pub const STDIN_DEPLOYER: &str = "stdin";
+
+/// Where a version's artifacts go under the artifacts directory by default.
+pub const DEFAULT_ARTIFACT_LAYOUT: &str = "{program}/{version}";
+
+impl Paths {
+    /// The configured artifact layout, or [DEFAULT_ARTIFACT_LAYOUT].
+    pub fn artifact_layout(&self) -> &str {
+        self.artifact_layout
+            .as_deref()
+            .unwrap_or(DEFAULT_ARTIFACT_LAYOUT)
+    }
+}
+
+/// How program keypair files are named, which decides which versions of a
+/// program share an address.
+///
+/// Changing the scheme of an existing workspace changes the paths Captain
+/// looks for keypairs at, so existing keypairs must be renamed to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KeypairScheme {
+    /// `<program>-<major>.x.json`: a new address for every major version.
+    PerMajor,
+    /// `<program>.json`: one address for every version.
+    PerProgram,
+    /// `<program>-<version>.json`: a new address for every version.
+    PerVersion,
+}
+
+// `#[default]` on enum variants isn't available on our pinned toolchain.
+#[allow(clippy::derivable_impls)]
+impl Default for KeypairScheme {
+    fn default() -> Self {
+        KeypairScheme::PerMajor
+    }
+}
+
+/// How settled a transaction must be before Captain considers it done.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+    AsRefStr,
+    Display,
+    EnumString,
+    EnumVariantNames,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for Commitment {
+    fn default() -> Self {
+        Commitment::Confirmed
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ProgramConfig {
+    /// Bytes to allocate for the program data on the first deploy. Defaults
+    /// to twice the size of the program binary.
+    pub max_len: Option<usize>,
+    /// Path of the program's IDL, relative to the workspace root. Defaults to
+    /// `target/idl/<program>.json`; the IDL steps are skipped if it doesn't
+    /// exist.
+    pub idl_path: Option<PathBuf>,
+    /// Address the program must be deployed to. Deploys and upgrades fail if
+    /// the program keypair has a different address.
+    pub expected_program_id: Option<String>,
+    /// Programs deployed right after this one, at the same version, such as
+    /// a proxy that is released in lockstep with it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub companions: Vec<String>,
+    /// Programs that must be deployed before this one when several are
+    /// deployed at once, e.g. with `--program '*'`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Tools {
+    /// Path to the `solana` binary. Overridden by `CAPTAIN_SOLANA_BIN`.
+    pub solana_bin: Option<CaptainPath>,
+    /// Path to the `anchor` binary. Overridden by `CAPTAIN_ANCHOR_BIN`.
+    pub anchor_bin: Option<CaptainPath>,
+    /// Path to the `aws` binary, used to store artifacts in S3. Overridden
+    /// by `CAPTAIN_AWS_BIN`.
+    pub aws_bin: Option<CaptainPath>,
+    /// Path to the `solana-test-validator` binary, used by `captain
+    /// localnet`. Overridden by `CAPTAIN_TEST_VALIDATOR_BIN`.
+    pub test_validator_bin: Option<CaptainPath>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Hooks {
+    /// URL the receipt of every deploy and upgrade is POSTed to as JSON.
+    pub post_deploy_url: Option<String>,
+    /// Shell command run after every deploy and upgrade, with the receipt in
+    /// `CAPTAIN_RECEIPT` and its fields in `CAPTAIN_PROGRAM`,
+    /// `CAPTAIN_VERSION`, `CAPTAIN_NETWORK`, `CAPTAIN_PROGRAM_ID` and
+    /// `CAPTAIN_ACTION`.
+    pub post_deploy_command: Option<String>,
+}
+
+/// Settings of the transactions that deploy and upgrade programs, set under
+/// `[deploy]` and overridden per network under `[networks.<network>.deploy]`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct DeployConfig {
+    /// Priority fee for deploy transactions, in micro-lamports per compute unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority_fee: Option<u64>,
+    /// Compute units each deploy transaction requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compute_unit_limit: Option<u32>,
+    /// Skip preflight simulation of deploy transactions, e.g. on congested
+    /// clusters where a lagging RPC makes simulation fail spuriously
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_preflight: Option<bool>,
+    /// Commitment level to wait for when sending and reading transactions.
+    /// Defaults to `confirmed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<Commitment>,
+    /// Send deploy transactions through the RPC instead of the TPU
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_rpc: Option<bool>,
+}
+
+/// Keys of [DeployConfig] that used to be set directly on networks.
+pub const DEPLOY_CONFIG_KEYS: &[&str] = &[
+    "priority_fee",
+    "compute_unit_limit",
+    "skip_preflight",
+    "commitment",
+    "use_rpc",
+];
+
+impl DeployConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == DeployConfig::default()
+    }
+
+    /// These settings, with the ones `overrides` sets taking precedence.
+    pub fn merge(&self, overrides: &DeployConfig) -> DeployConfig {
+        DeployConfig {
+            priority_fee: overrides.priority_fee.or(self.priority_fee),
+            compute_unit_limit: overrides.compute_unit_limit.or(self.compute_unit_limit),
+            skip_preflight: overrides.skip_preflight.or(self.skip_preflight),
+            commitment: overrides.commitment.or(self.commitment),
+            use_rpc: overrides.use_rpc.or(self.use_rpc),
+        }
+    }
+
+    pub fn commitment(&self) -> Commitment {
+        self.commitment.unwrap_or_default()
+    }
+
+    pub fn skip_preflight(&self) -> bool {
+        self.skip_preflight.unwrap_or(false)
+    }
+
+    pub fn use_rpc(&self) -> bool {
+        self.use_rpc.unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Security {
+    /// Keypair that signs released program binaries, writing
+    /// `program.so.sig` next to them. May be encrypted.
+    pub signing_key: Option<CaptainPath>,
+}
+
+impl Security {
+    pub fn is_empty(&self) -> bool {
+        self.signing_key.is_none()
+    }
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.post_deploy_url.is_none() && self.post_deploy_command.is_none()
+    }
+}
+
+impl Tools {
+    pub fn is_empty(&self) -> bool {
+        self.solana_bin.is_none()
+            && self.anchor_bin.is_none()
+            && self.aws_bin.is_none()
+            && self.test_validator_bin.is_none()
+    }
+
+    /// The `solana` binary to run.
+    pub fn solana(&self) -> PathBuf {
+        resolve_tool("CAPTAIN_SOLANA_BIN", &self.solana_bin, "solana")
+    }
+
+    /// The `anchor` binary to run.
+    pub fn anchor(&self) -> PathBuf {
+        resolve_tool("CAPTAIN_ANCHOR_BIN", &self.anchor_bin, "anchor")
+    }
+
+    /// The `aws` binary to run.
+    pub fn aws(&self) -> PathBuf {
+        resolve_tool("CAPTAIN_AWS_BIN", &self.aws_bin, "aws")
+    }
+
+    /// The `solana-test-validator` binary to run.
+    pub fn test_validator(&self) -> PathBuf {
+        resolve_tool(
+            "CAPTAIN_TEST_VALIDATOR_BIN",
+            &self.test_validator_bin,
+            "solana-test-validator",
+        )
+    }
+}
+
+fn resolve_tool(env_var: &str, configured: &Option<CaptainPath>, default: &str) -> PathBuf {
+    env::var(env_var)
+        .ok()
+        .and_then(|bin| bin.parse::<CaptainPath>().ok())
+        .or_else(|| configured.clone())
+        .map(|bin| bin.as_path_buf())
+        .unwrap_or_else(|| PathBuf::from(default))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct NetworkConfig {
-    pub deployer: CaptainPath,
-    /// The upgrade authority address.
+    /// Keypair that deploys programs. Defaults to the solana CLI's keypair
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployer: Option<CaptainPath>,
+    /// The upgrade authority address. A keypair path or signer URI is
+    /// resolved to its address, and also signs as the upgrade authority
+    /// unless `upgrade_authority_keypair` is set
     pub upgrade_authority: String,
+    /// Keypair or signer URI of the upgrade authority, for the transactions
+    /// it signs, e.g. upgrades. Its address must be `upgrade_authority`.
+    /// `UPGRADE_AUTHORITY_KEYPAIR` takes precedence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upgrade_authority_keypair: Option<CaptainPath>,
     /// URL
     pub url: Option<String>,
-    /// Websocket URL
+    /// Websocket URL. Derived from `url` if unset, e.g. `ws://127.0.0.1:8900`
+    /// for `http://127.0.0.1:8899`
     pub ws_url: Option<String>,
+    /// Keypair that pays transaction fees instead of the deployer
+    pub fee_payer: Option<CaptainPath>,
+    /// Deploy transaction settings for this network, overriding `[deploy]`
+    #[serde(default, skip_serializing_if = "DeployConfig::is_empty")]
+    pub deploy: DeployConfig,
+    /// Addresses Captain may hand the upgrade authority to. Any address is
+    /// allowed if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_upgrade_authorities: Option<Vec<String>>,
+}
+
+impl NetworkConfig {
+    /// The configured RPC URL, or the default URL of the network.
+    pub fn network_url(&self, network: &Network) -> String {
+        self.url.clone().unwrap_or_else(|| network.url())
+    }
+
+    /// The configured websocket URL. Defaults to the one derived from the
+    /// configured RPC URL, or else the default websocket URL of the network.
+    pub fn network_ws_url(&self, network: &Network) -> String {
+        match (&self.ws_url, &self.url) {
+            (Some(ws_url), _) => ws_url.clone(),
+            (None, Some(url)) => derive_ws_url(url),
+            (None, None) => network.ws_url(),
+        }
+    }
+
+    /// Whether the deployer is configured as `"stdin"` or `"stdin://"`, to
+    /// be read from stdin instead of a file.
+    pub fn deployer_from_stdin(&self) -> bool {
+        self.deployer
+            .as_ref()
+            .map(|deployer| SignerSource::from_path(&deployer.0) == SignerSource::Stdin)
+            .unwrap_or(false)
+    }
+
+    /// The configured deployer keypair, or else the keypair the solana CLI
+    /// uses by default.
+    pub fn deployer_path(&self, network: &Network) -> Result<PathBuf> {
+        match &self.deployer {
+            Some(deployer) => Ok(deployer.as_path_buf()),
+            None => solana_cli_keypair().ok_or_else(|| {
+                anyhow!(
+                    "No deployer is configured for {} and the solana CLI has no default keypair. Set `deployer` under `[networks.{}]` in Captain.toml.",
+                    network,
+                    network
+                )
+            }),
+        }
+    }
+
+    /// Resolves the upgrade authority, which may be an address, a path to a
+    /// keypair file or a signer URI, to its address. URIs are resolved with
+    /// `solana`.
+    pub fn upgrade_authority_pubkey(&self, solana: &Path) -> Result<Pubkey> {
+        if let Ok(pubkey) = Pubkey::from_str(&self.upgrade_authority) {
+            return Ok(pubkey);
+        }
+        match SignerSource::parse(&self.upgrade_authority) {
+            SignerSource::File(path) => solana_sdk::signer::keypair::read_keypair_file(&path)
+                .map(|kp| kp.pubkey())
+                .map_err(|_| {
+                    format_err!(
+                        "upgrade authority {} is neither an address nor a readable keypair",
+                        self.upgrade_authority
+                    )
+                }),
+            source => source.pubkey(solana),
+        }
+    }
+
+    /// The signer of the upgrade authority: `upgrade_authority_keypair`, or
+    /// else `upgrade_authority` if it is a keypair or signer URI rather than
+    /// an address.
+    pub fn upgrade_authority_signer(&self) -> Option<SignerSource> {
+        match &self.upgrade_authority_keypair {
+            Some(keypair) => Some(SignerSource::from_path(&keypair.as_path_buf())),
+            None if Pubkey::from_str(&self.upgrade_authority).is_err() => {
+                Some(SignerSource::parse(&self.upgrade_authority))
+            }
+            None => None,
+        }
+    }
+
+    /// Checks that `upgrade_authority_keypair`, if set, is the keypair of
+    /// `upgrade_authority`. URIs are resolved with `solana`.
+    pub fn check_upgrade_authority_keypair(&self, network: &Network, solana: &Path) -> Result<()> {
+        let keypair = match &self.upgrade_authority_keypair {
+            Some(keypair) => SignerSource::from_path(&keypair.as_path_buf()),
+            None => return Ok(()),
+        };
+        let signer = keypair.pubkey(solana)?;
+        let authority = self.upgrade_authority_pubkey(solana)?;
+        if signer != authority {
+            return Err(anyhow!(
+                "upgrade_authority_keypair of {} is {}, not the upgrade authority {}",
+                network,
+                signer,
+                authority
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the upgrade authority is in `allowed_upgrade_authorities`,
+    /// if the network restricts which authorities programs may be handed to.
+    /// The upgrade authority is only resolved if it does.
+    pub fn check_allowed_upgrade_authority(&self, network: &Network, solana: &Path) -> Result<()> {
+        let allowed = match &self.allowed_upgrade_authorities {
+            Some(allowed) => allowed,
+            None => return Ok(()),
+        };
+        let authority = self.upgrade_authority_pubkey(solana)?;
+        for address in allowed {
+            let pubkey = Pubkey::from_str(address).map_err(|_| {
+                format_err!(
+                    "invalid address {} in allowed_upgrade_authorities of {}",
+                    address,
+                    network
+                )
+            })?;
+            if pubkey == authority {
+                return Ok(());
+            }
+        }
+        Err(anyhow!(
+            "Upgrade authority {} is not allowed on {}. allowed_upgrade_authorities: [{}]",
+            authority,
+            network,
+            allowed.join(", ")
+        ))
+    }
+}
+
+/// The keypair the solana CLI signs with by default: the `keypair_path` of
+/// `~/.config/solana/cli/config.yml`, or else `~/.config/solana/id.json` if
+/// it exists.
+fn solana_cli_keypair() -> Option<PathBuf> {
+    let expand = |path: &str| PathBuf::from(shellexpand::tilde(path).as_ref());
+    let configured = fs::read_to_string(expand("~/.config/solana/cli/config.yml"))
+        .ok()
+        .and_then(|config| {
+            config.lines().find_map(|line| {
+                line.strip_prefix("keypair_path:")
+                    .map(|path| expand(path.trim().trim_matches(|c| c == '"' || c == '\'')))
+            })
+        });
+    configured.or_else(|| Some(expand("~/.config/solana/id.json")).filter(|path| path.exists()))
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -102,67 +665,169 @@ pub struct ArtifactPaths {
 }
 
 impl ArtifactPaths {
+    /// Paths of a program version's artifacts under an artifacts directory,
+    /// laid out by an artifact layout template.
+    pub fn new(
+        artifacts_dir: &Path,
+        layout: &str,
+        network: &Network,
+        version: &Version,
+        program: &str,
+    ) -> Self {
+        let dir = layout
+            .replace("{program}", program)
+            .replace("{network}", network.as_ref())
+            .replace("{version}", &version.to_string())
+            .replace("{major}", &version.major.to_string());
+        ArtifactPaths::from_root(artifacts_dir.join(dir))
+    }
+
+    /// Paths of the artifacts in a version's artifact directory.
+    pub fn from_root(root: PathBuf) -> Self {
+        ArtifactPaths {
+            root: root.clone(),
+            bin: root.join("program.so"),
+            idl: root.join("idl.json"),
+        }
+    }
+
     pub fn exist(&self) -> bool {
         self.bin.exists() || self.idl.exists()
     }
 }
 
 impl Config {
+    /// Configuration of a program, or the defaults if it has none.
+    pub fn program_config(&self, program: &str) -> ProgramConfig {
+        self.programs.get(program).cloned().unwrap_or_default()
+    }
+
+    /// Resolves a network given on the command line by its name or an
+    /// alias, from `[aliases]` or else [BUILTIN_NETWORK_ALIASES].
+    pub fn resolve_network(&self, name: &NetworkName) -> Result<Network> {
+        let name = name.0.as_str();
+        if let Ok(network) = Network::from_str(name) {
+            return Ok(network);
+        }
+        let builtin = BUILTIN_NETWORK_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, network)| network);
+        match self.aliases.get(name).or(builtin) {
+            Some(network) => Ok(network.clone()),
+            None => Err(anyhow!(
+                "Unknown network {}. Expected one of {}, or an alias: {}",
+                name,
+                Network::VARIANTS.join(", "),
+                BUILTIN_NETWORK_ALIASES
+                    .iter()
+                    .map(|(alias, _)| *alias)
+                    .chain(self.aliases.keys().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
     /// Path to the keypair of the deployer.
     pub fn network_config(&self, network: &Network) -> Result<&NetworkConfig> {
         self.networks
             .get(network)
-            .ok_or_else(|| format_err!("network {} not found", network))
+            .ok_or_else(|| CaptainError::NetworkNotConfigured(network.clone()).into())
+    }
+
+    /// The deploy settings of a network: `[deploy]` overridden by
+    /// `[networks.<network>.deploy]`.
+    pub fn deploy_config(&self, network: &Network) -> Result<DeployConfig> {
+        Ok(self.deploy.merge(&self.network_config(network)?.deploy))
+    }
+
+    /// Directory containing the program crates: the config's `program_dir`,
+    /// or `programs`.
+    pub fn programs_dir(&self, root: &Path) -> PathBuf {
+        root.join(
+            self.paths
+                .program_dir
+                .as_deref()
+                .unwrap_or_else(|| Path::new("programs")),
+        )
     }
 
     /// Path to the keypair of a program.
     pub fn program_kp_path(&self, version: &Version, program: &str) -> PathBuf {
-        self.paths
-            .program_keypairs
-            .0
-            .join(format!("{}-{}.x.json", program, version.major))
+        let file_name = match self.paths.keypair_scheme {
+            KeypairScheme::PerMajor => format!("{}-{}.x.json", program, version.major),
+            KeypairScheme::PerProgram => format!("{}.json", program),
+            KeypairScheme::PerVersion => format!("{}-{}.json", program, version),
+        };
+        self.paths.program_keypairs.0.join(file_name)
     }
 
     /// Path to where program binaries should be saved.
-    pub fn artifact_paths(&self, version: &Version, program: &str) -> ArtifactPaths {
-        let root = self
-            .paths
-            .artifacts
-            .0
-            .join(program)
-            .join(version.to_string());
-        ArtifactPaths {
-            root: root.clone(),
-            bin: root.join("program.so"),
-            idl: root.join("idl.json"),
+    pub fn artifact_paths(
+        &self,
+        network: &Network,
+        version: &Version,
+        program: &str,
+    ) -> ArtifactPaths {
+        ArtifactPaths::new(
+            &self.paths.artifacts.0,
+            self.paths.artifact_layout(),
+            network,
+            version,
+            program,
+        )
+    }
+
+    /// Where artifacts are stored: `paths.artifacts_url` if set, else the
+    /// artifacts directory.
+    pub fn artifact_store(&self) -> Result<Box<dyn ArtifactStore>> {
+        match &self.paths.artifacts_url {
+            Some(url) => store::from_url(url, &self.paths.artifacts.0, self.tools.aws()),
+            None => Ok(Box::new(LocalStore)),
         }
     }
 
     // Searches all parent directories for a Captain.toml and Cargo.toml file.
     pub fn discover() -> Result<(Self, Manifest, PathBuf)> {
-        // Set to true if we ever see a Cargo.toml file when traversing the
-        // parent directories.
+        Self::discover_with(None)
+    }
 
+    /// Like [Config::discover], but reads shared network definitions from
+    /// `network_config_file`, if set, instead of the config's
+    /// `networks_include`.
+    pub fn discover_with(network_config_file: Option<&Path>) -> Result<(Self, Manifest, PathBuf)> {
+        let root = Self::find_root()?;
+        let cfg = Self::load(&root, network_config_file)?;
+        Ok((cfg, Manifest::from_path(root.join("Cargo.toml"))?, root))
+    }
+
+    /// Reads the Captain.toml in `root`, with its networks file, or
+    /// `network_config_file` if set, merged in.
+    pub fn load(root: &Path, network_config_file: Option<&Path>) -> Result<Self> {
+        let mut cfg_file = File::open(root.join("Captain.toml"))?;
+        let mut cfg_contents = String::new();
+        cfg_file.read_to_string(&mut cfg_contents)?;
+        Self::from_value(include_networks(
+            parse_toml(&cfg_contents)?,
+            root,
+            network_config_file,
+        )?)
+    }
+
+    /// Searches the current directory and its parents for a Captain.toml,
+    /// returning the directory it is in, without reading it.
+    pub fn find_root() -> Result<PathBuf> {
         let _cwd = std::env::current_dir()?;
         let mut cwd_opt = Some(_cwd.as_path());
 
         while let Some(cwd) = cwd_opt {
             let files = fs::read_dir(cwd)?;
-            // Cargo.toml file for this directory level.
             for f in files {
                 let p = f?.path();
                 if let Some(filename) = p.file_name() {
                     if filename.to_str() == Some("Captain.toml") {
-                        let mut cfg_file = File::open(&p)?;
-                        let mut cfg_contents = String::new();
-                        cfg_file.read_to_string(&mut cfg_contents)?;
-                        let cfg = cfg_contents.parse()?;
-                        let cwd_buf = cwd.to_path_buf();
-                        return Ok((
-                            cfg,
-                            Manifest::from_path(cwd_buf.join("Cargo.toml"))?,
-                            cwd_buf,
-                        ));
+                        return Ok(cwd.to_path_buf());
                     }
                 }
             }
@@ -177,6 +842,26 @@ impl Config {
 #[derive(Debug, Default, Serialize, DeserializeFromStr, Clone)]
 pub struct CaptainPath(pub PathBuf);
 
+impl JsonSchema for CaptainPath {
+    fn schema_name() -> String {
+        "CaptainPath".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                description: Some(
+                    "A file path. A leading `~` expands to the home directory.".to_string(),
+                ),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 impl CaptainPath {
     pub fn as_path_buf(&self) -> PathBuf {
         self.0.clone()
@@ -197,7 +882,189 @@ impl FromStr for Config {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        toml::from_str(s)
-            .map_err(|e| anyhow::format_err!("Unable to deserialize config: {}", e.to_string()))
+        Config::from_value(parse_toml(s)?)
+    }
+}
+
+impl Config {
+    /// Reads a config from parsed TOML, expanding environment variables.
+    fn from_value(mut value: toml::Value) -> Result<Self> {
+        if let Some(toml::Value::Table(networks)) = value.get_mut("networks") {
+            for (_, network) in networks.iter_mut() {
+                if let toml::Value::Table(network) = network {
+                    move_deploy_keys(network);
+                }
+            }
+        }
+        let config: Config = expand_env(value, "")?
+            .try_into()
+            .map_err(|e| anyhow::format_err!("Unable to deserialize config: {}", e.to_string()))?;
+        if let Some(version) = config.version.filter(|version| *version > CONFIG_VERSION) {
+            return Err(anyhow!(
+                "Captain.toml is at config version {}, but this Captain only reads up to version {}. Upgrade Captain.",
+                version,
+                CONFIG_VERSION
+            ));
+        }
+        if let Some(alias) = config
+            .aliases
+            .keys()
+            .find(|alias| Network::from_str(alias).is_ok())
+        {
+            return Err(anyhow!(
+                "aliases.{} would shadow the network of the same name",
+                alias
+            ));
+        }
+        let layout = config.paths.artifact_layout();
+        if !layout.contains("{version}") {
+            return Err(anyhow!(
+                "paths.artifact_layout {} must contain {{version}}, or versions would overwrite each other",
+                layout
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// Moves the deploy settings set directly on a network, as configs did before
+/// `[networks.<network>.deploy]`, into its `deploy` table. Settings already in
+/// the table win. Returns the keys that were moved.
+pub fn move_deploy_keys(network: &mut toml::value::Table) -> Vec<String> {
+    let mut moved = vec![];
+    for key in DEPLOY_CONFIG_KEYS {
+        let value = match network.remove(*key) {
+            Some(value) => value,
+            None => continue,
+        };
+        let deploy = network
+            .entry("deploy")
+            .or_insert_with(|| toml::Value::Table(Default::default()));
+        if let toml::Value::Table(deploy) = deploy {
+            deploy.entry(*key).or_insert(value);
+            moved.push(key.to_string());
+        }
+    }
+    moved
+}
+
+fn parse_toml(s: &str) -> Result<toml::Value> {
+    toml::from_str(s)
+        .map_err(|e| anyhow::format_err!("Unable to deserialize config: {}", e.to_string()))
+}
+
+/// Merges the networks of the config's networks file, or of `file` if set,
+/// into it. Keys the config sets win over the file's.
+fn include_networks(
+    mut config: toml::Value,
+    root: &Path,
+    file: Option<&Path>,
+) -> Result<toml::Value> {
+    let path = match file {
+        Some(path) => path.to_path_buf(),
+        None => match config.get("networks_include").and_then(|p| p.as_str()) {
+            // read before the rest of the config is expanded by [expand_env]
+            Some(path) => root.join(
+                shellexpand::env(path)
+                    .map_err(|e| format_err!("Unable to expand networks_include in config: {}", e))?
+                    .parse::<CaptainPath>()?
+                    .0,
+            ),
+            None => return Ok(config),
+        },
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format_err!("could not read networks file {}: {}", path.display(), e))?;
+    let shared: toml::Value = toml::from_str(&contents)
+        .map_err(|e| format_err!("could not parse networks file {}: {}", path.display(), e))?;
+    let shared = match shared.get("networks") {
+        Some(toml::Value::Table(networks)) => networks.clone(),
+        _ => {
+            return Err(anyhow!(
+                "networks file {} has no [networks.<name>] tables",
+                path.display()
+            ))
+        }
+    };
+    let table = config
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("Unable to deserialize config: not a table"))?;
+    let networks = table
+        .entry("networks")
+        .or_insert_with(|| toml::Value::Table(Default::default()));
+    if let toml::Value::Table(networks) = networks {
+        for (name, shared_network) in shared {
+            match (networks.get_mut(&name), shared_network) {
+                (Some(toml::Value::Table(local)), toml::Value::Table(shared)) => {
+                    for (key, value) in shared {
+                        local.entry(key).or_insert(value);
+                    }
+                }
+                (Some(_), _) => {}
+                (None, shared_network) => {
+                    networks.insert(name, shared_network);
+                }
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Derives the websocket URL of an RPC URL the way the solana CLI does:
+/// `http` becomes `ws` and `https` becomes `wss`, and an explicit port is
+/// incremented, e.g. `http://127.0.0.1:8899` becomes `ws://127.0.0.1:8900`.
+pub fn derive_ws_url(url: &str) -> String {
+    let mut ws_url = match Url::parse(url) {
+        Ok(ws_url) => ws_url,
+        Err(_) => return url.to_string(),
+    };
+    if let Some(port) = ws_url.port() {
+        // can only fail for URLs without a host, which have no port
+        let _ = ws_url.set_port(Some(port.saturating_add(1)));
+    }
+    let ws_url = ws_url.to_string();
+    match (ws_url.strip_prefix("https"), ws_url.strip_prefix("http")) {
+        (Some(rest), _) => format!("wss{}", rest),
+        (None, Some(rest)) => format!("ws{}", rest),
+        (None, None) => ws_url,
     }
 }
+
+/// Keys whose values are left as is by [expand_env]: shell commands, which
+/// read variables such as `$CAPTAIN_PROGRAM` when they run.
+const UNEXPANDED_KEYS: &[&str] = &["hooks.post_deploy_command"];
+
+/// Expands `$VAR`, `${VAR}` and `${VAR:-default}` in every string of a
+/// config with the process environment, erroring if a variable without a
+/// default is unset. `$$` is a literal `$`.
+///
+/// `key` is the dotted key of `value`, for error messages.
+fn expand_env(value: toml::Value, key: &str) -> Result<toml::Value> {
+    Ok(match value {
+        toml::Value::String(s) if !UNEXPANDED_KEYS.contains(&key) => toml::Value::String(
+            shellexpand::env(&s)
+                .map_err(|e| format_err!("Unable to expand {} in config: {}", key, e))?
+                .into_owned(),
+        ),
+        toml::Value::Array(values) => toml::Value::Array(
+            values
+                .into_iter()
+                .map(|value| expand_env(value, key))
+                .collect::<Result<_>>()?,
+        ),
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .into_iter()
+                .map(|(name, value)| {
+                    let key = if key.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", key, name)
+                    };
+                    Ok((name, expand_env(value, &key)?))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        value => value,
+    })
+}
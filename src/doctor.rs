@@ -0,0 +1,143 @@
+//! Checks that the toolchain and workspace are set up correctly.
+use crate::command;
+use crate::keypair::SignerSource;
+use crate::rpc::RpcClient;
+use crate::workspace::LoadOptions;
+use anyhow::{format_err, Result};
+use colored::*;
+use semver::Version;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Oldest `solana` CLI known to work with Captain.
+pub const MIN_SOLANA_VERSION: &str = "1.6.0";
+/// Oldest `anchor` CLI known to work with Captain.
+pub const MIN_ANCHOR_VERSION: &str = "0.11.0";
+
+/// Collects and prints the outcome of each check.
+#[derive(Default)]
+struct Checklist {
+    critical_failures: usize,
+}
+
+impl Checklist {
+    fn check(&mut self, name: &str, critical: bool, result: Result<String>) {
+        match result {
+            Ok(detail) => println!("{} {}: {}", "✔".green(), name, detail),
+            Err(err) if critical => {
+                self.critical_failures += 1;
+                println!("{} {}: {}", "✘".red(), name, err.to_string().red());
+            }
+            Err(err) => println!("{} {}: {}", "!".yellow(), name, err.to_string().yellow()),
+        }
+    }
+}
+
+/// Runs every check and prints a checklist.
+///
+/// Returns false if any critical check failed.
+pub fn run(options: &LoadOptions) -> bool {
+    let mut checklist = Checklist::default();
+
+    let discovered = options.discover();
+    let has_anchor = discovered
+        .as_ref()
+        .map(|(_, _, root)| root.join("Anchor.toml").exists())
+        .unwrap_or(false);
+    let tools = discovered
+        .as_ref()
+        .map(|(config, _, _)| config.tools.clone())
+        .unwrap_or_default();
+
+    checklist.check(
+        "solana",
+        true,
+        check_tool_version(&tools.solana(), MIN_SOLANA_VERSION),
+    );
+    checklist.check(
+        "anchor",
+        has_anchor,
+        check_tool_version(&tools.anchor(), MIN_ANCHOR_VERSION),
+    );
+
+    let (config, root) = match discovered {
+        Ok((config, _, root)) => {
+            checklist.check("Captain.toml", true, Ok(root.display().to_string()));
+            (config, root)
+        }
+        Err(err) => {
+            checklist.check("Captain.toml", true, Err(err));
+            return false;
+        }
+    };
+
+    for (network, network_config) in &config.networks {
+        checklist.check(
+            &format!("{} deployer", network),
+            true,
+            if network_config.deployer_from_stdin() {
+                Ok("read from stdin".to_string())
+            } else {
+                network_config.deployer_path(network).and_then(|deployer| {
+                    if let SignerSource::Uri(uri) = SignerSource::from_path(&deployer) {
+                        return Ok(format!("signer URI {}", uri));
+                    }
+                    solana_sdk::signer::keypair::read_keypair_file(&deployer)
+                        .map(|_| deployer.display().to_string())
+                        .map_err(|_| format_err!("could not read keypair {}", deployer.display()))
+                })
+            },
+        );
+        let url = network_config.network_url(network);
+        checklist.check(
+            &format!("{} RPC", network),
+            false,
+            RpcClient::new(&url)
+                .and_then(|client| client.get_health())
+                .map(|_| url),
+        );
+    }
+
+    checklist.check(
+        "artifacts directory",
+        true,
+        check_writable(&root.join(&config.paths.artifacts.0)),
+    );
+    checklist.check(
+        "program keypairs directory",
+        true,
+        check_writable(&root.join(&config.paths.program_keypairs.0)),
+    );
+
+    checklist.critical_failures == 0
+}
+
+/// Checks that a CLI tool is on the PATH and is at least the given version.
+fn check_tool_version(tool: &Path, min_version: &str) -> Result<String> {
+    let output = command::exec_stdout(Command::new(tool).arg("--version"))
+        .map_err(|_| format_err!("`{} --version` failed; is it on your PATH?", tool.display()))?;
+    // e.g. `solana-cli 1.7.10 (src:...)` or `anchor-cli 0.13.2`
+    let version = output
+        .split_whitespace()
+        .nth(1)
+        .and_then(|v| Version::parse(v).ok())
+        .ok_or_else(|| format_err!("could not parse version from `{}`", output))?;
+    let min_version = Version::parse(min_version)?;
+    if version < min_version {
+        return Err(format_err!(
+            "version {} is older than the minimum {}",
+            version,
+            min_version
+        ));
+    }
+    Ok(version.to_string())
+}
+
+/// Checks that a directory exists (creating it if needed) and is writable.
+fn check_writable(dir: &Path) -> Result<String> {
+    fs::create_dir_all(dir)
+        .and_then(|_| tempfile::tempfile_in(dir))
+        .map(|_| dir.display().to_string())
+        .map_err(|e| format_err!("{} is not writable: {}", dir.display(), e))
+}
@@ -0,0 +1,42 @@
+//! Errors for failure modes callers may want to handle.
+use crate::config::Network;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// A failure with a known cause.
+///
+/// Library functions return [anyhow::Error]s that wrap these, so callers can
+/// match on them with `err.downcast_ref::<CaptainError>()`.
+#[derive(Debug, Error)]
+pub enum CaptainError {
+    /// Returned by [crate::Workspace::upgrade] if the program hasn't been
+    /// deployed yet.
+    #[error("Program does not exist. Use `captain deploy` if you want to deploy the program for the first time.")]
+    ProgramNotFound,
+    #[error("Program bin path {} does not exist", .0.display())]
+    ProgramBinNotFound(PathBuf),
+    /// A configured or overridden IDL is missing.
+    #[error("Program idl path {} does not exist", .0.display())]
+    IdlNotFound(PathBuf),
+    /// A keypair file is missing. `role` is what it is for, e.g. `Deployer`.
+    #[error("{role} path {} does not exist", .path.display())]
+    KeypairNotFound { role: &'static str, path: PathBuf },
+    #[error("network {0} not found")]
+    NetworkNotConfigured(Network),
+    /// A solana, anchor or other command exited unsuccessfully. `stderr` is
+    /// empty if it was not captured.
+    #[error("{command} exited with status {code}{}", with_stderr(.stderr))]
+    CommandFailed {
+        command: String,
+        code: i32,
+        stderr: String,
+    },
+}
+
+fn with_stderr(stderr: &str) -> String {
+    if stderr.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", stderr)
+    }
+}
@@ -0,0 +1,60 @@
+//! Notifications sent after a deploy, e.g. to a chat webhook.
+use crate::command;
+use crate::config::Hooks;
+use crate::receipt::Receipt;
+use anyhow::Result;
+use colored::*;
+use std::process::Command;
+use std::time::Duration;
+
+/// Runs the post-deploy hooks for a receipt.
+///
+/// Hooks can't undo a deploy, so failures are printed as warnings instead of
+/// failing it.
+pub fn post_deploy(hooks: &Hooks, receipt: &Receipt) {
+    if let Some(url) = &hooks.post_deploy_url {
+        if let Err(err) = post(url, receipt) {
+            eprintln!(
+                "{}",
+                format!("WARNING: post-deploy webhook {} failed: {}", url, err).yellow()
+            );
+        }
+    }
+    if let Some(cmd) = &hooks.post_deploy_command {
+        if let Err(err) = run(cmd, receipt) {
+            eprintln!(
+                "{}",
+                format!("WARNING: post-deploy command failed: {}", err).yellow()
+            );
+        }
+    }
+}
+
+fn post(url: &str, receipt: &Receipt) -> Result<()> {
+    reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?
+        .post(url)
+        .json(receipt)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn run(cmd: &str, receipt: &Receipt) -> Result<()> {
+    command::exec(
+        Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("CAPTAIN_RECEIPT", serde_json::to_string(receipt)?)
+            .env("CAPTAIN_PROGRAM", &receipt.program)
+            .env("CAPTAIN_VERSION", receipt.version.to_string())
+            .env("CAPTAIN_NETWORK", receipt.network.to_string())
+            .env("CAPTAIN_PROGRAM_ID", &receipt.program_id)
+            .env(
+                "CAPTAIN_ACTION",
+                serde_json::to_value(receipt.action)?.as_str().unwrap_or(""),
+            ),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! Structural comparison of Anchor IDLs.
+use crate::command;
+use crate::config::Config;
+use anyhow::{format_err, Result};
+use colored::*;
+use semver::Version;
+use serde::Serialize;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Top-level IDL sections whose entries are identified by their `name`.
+const NAMED_SECTIONS: &[(&str, &str)] = &[
+    ("instructions", "instruction"),
+    ("accounts", "account"),
+    ("types", "type"),
+    ("events", "event"),
+    ("errors", "error"),
+];
+
+/// A single difference between two IDLs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum IdlChange {
+    Added {
+        kind: String,
+        name: String,
+    },
+    Removed {
+        kind: String,
+        name: String,
+    },
+    Changed {
+        kind: String,
+        name: String,
+        path: String,
+    },
+}
+
+impl fmt::Display for IdlChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdlChange::Added { kind, name } => {
+                write!(f, "{}", format!("+ {} {}", kind, name).green())
+            }
+            IdlChange::Removed { kind, name } => {
+                write!(f, "{}", format!("- {} {}", kind, name).red())
+            }
+            IdlChange::Changed { kind, name, path } => write!(
+                f,
+                "{}",
+                format!("~ {} {}: {} changed", kind, name, path).yellow()
+            ),
+        }
+    }
+}
+
+/// Fetches the IDL a program has on chain with `anchor idl fetch`, or `None`
+/// if it has none.
+pub fn fetch(config: &Config, root: &Path, url: &str, program_id: &Pubkey) -> Option<Value> {
+    serde_json::from_str(&fetch_raw(config, root, url, program_id)?).ok()
+}
+
+/// Like [fetch], but returns the IDL as printed by Anchor, keeping its
+/// formatting and key order.
+pub fn fetch_raw(config: &Config, root: &Path, url: &str, program_id: &Pubkey) -> Option<String> {
+    command::exec_stdout(
+        Command::new(config.tools.anchor())
+            .current_dir(root)
+            .arg("idl")
+            .arg("fetch")
+            .arg(program_id.to_string())
+            .arg("--provider.cluster")
+            .arg(url),
+    )
+    .ok()
+}
+
+/// The program version an IDL was generated from: `version` up to Anchor
+/// 0.29, `metadata.version` since.
+pub fn version(idl: &Value) -> Option<Version> {
+    idl.get("version")
+        .or_else(|| idl.get("metadata").and_then(|m| m.get("version")))
+        .and_then(|version| version.as_str())
+        .and_then(|version| Version::parse(version).ok())
+}
+
+/// Seed of the IDL account, derived from the program's signer address.
+const IDL_SEED: &str = "anchor:idl";
+
+/// Bytes of an IDL account before the IDL: the account discriminator, the
+/// authority and the length of the IDL data.
+const IDL_HEADER_LEN: usize = 8 + 32 + 4;
+
+/// Address of the account Anchor stores a program's IDL in.
+pub fn address(program_id: &Pubkey) -> Result<Pubkey> {
+    let (base, _) = Pubkey::find_program_address(&[], program_id);
+    Pubkey::create_with_seed(&base, IDL_SEED, program_id)
+        .map_err(|e| format_err!("could not derive the IDL address: {}", e))
+}
+
+/// Roughly the bytes an IDL account needs to hold an IDL, which Anchor stores
+/// as zlib-compressed JSON.
+pub fn account_len(idl: &Value) -> Result<usize> {
+    let json = serde_json::to_vec(idl)?;
+    Ok(IDL_HEADER_LEN + miniz_oxide::deflate::compress_to_vec_zlib(&json, 6).len())
+}
+
+pub fn read(path: &Path) -> Result<Value> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format_err!("could not read IDL {}: {}", path.display(), e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format_err!("could not parse IDL {}: {}", path.display(), e))
+}
+
+/// Lists the instructions, accounts, types, events and errors that were
+/// added, removed or changed between two IDLs.
+pub fn diff(old: &Value, new: &Value) -> Vec<IdlChange> {
+    let mut changes = vec![];
+    for (section, kind) in NAMED_SECTIONS {
+        let old_entries = named_entries(old, section);
+        let new_entries = named_entries(new, section);
+        for (name, old_entry) in &old_entries {
+            match new_entries.iter().find(|(n, _)| n == name) {
+                None => changes.push(IdlChange::Removed {
+                    kind: kind.to_string(),
+                    name: name.clone(),
+                }),
+                Some((_, new_entry)) => {
+                    let mut paths = vec![];
+                    diff_values("", old_entry, new_entry, &mut paths);
+                    changes.extend(paths.into_iter().map(|path| IdlChange::Changed {
+                        kind: kind.to_string(),
+                        name: name.clone(),
+                        path,
+                    }));
+                }
+            }
+        }
+        for (name, _) in &new_entries {
+            if !old_entries.iter().any(|(n, _)| n == name) {
+                changes.push(IdlChange::Added {
+                    kind: kind.to_string(),
+                    name: name.clone(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+fn named_entries<'a>(idl: &'a Value, section: &str) -> Vec<(String, &'a Value)> {
+    idl.get(section)
+        .and_then(|entries| entries.as_array())
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| {
+                    entry
+                        .get("name")
+                        .and_then(|name| name.as_str())
+                        .map(|name| (name.to_string(), entry))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Records the path of every leaf that differs between two JSON values.
+fn diff_values(path: &str, old: &Value, new: &Value, paths: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                let child = join_path(path, key);
+                match new.get(key) {
+                    Some(new_value) => diff_values(&child, old_value, new_value, paths),
+                    None => paths.push(child),
+                }
+            }
+            for key in new.keys().filter(|key| !old.contains_key(*key)) {
+                paths.push(join_path(path, key));
+            }
+        }
+        (Value::Array(old), Value::Array(new)) if old.len() == new.len() => {
+            for (i, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+                diff_values(&format!("{}[{}]", path, i), old_value, new_value, paths);
+            }
+        }
+        (old, new) if old != new => paths.push(if path.is_empty() {
+            "value".to_string()
+        } else {
+            path.to_string()
+        }),
+        _ => {}
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
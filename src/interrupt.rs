@@ -0,0 +1,141 @@
+//! Ctrl-C handling that closes the buffers of interrupted upgrades, so their
+//! rent isn't abandoned.
+//!
+//! [install] replaces the default Ctrl-C behavior: buffers registered with
+//! [track_buffer] are closed before Captain exits with status 130. A second
+//! Ctrl-C exits right away.
+use once_cell::sync::Lazy;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Exit status of a process killed by Ctrl-C (SIGINT on unix).
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// How many times Ctrl-C was pressed.
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Buffers written during this run that have not been used or closed yet.
+static BUFFERS: Lazy<Mutex<BTreeMap<usize, TrackedBuffer>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Debug)]
+struct TrackedBuffer {
+    buffer: Pubkey,
+    /// The buffer's keypair, which a failed close leaves in place.
+    keypair: PathBuf,
+    /// `solana program close` for the buffer, signed by its authority.
+    close: Command,
+}
+
+/// A buffer closed if Captain is interrupted, until dropped.
+#[derive(Debug)]
+pub struct BufferGuard {
+    id: usize,
+}
+
+impl BufferGuard {
+    /// Closes the buffer with `close` instead, e.g. after its authority
+    /// changed.
+    pub fn set_close(&self, close: Command) {
+        if let Some(tracked) = BUFFERS.lock().unwrap().get_mut(&self.id) {
+            tracked.close = close;
+        }
+    }
+}
+
+impl Drop for BufferGuard {
+    fn drop(&mut self) {
+        // an interrupted run is cleaned up by the watcher, which may not have
+        // taken the buffers yet
+        if !interrupted() {
+            BUFFERS.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+/// Closes `buffer` with `close` if Captain is interrupted before the returned
+/// guard is dropped. `keypair` is the buffer's keypair file.
+pub fn track_buffer(buffer: Pubkey, keypair: PathBuf, close: Command) -> BufferGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    BUFFERS.lock().unwrap().insert(
+        id,
+        TrackedBuffer {
+            buffer,
+            keypair,
+            close,
+        },
+    );
+    BufferGuard { id }
+}
+
+/// Whether Ctrl-C was pressed.
+pub fn interrupted() -> bool {
+    INTERRUPTS.load(Ordering::SeqCst) > 0
+}
+
+/// Installs the Ctrl-C handler and starts the thread that cleans up after
+/// it.
+pub fn install() {
+    let (notify, interrupt) = mpsc::channel();
+    let installed = ctrlc::set_handler(move || {
+        if INTERRUPTS.fetch_add(1, Ordering::SeqCst) > 0 {
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        let _ = notify.send(());
+    });
+    if let Err(err) = installed {
+        eprintln!("Could not install the Ctrl-C handler: {}", err);
+        return;
+    }
+    thread::spawn(move || {
+        if interrupt.recv().is_ok() {
+            cleanup();
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    });
+}
+
+/// Blocks forever if Ctrl-C was pressed, so the watcher can finish cleaning
+/// up before the process exits.
+pub fn wait_for_cleanup() {
+    while interrupted() {
+        thread::park();
+    }
+}
+
+/// Closes every tracked buffer, reporting those that can't be closed.
+fn cleanup() {
+    let buffers = std::mem::take(&mut *BUFFERS.lock().unwrap());
+    if buffers.is_empty() {
+        return;
+    }
+    eprintln!("Interrupted. Closing buffers written by this run...");
+    for (_, mut tracked) in buffers {
+        crate::command::echo(&tracked.close);
+        let closed = tracked
+            .close
+            .stdin(process::Stdio::null())
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        if closed {
+            eprintln!("Closed buffer {}", tracked.buffer);
+            let _ = fs::remove_file(&tracked.keypair);
+        } else {
+            eprintln!(
+                "Could not close buffer {}. Its keypair is at {}; close it with `solana program close {}`.",
+                tracked.buffer,
+                tracked.keypair.display(),
+                tracked.buffer
+            );
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Reading and writing program keypairs, optionally encrypted at rest.
+//!
+//! Encrypted keypairs are [age](https://age-encryption.org) files protected by
+//! a passphrase, so they can be committed alongside the rest of `.captain`.
+use crate::command;
+use crate::error::CaptainError;
+use age::secrecy::{ExposeSecret, Secret, SecretString};
+use anyhow::{format_err, Result};
+use once_cell::sync::OnceCell;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use tempfile::NamedTempFile;
+
+/// Header every age-encrypted file starts with.
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+
+/// Environment variable holding the passphrase of encrypted keypairs.
+pub const PASSPHRASE_ENV: &str = "CAPTAIN_KEYPAIR_PASSPHRASE";
+
+/// A keypair read from stdin, kept for every workspace loaded in this run.
+static STDIN_KEYPAIR: OnceCell<Vec<u8>> = OnceCell::new();
+
+/// Passphrase entered at the prompt, so it is only asked for once per run.
+static PROMPTED_PASSPHRASE: OnceCell<Mutex<Option<SecretString>>> = OnceCell::new();
+
+/// Returns true if the file at `path` is an age-encrypted keypair.
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut header = [0u8; AGE_HEADER.len()];
+    let mut file = fs::File::open(path)
+        .map_err(|e| format_err!("could not open {}: {}", path.display(), e))?;
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(header == AGE_HEADER),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads a keypair file, decrypting it first if it is encrypted.
+pub fn read_keypair(path: &Path) -> Result<Keypair> {
+    if !path.exists() {
+        return Err(CaptainError::KeypairNotFound {
+            role: "Keypair",
+            path: path.to_path_buf(),
+        }
+        .into());
+    }
+    let contents = if is_encrypted(path)? {
+        decrypt(path)?
+    } else {
+        fs::read(path).map_err(|e| format_err!("could not read {}: {}", path.display(), e))?
+    };
+    solana_sdk::signer::keypair::read_keypair(&mut contents.as_slice())
+        .map_err(|_| format_err!("could not read kp file {}", path.display()))
+}
+
+/// Reads the address of every keypair in a directory, keyed by file name
+/// without the `.json` (and `.x`) suffix, e.g. `my_program-1`.
+///
+/// Keypairs that can't be read are returned as errors instead of failing the
+/// whole listing.
+pub fn read_pubkeys(dir: &Path) -> Result<BTreeMap<String, Result<Pubkey>>> {
+    let mut pubkeys = BTreeMap::new();
+    for entry in
+        fs::read_dir(dir).map_err(|e| format_err!("could not list {}: {}", dir.display(), e))?
+    {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format_err!("invalid keypair file name {}", path.display()))?;
+        let name = name.strip_suffix(".x").unwrap_or(name).to_string();
+        pubkeys.insert(name, read_keypair(&path).map(|kp| kp.pubkey()));
+    }
+    Ok(pubkeys)
+}
+
+/// Writes a keypair file, encrypting it with the keypair passphrase if
+/// `encrypt` is set.
+pub fn write_keypair(keypair: &Keypair, path: &Path, encrypt: bool) -> Result<()> {
+    if !encrypt {
+        solana_sdk::signer::keypair::write_keypair_file(keypair, path)
+            .map_err(|_| format_err!("could not write kp file {}", path.display()))?;
+        return Ok(());
+    }
+    let mut plaintext = vec![];
+    solana_sdk::signer::keypair::write_keypair(keypair, &mut plaintext)
+        .map_err(|_| format_err!("could not serialize keypair"))?;
+    let encryptor = age::Encryptor::with_user_passphrase(passphrase()?);
+    let mut encrypted = vec![];
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(&plaintext)?;
+    writer.finish()?;
+    fs::write(path, encrypted)
+        .map_err(|e| format_err!("could not write kp file {}: {}", path.display(), e))
+}
+
+/// Where a signer's keypair comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignerSource {
+    /// A keypair file, which may be encrypted.
+    File(PathBuf),
+    /// A keypair JSON array piped to stdin.
+    Stdin,
+    /// A signer URI the solana CLI understands, e.g. `usb://ledger` or
+    /// `prompt://` for a seed phrase. Passed to the solana CLI as is, since
+    /// there is no file to check.
+    Uri(String),
+}
+
+impl SignerSource {
+    /// Parses a signer as written in `Captain.toml` or an environment
+    /// variable: `stdin` or `stdin://`, a `file://` URI or other URI, or
+    /// else a path with a leading `~` expanded.
+    ///
+    /// `stdin://` is read by Captain once rather than by the solana CLI,
+    /// since every solana command would otherwise need its own copy of stdin.
+    pub fn parse(source: &str) -> Self {
+        if source == "stdin" || source == "stdin://" {
+            SignerSource::Stdin
+        } else if let Some(path) = source.strip_prefix("file://") {
+            SignerSource::File(PathBuf::from(shellexpand::tilde(path).as_ref()))
+        } else if source.contains("://") {
+            SignerSource::Uri(source.to_string())
+        } else {
+            SignerSource::File(PathBuf::from(shellexpand::tilde(source).as_ref()))
+        }
+    }
+
+    /// Parses a signer kept in a path, such as a network's `deployer`.
+    pub fn from_path(path: &Path) -> Self {
+        Self::parse(&path.to_string_lossy())
+    }
+
+    /// Resolves the signer's address without touching the network. URIs are
+    /// resolved with `solana address`, which may need the device connected.
+    pub fn pubkey(&self, solana: &Path) -> Result<Pubkey> {
+        match self {
+            SignerSource::File(path) => Ok(read_keypair(path)?.pubkey()),
+            SignerSource::Stdin => Ok(read_keypair(stdin_to_temp_file()?.path())?.pubkey()),
+            SignerSource::Uri(uri) => {
+                let mut cmd = Command::new(solana);
+                cmd.arg("address").arg("--keypair").arg(uri);
+                command::echo(&cmd);
+                let output = cmd
+                    .output()
+                    .map_err(|e| format_err!("could not run {}: {}", solana.display(), e))?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    return Err(format_err!(
+                        "could not read the address of {}: {}",
+                        uri,
+                        if stderr.trim().is_empty() {
+                            output.status.to_string()
+                        } else {
+                            stderr.trim().to_string()
+                        }
+                    ));
+                }
+                String::from_utf8_lossy(&output.stdout)
+                    .trim()
+                    .parse()
+                    .map_err(|_| format_err!("`solana address` returned no address for {}", uri))
+            }
+        }
+    }
+}
+
+impl fmt::Display for SignerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerSource::File(path) => write!(f, "{}", path.display()),
+            SignerSource::Stdin => write!(f, "stdin"),
+            SignerSource::Uri(uri) => write!(f, "{}", uri),
+        }
+    }
+}
+
+/// Reads a keypair JSON array from stdin into a temporary file readable only
+/// by the current user, so it never has to be written to disk unprotected.
+/// The file is deleted when dropped.
+///
+/// Stdin is only read once per run; later calls reuse the same keypair.
+pub fn stdin_to_temp_file() -> Result<NamedTempFile> {
+    let keypair = STDIN_KEYPAIR.get_or_try_init(|| -> Result<Vec<u8>> {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|e| format_err!("could not read keypair from stdin: {}", e))?;
+        let bytes: Vec<u8> = serde_json::from_str(input.trim())
+            .map_err(|_| format_err!("stdin is not a keypair JSON array"))?;
+        Keypair::from_bytes(&bytes)
+            .map_err(|e| format_err!("stdin is not a valid keypair: {}", e))?;
+        Ok(bytes)
+    })?;
+    let keypair = Keypair::from_bytes(keypair)
+        .map_err(|e| format_err!("stdin is not a valid keypair: {}", e))?;
+    let mut file = NamedTempFile::new()?;
+    solana_sdk::signer::keypair::write_keypair(&keypair, &mut file)
+        .map_err(|_| format_err!("could not write stdin kp file"))?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// Decrypts a keypair to a temporary file readable only by the current user,
+/// for tools that need a keypair path. The file is deleted when dropped.
+pub fn decrypt_to_temp_file(path: &Path) -> Result<NamedTempFile> {
+    let keypair = read_keypair(path)?;
+    let mut file = NamedTempFile::new()?;
+    solana_sdk::signer::keypair::write_keypair(&keypair, &mut file)
+        .map_err(|_| format_err!("could not write decrypted kp file"))?;
+    file.flush()?;
+    Ok(file)
+}
+
+fn decrypt(path: &Path) -> Result<Vec<u8>> {
+    let encrypted =
+        fs::read(path).map_err(|e| format_err!("could not read {}: {}", path.display(), e))?;
+    let decryptor = match age::Decryptor::new(encrypted.as_slice())? {
+        age::Decryptor::Passphrase(d) => d,
+        _ => {
+            return Err(format_err!(
+                "{} is not encrypted with a passphrase",
+                path.display()
+            ))
+        }
+    };
+    let mut reader = decryptor
+        .decrypt(&passphrase()?, None)
+        .map_err(|e| format_err!("could not decrypt {}: {}", path.display(), e))?;
+    let mut plaintext = vec![];
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+/// Reads the passphrase from `CAPTAIN_KEYPAIR_PASSPHRASE`, or prompts for it.
+fn passphrase() -> Result<SecretString> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(Secret::new(passphrase));
+    }
+    let mut prompted = PROMPTED_PASSPHRASE
+        .get_or_init(|| Mutex::new(None))
+        .lock()
+        .map_err(|_| format_err!("passphrase lock poisoned"))?;
+    if let Some(passphrase) = prompted.as_ref() {
+        return Ok(Secret::new(passphrase.expose_secret().clone()));
+    }
+    let passphrase = prompt_passphrase()
+        .map_err(|e| format_err!("could not read passphrase (set {}): {}", PASSPHRASE_ENV, e))?;
+    *prompted = Some(Secret::new(passphrase.clone()));
+    Ok(Secret::new(passphrase))
+}
+
+/// Prompts for a passphrase on the terminal without echoing it.
+fn prompt_passphrase() -> Result<String> {
+    let tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    let stty = |arg: &str| -> Result<()> {
+        Command::new("stty")
+            .arg(arg)
+            .stdin(tty.try_clone()?)
+            .status()?;
+        Ok(())
+    };
+    eprint!("Keypair passphrase: ");
+    stty("-echo")?;
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+    stty("echo")?;
+    eprintln!();
+    read?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
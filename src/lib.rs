@@ -0,0 +1,62 @@
+//! Program management for Solana and Anchor.
+//!
+//! The `captain` binary is a thin CLI over this library, so the same deploy
+//! and upgrade flows can be driven from other Rust tools.
+#[macro_use]
+mod macros;
+
+pub mod artifacts;
+pub mod clean;
+pub mod client_config;
+pub mod command;
+pub mod config;
+pub mod doctor;
+pub mod error;
+pub mod hooks;
+pub mod idl;
+pub mod interrupt;
+pub mod keypair;
+pub mod localnet;
+pub mod lock;
+pub mod migrate;
+pub mod output;
+pub mod progress;
+pub mod receipt;
+pub mod rpc;
+pub mod schedule;
+pub mod setup;
+pub mod signing;
+pub mod state;
+pub mod status;
+pub mod store;
+pub mod watch;
+pub mod workspace;
+
+pub use crate::config::{Config, Network};
+pub use crate::progress::Progress;
+pub use crate::receipt::Receipt;
+pub use crate::workspace::{DeployOptions, LoadOptions, UpgradeOptions, Workspace};
+
+use anyhow::Result;
+
+/// Loads the workspace of a program and deploys it for the first time.
+pub fn deploy(
+    program: &str,
+    network: Network,
+    load_options: &LoadOptions,
+    options: &DeployOptions,
+    progress: &mut Progress,
+) -> Result<Receipt> {
+    workspace::load(program, network, load_options)?.deploy(progress, options)
+}
+
+/// Loads the workspace of a program and upgrades it.
+pub fn upgrade(
+    program: &str,
+    network: Network,
+    load_options: &LoadOptions,
+    options: &UpgradeOptions,
+    progress: &mut Progress,
+) -> Result<Receipt> {
+    workspace::load(program, network, load_options)?.upgrade(progress, options)
+}
@@ -0,0 +1,176 @@
+//! A `solana-test-validator` managed by Captain, for deploying to localnet
+//! without running a validator in another terminal.
+use crate::config::{Config, Network};
+use crate::rpc::RpcClient;
+use anyhow::{anyhow, format_err, Result};
+use reqwest::Url;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a started validator has to become healthy.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a starting validator is checked for health.
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Where Captain keeps the validator's pid, ledger and log:
+/// `.captain/localnet/`.
+pub fn dir(root: &Path) -> PathBuf {
+    root.join(".captain").join("localnet")
+}
+
+fn pid_path(root: &Path) -> PathBuf {
+    dir(root).join("validator.pid")
+}
+
+/// Whether a validator answers on `url`.
+pub fn is_running(url: &str) -> bool {
+    RpcClient::new(url)
+        .and_then(|client| client.get_health())
+        .is_ok()
+}
+
+/// A validator started by Captain. Dropping it leaves it running; use
+/// [Validator::stop_on_drop] for one that lives as long as the command.
+#[derive(Debug)]
+pub struct Validator {
+    child: Child,
+    root: PathBuf,
+    stop_on_drop: bool,
+}
+
+impl Validator {
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Stops the validator when dropped, e.g. when Captain exits.
+    pub fn stop_on_drop(mut self) -> Self {
+        self.stop_on_drop = true;
+        self
+    }
+}
+
+impl Drop for Validator {
+    fn drop(&mut self) {
+        if self.stop_on_drop {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            let _ = fs::remove_file(pid_path(&self.root));
+        }
+    }
+}
+
+/// Starts `solana-test-validator` on the localnet RPC port, with its ledger
+/// under `.captain/localnet/`, and waits until it is healthy.
+///
+/// Errors if a validator already answers on the localnet URL.
+pub fn start(config: &Config, root: &Path) -> Result<Validator> {
+    let url = localnet_url(config);
+    if is_running(&url) {
+        return Err(anyhow!("A validator is already running at {}", url));
+    }
+    let dir = dir(root);
+    fs::create_dir_all(&dir)
+        .map_err(|e| format_err!("could not create {}: {}", dir.display(), e))?;
+    let log_path = dir.join("validator.log");
+    let log = File::create(&log_path)
+        .map_err(|e| format_err!("could not create {}: {}", log_path.display(), e))?;
+    let bin = config.tools.test_validator();
+    let mut cmd = Command::new(&bin);
+    cmd.arg("--ledger")
+        .arg(dir.join("ledger"))
+        .arg("--rpc-port")
+        .arg(rpc_port(&url)?.to_string())
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    crate::command::echo(&cmd);
+    let child = cmd
+        .spawn()
+        .map_err(|e| format_err!("could not run {}: {}", bin.display(), e))?;
+    fs::write(pid_path(root), child.id().to_string())?;
+    let mut validator = Validator {
+        child,
+        root: root.to_path_buf(),
+        stop_on_drop: true,
+    };
+
+    let started = Instant::now();
+    while !is_running(&url) {
+        if let Some(status) = validator.child.try_wait()? {
+            return Err(anyhow!(
+                "{} exited with {} before becoming healthy. See {}.",
+                bin.display(),
+                status,
+                log_path.display()
+            ));
+        }
+        if started.elapsed() > STARTUP_TIMEOUT {
+            return Err(anyhow!(
+                "Validator at {} was not healthy after {}s. See {}.",
+                url,
+                STARTUP_TIMEOUT.as_secs(),
+                log_path.display()
+            ));
+        }
+        thread::sleep(STARTUP_POLL_INTERVAL);
+    }
+    validator.stop_on_drop = false;
+    Ok(validator)
+}
+
+/// Stops the validator started by `captain localnet start`. Returns its pid,
+/// or `None` if Captain didn't start one.
+pub fn stop(root: &Path) -> Result<Option<u32>> {
+    let pid_path = pid_path(root);
+    let pid: u32 = match fs::read_to_string(&pid_path) {
+        Ok(pid) => pid
+            .trim()
+            .parse()
+            .map_err(|_| format_err!("{} does not contain a pid", pid_path.display()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format_err!("could not read {}: {}", pid_path.display(), e)),
+    };
+    // the validator may have exited on its own, leaving a stale pid file
+    let _ = kill(pid);
+    fs::remove_file(&pid_path)
+        .map_err(|e| format_err!("could not remove {}: {}", pid_path.display(), e))?;
+    Ok(Some(pid))
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) -> Result<()> {
+    crate::command::exec(Command::new("kill").arg(pid.to_string()))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill(pid: u32) -> Result<()> {
+    crate::command::exec(
+        Command::new("taskkill")
+            .arg("/PID")
+            .arg(pid.to_string())
+            .arg("/F"),
+    )?;
+    Ok(())
+}
+
+/// The configured localnet RPC URL, or the default one.
+pub fn localnet_url(config: &Config) -> String {
+    config
+        .networks
+        .get(&Network::Localnet)
+        .map(|network_config| network_config.network_url(&Network::Localnet))
+        .unwrap_or_else(|| Network::Localnet.url())
+}
+
+fn rpc_port(url: &str) -> Result<u16> {
+    let url = Url::parse(url).map_err(|e| format_err!("invalid localnet URL {}: {}", url, e))?;
+    url.port_or_known_default()
+        .ok_or_else(|| anyhow!("localnet URL {} has no port", url))
+}
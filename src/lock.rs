@@ -0,0 +1,109 @@
+//! Advisory locks that keep concurrent deploys of a program from racing.
+use crate::config::Network;
+use anyhow::{anyhow, format_err, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An exclusive lock on deploying a program to a network, held until dropped.
+///
+/// The lock is an advisory `flock` on `.captain/locks/<program>-<network>.lock`,
+/// so the OS releases it if Captain dies mid-deploy. It is not enforced on
+/// platforms without `flock`.
+#[derive(Debug)]
+pub struct DeployLock {
+    file: File,
+    pub path: PathBuf,
+}
+
+impl DeployLock {
+    /// Takes the lock, or errors if another deploy holds it. With `wait`,
+    /// blocks until the other deploy is done instead.
+    pub fn acquire(
+        root: &Path,
+        program: &str,
+        network: &Network,
+        wait: bool,
+    ) -> Result<DeployLock> {
+        let dir = root.join(".captain").join("locks");
+        fs::create_dir_all(&dir)
+            .map_err(|e| format_err!("could not create {}: {}", dir.display(), e))?;
+        let path = dir.join(format!("{}-{}.lock", program, network));
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format_err!("could not open {}: {}", path.display(), e))?;
+
+        if !try_lock(&file)? {
+            if !wait {
+                return Err(anyhow!(
+                    "Another deploy of {} to {} is in progress (lock held on {}). Pass --wait-for-lock to wait for it.",
+                    program,
+                    network,
+                    path.display()
+                ));
+            }
+            info!(
+                "Waiting for another deploy of {} to {} to finish...",
+                program, network
+            );
+            lock(&file)?;
+        }
+        Ok(DeployLock { file, path })
+    }
+}
+
+impl Drop for DeployLock {
+    fn drop(&mut self) {
+        unlock(&self.file);
+    }
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(format_err!("could not lock: {}", err))
+    }
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(format_err!(
+            "could not lock: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn unlock(file: &File) {
+    use std::os::unix::io::AsRawFd;
+    unsafe {
+        libc::flock(file.as_raw_fd(), libc::LOCK_UN);
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> Result<bool> {
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &File) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn unlock(_file: &File) {}
@@ -1,20 +1,40 @@
 macro_rules! solana_cmd {
     ($workspace:expr) => {
-        std::process::Command::new("solana")
-            .arg("--url")
+        solana_cmd!($workspace, &$workspace.deployer_path)
+    };
+    ($workspace:expr, $keypair:expr) => {{
+        let mut cmd = std::process::Command::new($workspace.config.tools.solana());
+        cmd.arg("--url")
             .arg(&$workspace.network_url())
+            .arg("--ws")
+            .arg(&$workspace.network_ws_url())
             .arg("--keypair")
-            .arg(&$workspace.deployer_path)
-    };
+            .arg($keypair)
+            .arg("--commitment")
+            .arg($workspace.deploy_config().commitment().as_ref());
+        cmd
+    }};
 }
 
 macro_rules! anchor_cmd {
-    ($workspace:expr, $cmd:expr) => {
-        std::process::Command::new("anchor")
-            .arg($cmd)
+    ($workspace:expr, $cmd:expr) => {{
+        let mut cmd = std::process::Command::new($workspace.config.tools.anchor());
+        cmd.arg($cmd)
             .arg("--provider.cluster")
             .arg(&$workspace.network.to_string())
             .arg("--provider.wallet")
-            .arg(&$workspace.deployer_path)
+            .arg(&$workspace.deployer_path);
+        cmd
+    }};
+}
+
+/// Like `eprintln!`, for progress messages that `--quiet` silences. Warnings
+/// and errors should use `eprintln!` so they are always shown.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::progress::is_quiet() {
+            eprintln!($($arg)*);
+        }
     };
 }
@@ -1,43 +1,425 @@
 //! Captain entrypoint
-#[macro_use]
-mod macros;
-
-mod command;
-mod config;
-mod workspace;
-
-use crate::config::CaptainPath;
-use crate::config::Config;
-use crate::config::Network;
-use crate::config::NetworkConfig;
 use anyhow::{anyhow, format_err, Result};
+use captain::artifacts;
+use captain::clean::{self, Removed};
+use captain::client_config::ClientConfig;
+use captain::command;
+use captain::command::RetryPolicy;
+use captain::config::ArtifactPaths;
+use captain::config::CaptainPath;
+use captain::config::Commitment;
+use captain::config::Config;
+use captain::config::DeployConfig;
+use captain::config::Network;
+use captain::config::NetworkConfig;
+use captain::config::NetworkName;
+use captain::doctor;
+use captain::error::CaptainError;
+use captain::idl;
+use captain::info;
+use captain::interrupt;
+use captain::keypair;
+use captain::keypair::SignerSource;
+use captain::localnet;
+use captain::migrate;
+use captain::output::{self, Format, Output};
+use captain::progress::{self, format_duration, Progress};
+use captain::receipt::Action;
+use captain::schedule::{self, Timing};
+use captain::setup;
+use captain::signing;
+use captain::status::{self, ProgramStatus};
+use captain::watch;
+use captain::workspace::{
+    self, DeployOptions, FinalizedWait, LoadOptions, ProgramIdSource, UpgradeOptions, VersionSource,
+};
 use clap::{crate_authors, crate_description, crate_version, AppSettings, Clap};
 use colored::*;
 use semver::Version;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signer;
+use std::collections::BTreeMap;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::iter;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
 use strum::VariantNames;
-use tempfile::NamedTempFile;
+use strum_macros::{Display, EnumString, EnumVariantNames, IntoStaticStr};
+
+/// The default `--network`: `CAPTAIN_NETWORK` if set, otherwise devnet.
+///
+/// clap needs a `&'static str`, so the environment variable is leaked. This
+/// is only called while building the CLI.
+fn default_network() -> &'static str {
+    match env::var("CAPTAIN_NETWORK") {
+        Ok(network) => Box::leak(network.into_boxed_str()),
+        Err(_) => Network::Devnet.into(),
+    }
+}
+
+/// How failed deploy transactions are retried.
+#[derive(Debug, Clap)]
+pub struct RetryArgs {
+    #[clap(long, default_value = "0")]
+    #[clap(about = "Retries right away when a transaction's blockhash expires.")]
+    max_retries_blockhash: u32,
+    #[clap(long, default_value = "0")]
+    #[clap(about = "Retries with exponential backoff after other failures.")]
+    max_retries: u32,
+    #[clap(long, default_value = "0")]
+    #[clap(about = "Retries anchor IDL commands with exponential backoff after any failure.")]
+    max_retries_idl: u32,
+    #[clap(long, default_value = "1")]
+    #[clap(about = "Attempts at signing with a hardware wallet, prompting to reconnect it.")]
+    max_sign_attempts: u32,
+}
+
+impl From<RetryArgs> for RetryPolicy {
+    fn from(args: RetryArgs) -> Self {
+        RetryPolicy {
+            blockhash: args.max_retries_blockhash,
+            backoff: args.max_retries,
+            idl: args.max_retries_idl,
+            sign: args.max_sign_attempts.saturating_sub(1),
+        }
+    }
+}
+
+/// Overrides for the program artifacts to deploy.
+#[derive(Debug, Clap)]
+pub struct ArtifactOverrides {
+    #[clap(long)]
+    #[clap(about = "Deploy this program binary instead of the archived one.")]
+    bin: Option<PathBuf>,
+    #[clap(long, alias = "idl-path")]
+    #[clap(about = "Upload this IDL instead of target/idl/<program>.json.")]
+    idl: Option<PathBuf>,
+    #[clap(long, conflicts_with = "idl")]
+    #[clap(
+        about = "Build the IDL with `anchor idl build` if target/idl/<program>.json is missing."
+    )]
+    idl_build: bool,
+    #[clap(long)]
+    #[clap(about = "Fail unless the program binary has this hex-encoded SHA-256.")]
+    expect_sha256: Option<String>,
+    #[clap(long)]
+    #[clap(about = "Fail unless the program keypair has this address.")]
+    expect_program_id: Option<Pubkey>,
+    #[clap(long)]
+    #[clap(
+        about = "Use this program keypair instead of the one in the program keypairs directory."
+    )]
+    program_keypair: Option<PathBuf>,
+}
+
+impl ArtifactOverrides {
+    /// Returns true if these only make sense for a single program.
+    fn overrides_program(&self) -> bool {
+        self.bin.is_some()
+            || self.idl.is_some()
+            || self.expect_sha256.is_some()
+            || self.expect_program_id.is_some()
+            || self.program_keypair.is_some()
+    }
+}
+
+/// Source code syntaxes `export-keypair-pubkeys` can print addresses in,
+/// besides `--format`.
+#[derive(Clone, Copy, Debug, Display, EnumString, EnumVariantNames, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum PubkeyFormat {
+    /// `MY_PROGRAM_1=<pubkey>`
+    Env,
+    /// `export const PROGRAM_IDS = { ... } as const;`
+    Ts,
+}
+
+/// Which part of a version `bump` increments.
+#[derive(Clone, Copy, Debug, Display, EnumString, EnumVariantNames, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum BumpLevel {
+    Major,
+    Minor,
+    Patch,
+}
+
+impl BumpLevel {
+    /// The next version at this level, without pre-release or build metadata.
+    fn bump(self, version: &Version) -> Version {
+        match self {
+            BumpLevel::Major => Version::new(version.major + 1, 0, 0),
+            BumpLevel::Minor => Version::new(version.major, version.minor + 1, 0),
+            BumpLevel::Patch => Version::new(version.major, version.minor, version.patch + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clap)]
+pub enum ArtifactsCommand {
+    #[clap(about = "Deletes the artifacts of old versions of a program.")]
+    Gc {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(long)]
+        #[clap(about = "Number of most recent versions to keep.")]
+        keep: usize,
+        #[clap(long)]
+        #[clap(about = "List what would be deleted without deleting anything.")]
+        dry_run: bool,
+        #[clap(short, long)]
+        #[clap(about = "Never delete the version currently deployed to this network.")]
+        network: Option<NetworkName>,
+    },
+}
+
+#[derive(Debug, Clap)]
+pub enum IdlCommand {
+    #[clap(about = "Archives the IDL a program has on chain as its version's IDL artifact.")]
+    Fetch {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(
+            about = "Version to archive the IDL as. Defaults to the program's current version."
+        )]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Network to fetch the IDL from. Defaults to $CAPTAIN_NETWORK if set.")]
+        #[clap(default_value = default_network())]
+        network: NetworkName,
+        #[clap(long)]
+        #[clap(about = "Where to read the program address from.")]
+        #[clap(
+            default_value = ProgramIdSource::Keypair.into(),
+            possible_values = ProgramIdSource::VARIANTS
+        )]
+        program_id_source: ProgramIdSource,
+    },
+}
+
+#[derive(Debug, Clap)]
+pub enum LocalnetCommand {
+    #[clap(
+        about = "Starts solana-test-validator in the background on the localnet URL, with its ledger under .captain/localnet."
+    )]
+    Start,
+    #[clap(about = "Stops the validator started by `captain localnet start`.")]
+    Stop,
+}
 
 #[derive(Debug, Clap)]
 pub enum SubCommand {
     #[clap(about = "Initializes a new Captain workspace.")]
     Init,
+    #[clap(
+        about = "Interactively writes Captain.toml, asking only for values it doesn't have yet."
+    )]
+    Setup,
     #[clap(about = "Builds all programs. (Uses Anchor)")]
     Build,
+    #[clap(about = "Removes build outputs and leftover buffer keypairs.")]
+    Clean {
+        #[clap(short, long)]
+        #[clap(about = "Only remove the outputs of this program.")]
+        program: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Also remove archived artifacts.")]
+        artifacts: bool,
+        #[clap(long)]
+        #[clap(about = "List what would be removed without removing anything.")]
+        dry_run: bool,
+    },
+    #[clap(about = "Checks that the toolchain and workspace are set up correctly.")]
+    Doctor,
+    #[clap(about = "Prints the JSON Schema of Captain.toml.")]
+    Schema,
+    #[clap(about = "Upgrades Captain.toml to the current config schema.")]
+    MigrateConfig {
+        #[clap(long)]
+        #[clap(about = "Print the migrated config instead of writing it.")]
+        dry_run: bool,
+    },
+    #[clap(about = "Shows how the built IDL differs from a released version's IDL.")]
+    DiffIdl {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/idl/<id>.json")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(about = "Released version to compare against.")]
+        version: Version,
+        #[clap(short, long)]
+        #[clap(about = "Network of the release, if paths.artifact_layout contains {network}.")]
+        #[clap(default_value = default_network())]
+        network: NetworkName,
+    },
+    #[clap(about = "Generates a program keypair for a major version.")]
+    GenKeypair {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(about = "Version of the program. Defaults to the version in its Cargo.toml.")]
+        version: Option<Version>,
+        #[clap(long)]
+        #[clap(
+            about = "Encrypt the keypair with $CAPTAIN_KEYPAIR_PASSPHRASE or a prompted passphrase."
+        )]
+        encrypt: bool,
+    },
+    #[clap(about = "Copies an existing program keypair into the program keypairs directory.")]
+    ImportKeypair {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(about = "Version of the program. Defaults to the version in its Cargo.toml.")]
+        version: Option<Version>,
+        #[clap(long)]
+        #[clap(about = "Keypair file to import.")]
+        from: PathBuf,
+        #[clap(long)]
+        #[clap(about = "Overwrite the program's keypair if it already exists.")]
+        force: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Encrypt the keypair with $CAPTAIN_KEYPAIR_PASSPHRASE or a prompted passphrase. Encrypted keypairs stay encrypted."
+        )]
+        encrypt: bool,
+    },
+    #[clap(about = "Bumps the version of a program, generating a keypair for it if needed.")]
+    Bump {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(long)]
+        #[clap(about = "Part of the version to increment.")]
+        #[clap(possible_values = BumpLevel::VARIANTS)]
+        level: BumpLevel,
+        #[clap(long)]
+        #[clap(
+            about = "Encrypt a new keypair with $CAPTAIN_KEYPAIR_PASSPHRASE or a prompted passphrase."
+        )]
+        encrypt: bool,
+    },
+    #[clap(about = "Prints the addresses of all program keypairs.")]
+    ExportKeypairPubkeys {
+        #[clap(long, possible_values = PubkeyFormat::VARIANTS)]
+        #[clap(about = "Print the addresses as env vars or TypeScript instead.")]
+        syntax: Option<PubkeyFormat>,
+    },
+    #[clap(about = "Checks the signature of a released program binary.")]
+    VerifySignature {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(about = "Released version to check. Defaults to the program's current version.")]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Network whose artifacts to check. Defaults to $CAPTAIN_NETWORK if set.")]
+        #[clap(default_value = default_network())]
+        network: NetworkName,
+        #[clap(long)]
+        #[clap(
+            about = "Address the binary must be signed by. Defaults to that of security.signing_key."
+        )]
+        signer: Option<Pubkey>,
+    },
+    #[clap(about = "Manages archived program artifacts.")]
+    Artifacts {
+        #[clap(subcommand)]
+        command: ArtifactsCommand,
+    },
+    #[clap(about = "Manages on-chain Anchor IDLs.")]
+    Idl {
+        #[clap(subcommand)]
+        command: IdlCommand,
+    },
+    #[clap(about = "Runs a local validator to deploy to localnet.")]
+    Localnet {
+        #[clap(subcommand)]
+        command: LocalnetCommand,
+    },
     #[clap(about = "Request an airdrop.")]
     Airdrop { amount: u64 },
     #[clap(about = "Lists all available programs.")]
-    Programs,
+    Programs {
+        #[clap(long)]
+        #[clap(about = "Where to read program versions from.")]
+        #[clap(
+            default_value = VersionSource::Cargo.into(),
+            possible_values = VersionSource::VARIANTS
+        )]
+        version_from: VersionSource,
+    },
+    #[clap(about = "Reports whether programs are deployed on each configured network.")]
+    Status {
+        #[clap(short, long, required_unless_present = "all", conflicts_with = "all")]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Report every program that has a keypair.")]
+        all: bool,
+        #[clap(short, long)]
+        #[clap(about = "Only report this network.")]
+        network: Option<NetworkName>,
+    },
+    #[clap(about = "Prints the version of a program.")]
+    ProgramVersion {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(long)]
+        #[clap(about = "Where to read the version from.")]
+        #[clap(
+            default_value = VersionSource::Cargo.into(),
+            possible_values = VersionSource::VARIANTS
+        )]
+        version_from: VersionSource,
+        #[clap(long, requires = "network")]
+        #[clap(about = "Print the version deployed on --network instead.")]
+        on_chain: bool,
+        #[clap(short, long)]
+        #[clap(about = "Network to read the deployed version from.")]
+        network: Option<NetworkName>,
+    },
     #[clap(about = "Releases a program into the artifactory.")]
     Release {
         #[clap(short, long)]
         #[clap(about = "Name of the program in target/deploy/<id>.so")]
         program: String,
+        #[clap(long)]
+        #[clap(about = "Where to read the program version from.")]
+        #[clap(
+            default_value = VersionSource::Cargo.into(),
+            possible_values = VersionSource::VARIANTS
+        )]
+        version_from: VersionSource,
+        #[clap(long)]
+        #[clap(about = "Fail instead of warning if the program binary is older than its sources.")]
+        require_fresh: bool,
+        #[clap(long, conflicts_with = "require-fresh")]
+        #[clap(about = "Skip checking whether the program binary is older than its sources.")]
+        no_build_check: bool,
+        #[clap(long)]
+        #[clap(about = "Read and write artifacts here instead of paths.artifacts.")]
+        artifacts_dir: Option<PathBuf>,
+        #[clap(long)]
+        #[clap(
+            about = "Build the IDL with `anchor idl build` if target/idl/<program>.json is missing."
+        )]
+        idl_build: bool,
+        #[clap(short, long)]
+        #[clap(about = "Network to release for, if paths.artifact_layout contains {network}.")]
+        #[clap(default_value = default_network())]
+        network: NetworkName,
     },
     #[clap(about = "Deploys a program.")]
     Deploy {
@@ -48,33 +430,251 @@ pub enum SubCommand {
         #[clap(about = "Name of the program in target/deploy/<id>.so")]
         program: String,
         #[clap(short, long)]
-        #[clap(about = "Network to deploy to")]
         #[clap(
-            default_value = Network::Devnet.into(),
-            possible_values = Network::VARIANTS
+            about = "Comma-separated networks to deploy to, in order. Defaults to $CAPTAIN_NETWORK if set."
         )]
-        network: Network,
+        #[clap(
+            default_value = default_network(),
+            use_delimiter = true,
+            require_delimiter = true
+        )]
+        network: Vec<NetworkName>,
+        #[clap(long)]
+        #[clap(about = "Keep deploying to the remaining networks if one fails.")]
+        keep_going: bool,
+        #[clap(short, long, default_value = "1")]
+        #[clap(
+            about = "Deploy up to this many programs at once. Programs wait for those listed in their depends_on."
+        )]
+        jobs: usize,
+        #[clap(long)]
+        #[clap(
+            about = "Wait for each deploy to be finalized before moving on to the next network."
+        )]
+        wait_finalized: bool,
+        #[clap(long, default_value = "2")]
+        #[clap(about = "Seconds between checks for finalization with --wait-finalized.")]
+        finalized_poll_interval: u64,
+        #[clap(long, default_value = "120")]
+        #[clap(about = "Seconds to wait for finalization with --wait-finalized before failing.")]
+        finalized_timeout: u64,
         #[clap(short, long)]
         #[clap(about = "Skip the Anchor IDL upload.")]
         skip_anchor_idl: bool,
+        #[clap(flatten)]
+        artifacts: ArtifactOverrides,
+        #[clap(flatten)]
+        retry: RetryArgs,
+        #[clap(long)]
+        #[clap(
+            about = "Wait for a concurrent deploy of the program to finish instead of failing."
+        )]
+        wait_for_lock: bool,
+        #[clap(long)]
+        #[clap(about = "Skip checking that the network's RPC is healthy before starting.")]
+        no_health_check: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Skip checking whether the program already exists. The deploy then fails if it does."
+        )]
+        skip_show: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Start solana-test-validator for the localnet deploy if none is running, and stop it when done."
+        )]
+        with_localnet: bool,
+        #[clap(long)]
+        #[clap(about = "Fail unless the workspace is a clean checkout of this git tag.")]
+        from_tag: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Skip the steps a failed deploy of this version already completed.")]
+        resume: bool,
+        #[clap(long)]
+        #[clap(about = "Read the deployer keypair as a JSON array from stdin.")]
+        deployer_stdin: bool,
+        #[clap(long)]
+        #[clap(about = "Read and write artifacts here instead of paths.artifacts.")]
+        artifacts_dir: Option<PathBuf>,
+        #[clap(long)]
+        #[clap(about = "Where to read the program address from.")]
+        #[clap(
+            default_value = ProgramIdSource::Keypair.into(),
+            possible_values = ProgramIdSource::VARIANTS
+        )]
+        program_id_source: ProgramIdSource,
+        #[clap(long)]
+        #[clap(about = "Bytes to allocate for the program data, to leave room for upgrades.")]
+        max_len: Option<usize>,
+        #[clap(long)]
+        #[clap(about = "Print the estimated cost of the deploy and exit without deploying.")]
+        simulate_cost: bool,
+        #[clap(long)]
+        #[clap(
+            about = "After deploying, write the program ids and IDL paths for clients here, as TypeScript if it ends in .ts and JSON otherwise."
+        )]
+        emit_client_config: Option<PathBuf>,
+        #[clap(long)]
+        #[clap(
+            about = "Also write the receipt here, or to stdout instead of the usual output if -. Several deploys write an array of receipts."
+        )]
+        output_receipt: Option<PathBuf>,
+        #[clap(long)]
+        #[clap(about = "Priority fee in micro-lamports per compute unit.")]
+        priority_fee: Option<u64>,
+        #[clap(long)]
+        #[clap(about = "Compute units each transaction requests, up to 1400000.")]
+        compute_unit_limit: Option<u32>,
+        #[clap(long)]
+        #[clap(about = "Send transactions through the RPC instead of the TPU.")]
+        use_rpc: bool,
+        #[clap(long)]
+        #[clap(about = "Keypair that pays transaction fees instead of the deployer.")]
+        fee_payer: Option<PathBuf>,
+        #[clap(long)]
+        #[clap(about = "Leave the deployer as the upgrade authority, e.g. on devnet.")]
+        no_set_authority: bool,
+        #[clap(
+            long = "final",
+            conflicts_with = "no-set-authority",
+            requires = "i-understand-this-is-irreversible"
+        )]
+        #[clap(about = "Make the program immutable by removing its upgrade authority.")]
+        finalize: bool,
+        #[clap(long, requires = "finalize")]
+        #[clap(about = "Confirm that --final can't be undone.")]
+        i_understand_this_is_irreversible: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Leave the deployer as the IDL authority, e.g. to keep iterating on the IDL."
+        )]
+        keep_idl_authority: bool,
+        #[clap(long)]
+        #[clap(about = "Check the binary and deployed program against the verifiable build hash.")]
+        verifiable: bool,
+        #[clap(long, requires = "verifiable", possible_values = Commitment::VARIANTS)]
+        #[clap(about = "Commitment to read the deployed program at when verifying it.")]
+        commitment_on_verify: Option<Commitment>,
     },
-    #[clap(about = "Upgrades a program.")]
-    Upgrade {
+    #[clap(about = "Redeploys a program to localnet or devnet whenever it is rebuilt.")]
+    Watch {
         #[clap(short, long)]
-        version: Version,
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(about = "Network to deploy to: localnet or devnet.")]
+        #[clap(default_value = Network::Localnet.into(), possible_values = &["localnet", "devnet"])]
+        network: Network,
+    },
+    #[clap(about = "Prints the signers Captain would use on a network.")]
+    Whoami {
+        #[clap(short, long)]
+        #[clap(about = "Network whose signers to print. Defaults to $CAPTAIN_NETWORK if set.")]
+        #[clap(default_value = default_network())]
+        network: NetworkName,
+    },
+    #[clap(about = "Streams the logs of a deployed program.")]
+    Logs {
         #[clap(short, long)]
         #[clap(about = "Name of the program in target/deploy/<id>.so")]
         program: String,
         #[clap(short, long)]
-        #[clap(about = "Network to deploy to")]
+        #[clap(about = "Version whose address to use. Defaults to the program's current version.")]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Network to stream logs from. Defaults to $CAPTAIN_NETWORK if set.")]
+        #[clap(default_value = default_network())]
+        network: NetworkName,
+        #[clap(long)]
+        #[clap(about = "Where to read the program address from.")]
         #[clap(
-            default_value = Network::Devnet.into(),
-            possible_values = Network::VARIANTS
+            default_value = ProgramIdSource::Keypair.into(),
+            possible_values = ProgramIdSource::VARIANTS
         )]
-        network: Network,
+        program_id_source: ProgramIdSource,
+        #[clap(long)]
+        #[clap(about = "Websocket URL to stream from. Defaults to the network's ws_url.")]
+        ws_url: Option<String>,
+    },
+    #[clap(about = "Upgrades a program.")]
+    Upgrade {
+        #[clap(short, long)]
+        version: Version,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        #[clap(required_unless_present = "program-id", conflicts_with = "program-id")]
+        program: Option<String>,
+        #[clap(long, requires = "bin")]
+        #[clap(about = "Address of a program that isn't built in this workspace. Requires --bin.")]
+        program_id: Option<Pubkey>,
+        #[clap(short, long)]
+        #[clap(about = "Network to deploy to. Defaults to $CAPTAIN_NETWORK if set.")]
+        #[clap(default_value = default_network())]
+        network: NetworkName,
         #[clap(short, long)]
         #[clap(about = "Skip the Anchor IDL upload.")]
         skip_anchor_idl: bool,
+        #[clap(flatten)]
+        artifacts: ArtifactOverrides,
+        #[clap(flatten)]
+        retry: RetryArgs,
+        #[clap(long)]
+        #[clap(
+            about = "Wait for a concurrent deploy of the program to finish instead of failing."
+        )]
+        wait_for_lock: bool,
+        #[clap(long)]
+        #[clap(about = "Skip checking that the network's RPC is healthy before starting.")]
+        no_health_check: bool,
+        #[clap(long)]
+        #[clap(about = "Read the deployer keypair as a JSON array from stdin.")]
+        deployer_stdin: bool,
+        #[clap(long)]
+        #[clap(about = "Read and write artifacts here instead of paths.artifacts.")]
+        artifacts_dir: Option<PathBuf>,
+        #[clap(long)]
+        #[clap(about = "Where to read the program address from.")]
+        #[clap(
+            default_value = ProgramIdSource::Keypair.into(),
+            possible_values = ProgramIdSource::VARIANTS
+        )]
+        program_id_source: ProgramIdSource,
+        #[clap(long)]
+        #[clap(about = "Extend the program data account if the new binary doesn't fit.")]
+        auto_extend: bool,
+        #[clap(long, alias = "from-buffer")]
+        #[clap(
+            about = "Reuse an already-written buffer, e.g. to retry a failed upgrade or finalize a --write-only one."
+        )]
+        buffer: Option<Pubkey>,
+        #[clap(long, conflicts_with_all = &["buffer", "preflight"])]
+        #[clap(
+            about = "Stop after writing the buffer and handing it to the upgrade authority. Finalize with --from-buffer."
+        )]
+        write_only: bool,
+        #[clap(long)]
+        #[clap(about = "Write the buffer and simulate the upgrade without applying it.")]
+        preflight: bool,
+        #[clap(long, requires = "preflight")]
+        #[clap(about = "Apply the upgrade if the preflight simulation succeeds.")]
+        commit: bool,
+        #[clap(long)]
+        #[clap(about = "Priority fee in micro-lamports per compute unit.")]
+        priority_fee: Option<u64>,
+        #[clap(long)]
+        #[clap(about = "Compute units each transaction requests, up to 1400000.")]
+        compute_unit_limit: Option<u32>,
+        #[clap(long)]
+        #[clap(about = "Send transactions through the RPC instead of the TPU.")]
+        use_rpc: bool,
+        #[clap(long)]
+        #[clap(about = "Keypair that pays transaction fees instead of the deployer.")]
+        fee_payer: Option<PathBuf>,
+        #[clap(long)]
+        #[clap(about = "Check the binary and upgraded program against the verifiable build hash.")]
+        verifiable: bool,
+        #[clap(long, requires = "verifiable", possible_values = Commitment::VARIANTS)]
+        #[clap(about = "Commitment to read the upgraded program at when verifying it.")]
+        commitment_on_verify: Option<Commitment>,
     },
 }
 
@@ -84,24 +684,79 @@ pub enum SubCommand {
 #[clap(author = crate_authors!())]
 #[clap(setting = AppSettings::ColoredHelp)]
 pub struct Opts {
+    #[clap(short, long, global = true)]
+    #[clap(about = "Only print results, warnings and errors.")]
+    quiet: bool,
+    #[clap(long, global = true)]
+    #[clap(about = "Format of the result printed to stdout.")]
+    #[clap(default_value = Format::Human.into(), possible_values = Format::VARIANTS)]
+    format: Format,
+    #[clap(long, global = true)]
+    #[clap(about = "Print every external command before running it.")]
+    verbose: bool,
+    #[clap(long, global = true)]
+    #[clap(about = "Mask keypair paths in the commands printed by --verbose.")]
+    redact_secrets: bool,
+    #[clap(long, global = true)]
+    #[clap(about = "Read shared network definitions from this file instead of networks_include.")]
+    network_config_file: Option<PathBuf>,
+    #[clap(long, global = true)]
+    #[clap(about = "Directory containing the program crates, overriding program_dir.")]
+    programs_dir: Option<PathBuf>,
     #[clap(subcommand)]
     command: SubCommand,
 }
 
-fn main_with_result() -> Result<()> {
+/// Exit codes of the CLI, so scripts can branch on the outcome.
+///
+/// These are stable: new outcomes get new codes rather than reusing these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitCode {
+    /// Everything was deployed or upgraded.
+    Success = 0,
+    /// Any error not covered by a more specific code.
+    Failure = 1,
+    /// `deploy` found every program already deployed, so did nothing.
+    AlreadyDeployed = 10,
+    /// `upgrade` found no deployed program to upgrade.
+    ProgramNotFound = 11,
+}
+
+impl ExitCode {
+    fn of_error(err: &anyhow::Error) -> Self {
+        if let Some(CaptainError::ProgramNotFound) = err.downcast_ref::<CaptainError>() {
+            ExitCode::ProgramNotFound
+        } else {
+            ExitCode::Failure
+        }
+    }
+}
+
+fn main_with_result() -> Result<ExitCode> {
     let opts: Opts = Opts::parse();
+    command::echo_commands(opts.verbose, opts.redact_secrets);
+    progress::set_quiet(opts.quiet);
+    let mut progress = Progress::new(opts.quiet);
+    let format = opts.format;
+    let mut exit_code = ExitCode::Success;
+    // the workspace overrides every command applies to its config
+    let workspace_options = LoadOptions {
+        programs_dir: opts.programs_dir.clone(),
+        network_config_file: opts.network_config_file.clone(),
+        ..Default::default()
+    };
 
     match opts.command {
         SubCommand::Init => {
             if std::env::current_dir()?.join("Captain.toml").exists() {
-                println!(
+                eprintln!(
                     "{}",
                     "Captain.toml has already been initialized in this directory.".red()
                 );
                 std::process::exit(1);
             }
             if !std::env::current_dir()?.join("Cargo.toml").exists() {
-                println!(
+                eprintln!(
                     "{}",
                     "Cargo.toml does not exist in the current working directory. Ensure that you are at the Cargo workspace root.".red()
                 );
@@ -119,8 +774,7 @@ fn main_with_result() -> Result<()> {
                 Network::Localnet,
             ] {
                 let deployer_kp = solana_sdk::signer::keypair::Keypair::new();
-                let deployer_path =
-                    deployers_root.join(format!("{}/deployer.json", network.to_string()));
+                let deployer_path = deployers_root.join(format!("{}/deployer.json", network));
                 solana_sdk::signer::keypair::write_keypair_file(&deployer_kp, &deployer_path)
                     .map_err(|_| format_err!("could not generate temp buffer keypair"))?;
 
@@ -128,10 +782,14 @@ fn main_with_result() -> Result<()> {
                 networks.insert(
                     network.clone(),
                     NetworkConfig {
-                        deployer: CaptainPath(deployer_path),
-                        url: network.url().to_string().into(),
-                        ws_url: network.ws_url().to_string().into(),
+                        deployer: Some(CaptainPath(deployer_path)),
+                        url: network.url().into(),
+                        ws_url: network.ws_url().into(),
                         upgrade_authority: "~/.config/solana/id.json".to_string(),
+                        upgrade_authority_keypair: None,
+                        fee_payer: None,
+                        deploy: DeployConfig::default(),
+                        allowed_upgrade_authorities: None,
                     },
                 );
             }
@@ -140,27 +798,478 @@ fn main_with_result() -> Result<()> {
             let mut file = File::create("Captain.toml")?;
             file.write_all(toml.as_bytes())?;
         }
+        SubCommand::Setup => {
+            if !std::env::current_dir()?.join("Cargo.toml").exists() {
+                eprintln!(
+                    "{}",
+                    "Cargo.toml does not exist in the current working directory. Ensure that you are at the Cargo workspace root.".red()
+                );
+                std::process::exit(1);
+            }
+            if !atty::is(atty::Stream::Stdin) {
+                return Err(anyhow!(
+                    "`captain setup` is interactive. Use `captain init` in scripts."
+                ));
+            }
+            setup::run(Path::new("Captain.toml"), &mut std::io::stdin().lock())?;
+        }
         SubCommand::Build => {
-            let (_, _, root) = Config::discover()?;
+            let (config, _, root) = workspace_options.discover()?;
             if root.join("Anchor.toml").exists() {
-                println!("{}", "Anchor found! Running `anchor build -v`.".green());
-                command::exec(Command::new("anchor").arg("build").arg("-v"))?;
+                eprintln!("{}", "Anchor found! Running `anchor build -v`.".green());
+                command::exec(Command::new(config.tools.anchor()).arg("build").arg("-v"))?;
             } else {
-                println!(
+                eprintln!(
                     "{}",
                     "Anchor.toml not found in workspace root. Running `cargo build-bpf`.".yellow()
                 );
                 command::exec(Command::new("cargo").arg("build-bpf"))?;
             }
         }
+        SubCommand::Clean {
+            program,
+            artifacts,
+            dry_run,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let removed = clean::clean(&config, &root, program.as_deref(), artifacts, dry_run)?;
+            print_output(format, &CleanReport { dry_run, removed })?;
+        }
+        SubCommand::Doctor => {
+            if !doctor::run(&workspace_options) {
+                std::process::exit(1);
+            }
+        }
+        SubCommand::Schema => {
+            let schema = schemars::schema_for!(Config);
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+        }
+        SubCommand::MigrateConfig { dry_run } => {
+            let path = Config::find_root()?.join("Captain.toml");
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format_err!("could not read {}: {}", path.display(), e))?;
+            let migration = migrate::migrate(&contents)?;
+            for key in &migration.unknown_keys {
+                eprintln!(
+                    "{}",
+                    format!("WARNING: {} is not a Captain.toml key and is ignored", key).yellow()
+                );
+            }
+            let backup = if dry_run || migration.changes.is_empty() {
+                None
+            } else {
+                let mut backup = path.clone().into_os_string();
+                backup.push(".bak");
+                let backup = PathBuf::from(backup);
+                std::fs::copy(&path, &backup)
+                    .map_err(|e| format_err!("could not back up {}: {}", path.display(), e))?;
+                std::fs::write(&path, &migration.contents)
+                    .map_err(|e| format_err!("could not write {}: {}", path.display(), e))?;
+                Some(backup)
+            };
+            print_output(
+                format,
+                &MigrationReport {
+                    path,
+                    from_version: migration.from_version,
+                    to_version: migration.to_version,
+                    changes: migration.changes,
+                    backup,
+                    config: Some(migration.contents).filter(|_| dry_run),
+                },
+            )?;
+        }
+        SubCommand::DiffIdl {
+            program,
+            version,
+            network,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let network = config.resolve_network(&network)?;
+            let old_path = config.artifact_paths(&network, &version, &program).idl;
+            let new_path =
+                workspace::find_target_output(&root.join("target").join("idl"), &program, "json")?;
+            let changes = idl::diff(&idl::read(&old_path)?, &idl::read(&new_path)?);
+            print_output(format, &IdlDiffReport { version, changes })?;
+        }
+        SubCommand::GenKeypair {
+            program,
+            version,
+            encrypt,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let version = match version {
+                Some(version) => version,
+                None => workspace::resolve_program_version(
+                    &program,
+                    &root,
+                    &config.programs_dir(&root),
+                    VersionSource::Cargo,
+                )?,
+            };
+            let path = config.program_kp_path(&version, &program);
+            if path.exists() {
+                return Err(anyhow!("Program keypair {} already exists", path.display()));
+            }
+            let pubkey = gen_program_keypair(&path, encrypt)?;
+            print_output(
+                format,
+                &KeypairReport {
+                    verb: "Wrote",
+                    pubkey: pubkey.to_string(),
+                    path,
+                    encrypted: encrypt,
+                },
+            )?;
+        }
+        SubCommand::ImportKeypair {
+            program,
+            version,
+            from,
+            force,
+            encrypt,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let version = match version {
+                Some(version) => version,
+                None => workspace::resolve_program_version(
+                    &program,
+                    &root,
+                    &config.programs_dir(&root),
+                    VersionSource::Cargo,
+                )?,
+            };
+            let path = config.program_kp_path(&version, &program);
+            if path.exists() && !force {
+                return Err(anyhow!(
+                    "Program keypair {} already exists. Pass --force to overwrite it.",
+                    path.display()
+                ));
+            }
+            let program_kp = keypair::read_keypair(&from)?;
+            let encrypt = encrypt || keypair::is_encrypted(&from)?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            keypair::write_keypair(&program_kp, &path, encrypt)?;
+            print_output(
+                format,
+                &KeypairReport {
+                    verb: "Imported",
+                    pubkey: program_kp.pubkey().to_string(),
+                    path,
+                    encrypted: encrypt,
+                },
+            )?;
+        }
+        SubCommand::Bump {
+            program,
+            level,
+            encrypt,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let programs_dir = config.programs_dir(&root);
+            let old_version = workspace::get_program_version(&program, &root, &programs_dir)?;
+            let new_version = level.bump(&old_version);
+            let manifest_path =
+                workspace::set_program_version(&program, &root, &programs_dir, &new_version)?;
+
+            let path = config.program_kp_path(&new_version, &program);
+            let new_keypair = if path.exists() {
+                None
+            } else {
+                let pubkey = gen_program_keypair(&path, encrypt)?;
+                Some(KeypairReport {
+                    verb: "Wrote",
+                    pubkey: pubkey.to_string(),
+                    path: path.clone(),
+                    encrypted: encrypt,
+                })
+            };
+            print_output(
+                format,
+                &BumpReport {
+                    program,
+                    old_version,
+                    new_version,
+                    manifest_path,
+                    keypair_path: path,
+                    new_keypair,
+                },
+            )?;
+        }
+        SubCommand::ExportKeypairPubkeys { syntax } => {
+            let (config, _, _) = workspace_options.discover()?;
+            let mut pubkeys = BTreeMap::new();
+            let mut failures = 0;
+            for (name, pubkey) in keypair::read_pubkeys(&config.paths.program_keypairs.0)? {
+                match pubkey {
+                    Ok(pubkey) => {
+                        pubkeys.insert(name, pubkey.to_string());
+                    }
+                    Err(err) => {
+                        failures += 1;
+                        eprintln!("{}", format!("{}: {}", name, err).red());
+                    }
+                }
+            }
+            match syntax {
+                None => print_output(format, &PubkeyList(pubkeys.clone()))?,
+                Some(PubkeyFormat::Env) => {
+                    for (name, pubkey) in &pubkeys {
+                        println!("{}={}", env_var_name(name), pubkey);
+                    }
+                }
+                Some(PubkeyFormat::Ts) => {
+                    println!("export const PROGRAM_IDS = {{");
+                    for (name, pubkey) in &pubkeys {
+                        println!("  {:?}: {:?},", name, pubkey);
+                    }
+                    println!("}} as const;");
+                }
+            }
+            if failures > 0 {
+                return Err(anyhow!("{} program keypairs could not be read", failures));
+            }
+        }
+        SubCommand::VerifySignature {
+            program,
+            version,
+            network,
+            signer,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let network = config.resolve_network(&network)?;
+            let version = match version {
+                Some(version) => version,
+                None => workspace::resolve_program_version(
+                    &program,
+                    &root,
+                    &config.programs_dir(&root),
+                    VersionSource::Cargo,
+                )?,
+            };
+            let expected_signer = match (signer, &config.security.signing_key) {
+                (Some(signer), _) => signer,
+                (None, Some(key)) => keypair::read_keypair(&key.0)?.pubkey(),
+                (None, None) => {
+                    return Err(anyhow!(
+                        "Pass --signer or set security.signing_key in Captain.toml."
+                    ))
+                }
+            };
+            let paths = config.artifact_paths(&network, &version, &program);
+            config.artifact_store()?.get(&paths)?;
+            let signer = signing::verify(&paths.bin, &expected_signer)?;
+            print_output(
+                format,
+                &SignatureReport {
+                    program,
+                    version,
+                    bin: paths.bin,
+                    signer: signer.to_string(),
+                },
+            )?;
+        }
+        SubCommand::Artifacts {
+            command:
+                ArtifactsCommand::Gc {
+                    program,
+                    keep,
+                    dry_run,
+                    network,
+                },
+        } => {
+            let (config, _, _) = workspace_options.discover()?;
+            let network = network
+                .map(|network| config.resolve_network(&network))
+                .transpose()?;
+            let removed = artifacts::gc(&config, &program, keep, network.as_ref(), dry_run)?;
+            let removed = removed
+                .into_iter()
+                .map(|(version, root)| RemovedArtifacts { version, root })
+                .collect();
+            print_output(format, &GcReport { dry_run, removed })?;
+        }
+        SubCommand::Idl {
+            command:
+                IdlCommand::Fetch {
+                    program,
+                    version,
+                    network,
+                    program_id_source,
+                },
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let network = config.resolve_network(&network)?;
+            let version = match version {
+                Some(version) => version,
+                None => workspace::resolve_program_version(
+                    &program,
+                    &root,
+                    &config.programs_dir(&root),
+                    VersionSource::Cargo,
+                )?,
+            };
+            let program_id = workspace::resolve_program_id(
+                &program,
+                &network,
+                &LoadOptions {
+                    version: Some(version.clone()),
+                    program_id_source,
+                    ..workspace_options.clone()
+                },
+            )?;
+            let url = config.network_config(&network)?.network_url(&network);
+            info!("Fetching the IDL of {} from {}", program_id, network);
+            let raw = idl::fetch_raw(&config, &root, &url, &program_id).ok_or_else(|| {
+                anyhow!(
+                    "{} has no IDL on {} that could be fetched",
+                    program_id,
+                    network
+                )
+            })?;
+            let fetched = serde_json::from_str(&raw)
+                .map_err(|e| format_err!("could not parse the fetched IDL: {}", e))?;
+
+            let local_path =
+                workspace::find_target_output(&root.join("target").join("idl"), &program, "json");
+            if let Ok(local_path) = local_path {
+                let changes = idl::diff(&idl::read(&local_path)?, &fetched);
+                if !changes.is_empty() {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "WARNING: the IDL on {} differs from {}:",
+                            network,
+                            local_path.display()
+                        )
+                        .yellow()
+                    );
+                    for change in changes {
+                        eprintln!("    {}", change);
+                    }
+                }
+            }
+
+            let mut fetched_file = tempfile::NamedTempFile::new()?;
+            fetched_file.write_all(raw.as_bytes())?;
+            let artifact_paths = config.artifact_paths(&network, &version, &program);
+            config
+                .artifact_store()?
+                .put_idl(fetched_file.path(), &artifact_paths)?;
+            print_output(
+                format,
+                &IdlFetchReport {
+                    program,
+                    version,
+                    network,
+                    path: artifact_paths.idl,
+                },
+            )?;
+        }
+        SubCommand::Localnet { command } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let url = localnet::localnet_url(&config);
+            match command {
+                LocalnetCommand::Start => {
+                    let validator = localnet::start(&config, &root)?;
+                    print_output(
+                        format,
+                        &LocalnetReport {
+                            running: true,
+                            pid: Some(validator.pid()),
+                            url,
+                        },
+                    )?;
+                }
+                LocalnetCommand::Stop => {
+                    let pid = localnet::stop(&root)?;
+                    print_output(
+                        format,
+                        &LocalnetReport {
+                            running: false,
+                            pid,
+                            url,
+                        },
+                    )?;
+                }
+            }
+        }
         SubCommand::Airdrop { amount: _amount } => {
             // let workspace = &workspace::load(program.as_str(), version, network.clone())?;
             // command::exec(solana_cmd!(workspace).arg("airdrop").arg(amount))?;
             println!("Unimplemented")
         }
-        SubCommand::Programs => {
-            let (config, _, root) = Config::discover()?;
+        SubCommand::ProgramVersion {
+            program,
+            version_from,
+            on_chain,
+            network,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let network = network
+                .map(|network| config.resolve_network(&network))
+                .transpose()?;
+            let version = match network {
+                Some(network) if on_chain => {
+                    artifacts::onchain_version(&config, &root, &program, &network)?.ok_or_else(
+                        || anyhow!("No known version of {} is deployed on {}", program, network),
+                    )?
+                }
+                _ => workspace::resolve_program_version(
+                    &program,
+                    &root,
+                    &config.programs_dir(&root),
+                    version_from,
+                )?,
+            };
+            print_output(format, &ProgramVersionReport { program, version })?;
+        }
+        SubCommand::Status {
+            program,
+            all,
+            network,
+        } => {
+            let (config, _, root) = workspace_options.discover()?;
+            let mut programs = status::programs(&config)?;
+            if !all {
+                let program = program.unwrap_or_default();
+                let program_ids = programs
+                    .remove(&program)
+                    .ok_or_else(|| anyhow!("No program keypair found for {}", program))?;
+                programs = BTreeMap::new();
+                programs.insert(program, program_ids);
+            }
+            let networks: Vec<Network> = match network {
+                Some(network) => vec![config.resolve_network(&network)?],
+                None => config.networks.keys().cloned().collect(),
+            };
+            let mut cells = vec![];
+            for (program, program_ids) in &programs {
+                for network in &networks {
+                    let cell = status::fetch(&config, &root, program, program_ids, network);
+                    if let Some(err) = &cell.error {
+                        eprintln!(
+                            "{}",
+                            format!("{} on {}: {}", program, network, err).yellow()
+                        );
+                    }
+                    cells.push(cell);
+                }
+            }
+            print_output(
+                format,
+                &StatusMatrix {
+                    networks,
+                    programs: cells,
+                },
+            )?;
+        }
+        SubCommand::Programs { version_from } => {
+            let (config, _, root) = workspace_options.discover()?;
             let paths = std::fs::read_dir(root.join("./target/deploy/")).unwrap();
+            let mut programs = vec![];
             for path in paths {
                 let the_path = path?.path();
                 if the_path.extension().and_then(|ex| ex.to_str()) != Some("so") {
@@ -173,233 +1282,1142 @@ fn main_with_result() -> Result<()> {
                     .to_str()
                     .ok_or_else(|| format_err!("no str"))?;
 
-                let program_version = workspace::get_program_version(program, &root).ok();
+                let program_version = workspace::resolve_program_version(
+                    program,
+                    &root,
+                    &config.programs_dir(&root),
+                    version_from,
+                )
+                .ok();
 
                 let program_key = program_version
                     .clone()
                     .and_then(|version| {
-                        solana_sdk::signer::keypair::read_keypair_file(
-                            &config.program_kp_path(&version, program),
-                        )
-                        .ok()
+                        keypair::read_keypair(&config.program_kp_path(&version, program)).ok()
                     })
                     .map(|k| k.pubkey());
 
-                println!("Program: {}", program);
-                println!(
-                    "    Version: {}",
-                    program_version
-                        .map(|v| v.to_string())
-                        .unwrap_or(format!("{}", "Cargo.toml not found".yellow()))
-                );
-                println!(
-                    "    Address: {}",
-                    program_key
-                        .map(|k| k.to_string())
-                        .unwrap_or(format!("{}", "not deployed".yellow()))
-                );
-                println!();
+                programs.push(ProgramReport {
+                    program: program.to_string(),
+                    version: program_version.map(|v| v.to_string()),
+                    address: program_key.map(|k| k.to_string()),
+                });
             }
+            print_output(format, &ProgramList(programs))?;
         }
-        SubCommand::Release { program } => {
-            let workspace = &workspace::load(program.as_str(), None, Network::Localnet)?;
-            if workspace.artifact_paths.exist() {
+        SubCommand::Release {
+            program,
+            version_from,
+            require_fresh,
+            no_build_check,
+            artifacts_dir,
+            idl_build,
+            network,
+        } => {
+            let workspace = &workspace::load(
+                program.as_str(),
+                resolve_network(&network, &workspace_options)?,
+                &LoadOptions {
+                    version_from,
+                    artifacts_dir,
+                    idl_build,
+                    ..workspace_options.clone()
+                },
+            )?;
+            if workspace.artifacts_exist()? {
                 return Err(anyhow!("Program artifacts already exist for this version. Make sure to bump your Cargo.toml."));
             }
 
-            println!(
+            if !no_build_check {
+                if let Some(source) = workspace.find_stale_source()? {
+                    let message = format!(
+                        "{} is older than {}. Run `captain build` to rebuild the program.",
+                        workspace.program_paths.bin.display(),
+                        source.display()
+                    );
+                    if require_fresh {
+                        return Err(anyhow!(message));
+                    }
+                    eprintln!("{}", format!("WARNING: {}", message).yellow());
+                }
+            }
+
+            info!(
                 "Releasing program {} with version {}",
                 program, workspace.deploy_version
             );
 
-            output_header("Copying artifacts");
-            workspace.copy_artifacts()?;
+            progress.step("Copying artifacts", || workspace.copy_artifacts())?;
+            progress.summary();
 
-            println!("Release success!");
+            info!("Release success!");
         }
         SubCommand::Deploy {
             version,
             program,
-            ref network,
+            network: ref networks,
+            keep_going,
+            jobs,
+            wait_finalized,
+            finalized_poll_interval,
+            finalized_timeout,
             skip_anchor_idl,
+            artifacts,
+            retry,
+            wait_for_lock,
+            no_health_check,
+            skip_show,
+            with_localnet,
+            from_tag,
+            resume,
+            deployer_stdin,
+            artifacts_dir,
+            program_id_source,
+            max_len,
+            simulate_cost,
+            emit_client_config,
+            output_receipt,
+            priority_fee,
+            compute_unit_limit,
+            use_rpc,
+            fee_payer,
+            no_set_authority,
+            finalize,
+            i_understand_this_is_irreversible: _,
+            keep_idl_authority,
+            verifiable,
+            commitment_on_verify,
         } => {
-            let workspace = &workspace::load(program.as_str(), version.into(), network.clone())?;
-            println!(
-                "Deploying program {} with version {}",
-                program, workspace.deploy_version
-            );
-
-            println!("Address: {}", workspace.program_key);
-
-            if workspace.show_program()? {
-                println!("Program already deployed. Use `captain upgrade` if you want to upgrade the program.");
-                std::process::exit(0);
+            let networks = &networks
+                .iter()
+                .map(|network| resolve_network(network, &workspace_options))
+                .collect::<Result<Vec<_>>>()?;
+            let programs =
+                expand_programs(&program, artifacts.overrides_program(), &workspace_options)?;
+            let load_options = LoadOptions {
+                version: version.into(),
+                bin: artifacts.bin,
+                idl: artifacts.idl,
+                idl_build: artifacts.idl_build,
+                expect_sha256: artifacts.expect_sha256,
+                expect_program_id: artifacts.expect_program_id,
+                program_id_source,
+                program_keypair: artifacts.program_keypair,
+                artifacts_dir,
+                deployer_stdin,
+                ..workspace_options.clone()
+            };
+            let options = DeployOptions {
+                skip_anchor_idl,
+                max_len,
+                priority_fee,
+                compute_unit_limit,
+                use_rpc,
+                fee_payer,
+                no_set_authority,
+                finalize,
+                keep_idl_authority,
+                verifiable,
+                verify_commitment: commitment_on_verify,
+                retry: retry.into(),
+                wait_for_lock,
+                check_health: !no_health_check,
+                skip_show,
+                from_tag,
+                resume,
+                wait_finalized: Some(FinalizedWait {
+                    poll_interval: Duration::from_secs(finalized_poll_interval),
+                    timeout: Duration::from_secs(finalized_timeout),
+                })
+                .filter(|_| wait_finalized),
+            };
+            if simulate_cost {
+                for network in networks {
+                    for program in &programs {
+                        let workspace = workspace::load(program, network.clone(), &load_options)?;
+                        let cost = workspace.estimate_deploy_cost(max_len)?;
+                        print_output(format, &cost)?;
+                    }
+                }
+                return Ok(ExitCode::Success);
             }
+            if emit_client_config.is_some() && networks.len() > 1 {
+                return Err(anyhow!(
+                    "--emit-client-config writes the programs of one network. Deploy to each network separately."
+                ));
+            }
+            let quiet = opts.quiet;
+            let (config, _, root) = workspace_options.discover()?;
+            // stopped when dropped at the end of the deploy
+            let _validator = if with_localnet {
+                if !networks.contains(&Network::Localnet) {
+                    return Err(anyhow!(
+                        "--with-localnet only applies to deploys to localnet"
+                    ));
+                }
+                if localnet::is_running(&localnet::localnet_url(&config)) {
+                    None
+                } else {
+                    info!("Starting solana-test-validator");
+                    Some(localnet::start(&config, &root)?.stop_on_drop())
+                }
+            } else {
+                None
+            };
+            let mut client_config = ClientConfig::new(networks[0].clone());
+            let depends_on: BTreeMap<String, Vec<String>> = programs
+                .iter()
+                .map(|program| (program.clone(), config.program_config(program).depends_on))
+                .collect();
+            let programs = schedule::order(&programs, &depends_on)?;
+            let receipt_to_stdout = output_receipt.as_deref() == Some(Path::new("-"));
+            let mut receipts = vec![];
+            let mut results = vec![];
+            for network in networks {
+                let task_network = network.clone();
+                let task_load_options = load_options.clone();
+                let task_options = options.clone();
+                let mut failed = false;
+                let timings = schedule::run(
+                    &programs,
+                    &depends_on,
+                    jobs,
+                    move |program| {
+                        workspace::deploy_with_companions(
+                            program,
+                            &task_network,
+                            &task_load_options,
+                            &task_options,
+                            quiet,
+                        )
+                    },
+                    |program, result| match report_deployment(result) {
+                        Ok(receipt) => {
+                            if !receipt_to_stdout {
+                                print_output(format, &receipt)?;
+                            }
+                            if let Some(path) = &emit_client_config {
+                                for receipt in iter::once(&receipt).chain(receipt.companions.iter())
+                                {
+                                    let idl = match &load_options.artifacts_dir {
+                                        Some(artifacts_dir) => ArtifactPaths::new(
+                                            artifacts_dir,
+                                            config.paths.artifact_layout(),
+                                            network,
+                                            &receipt.version,
+                                            &receipt.program,
+                                        ),
+                                        None => config.artifact_paths(
+                                            network,
+                                            &receipt.version,
+                                            &receipt.program,
+                                        ),
+                                    }
+                                    .idl;
+                                    client_config.add(
+                                        &receipt.program,
+                                        &receipt.program_id,
+                                        Some(&idl)
+                                            .filter(|idl| idl.exists())
+                                            .map(|idl| idl.as_path()),
+                                        path,
+                                    );
+                                }
+                            }
+                            // a program counts as deployed if any of its
+                            // companions was
+                            let action = receipt
+                                .companions
+                                .iter()
+                                .map(|companion| companion.action)
+                                .find(|action| *action != Action::AlreadyDeployed)
+                                .unwrap_or(receipt.action);
+                            results.push((network.clone(), program.to_string(), Ok(action)));
+                            receipts.push(receipt);
+                            Ok(true)
+                        }
+                        Err(err) => {
+                            results.push((network.clone(), program.to_string(), Err(err)));
+                            failed = true;
+                            Ok(keep_going)
+                        }
+                    },
+                )?;
+                if jobs > 1 && programs.len() > 1 {
+                    print_timeline(network, &timings);
+                }
+                if failed && !keep_going {
+                    break;
+                }
+            }
+            if let Some(path) = &output_receipt {
+                let json = match receipts.as_slice() {
+                    [receipt] => output::to_json(receipt)?,
+                    receipts => output::to_json(receipts)?,
+                };
+                if receipt_to_stdout {
+                    println!("{}", json);
+                } else {
+                    fs::write(path, json)
+                        .map_err(|e| format_err!("could not write {}: {}", path.display(), e))?;
+                }
+            }
+            if networks.len() == 1 && !keep_going {
+                if let Some(i) = results.iter().position(|(_, _, r)| r.is_err()) {
+                    if let (_, _, Err(err)) = results.swap_remove(i) {
+                        return Err(err);
+                    }
+                }
+            }
+            if networks.len() > 1 {
+                print_network_summary(&results);
+            }
+            let failures = results.iter().filter(|(_, _, r)| r.is_err()).count();
+            if failures > 0 {
+                return Err(anyhow!("{} deployments failed", failures));
+            }
+            if let Some(path) = &emit_client_config {
+                client_config.write(path)?;
+                info!("Wrote client config to {}", path.display());
+            }
+            if results
+                .iter()
+                .all(|(_, _, r)| matches!(r, Ok(Action::AlreadyDeployed)))
+            {
+                exit_code = ExitCode::AlreadyDeployed;
+            }
+        }
+        SubCommand::Whoami { network } => {
+            let (config, _, _) = workspace_options.discover()?;
+            let network = config.resolve_network(&network)?;
+            let network_config = config.network_config(&network)?;
+            let solana = config.tools.solana();
 
-            output_header("Deploying program");
-
-            command::exec(
-                solana_cmd!(workspace)
-                    .arg("program")
-                    .arg("deploy")
-                    .arg(&workspace.artifact_paths.bin)
-                    .arg("--program-id")
-                    .arg(&workspace.program_paths.id),
-            )?;
-
-            output_header("Setting upgrade authority");
+            let deployer = network_config
+                .deployer_path(&network)
+                .map(|deployer| SignerSource::from_path(&deployer));
+            let mut signers = vec![match deployer {
+                Ok(source) => SignerReport::resolve("deployer", &source, &solana),
+                Err(err) => SignerReport {
+                    role: "deployer",
+                    source: "solana CLI default".to_string(),
+                    pubkey: None,
+                    error: Some(err.to_string()),
+                },
+            }];
+            signers.push(match Pubkey::from_str(&network_config.upgrade_authority) {
+                Ok(pubkey) => SignerReport {
+                    role: "upgrade authority",
+                    source: "address".to_string(),
+                    pubkey: Some(pubkey.to_string()),
+                    error: None,
+                },
+                Err(_) => SignerReport::resolve(
+                    "upgrade authority",
+                    &SignerSource::parse(&network_config.upgrade_authority),
+                    &solana,
+                ),
+            });
+            let upgrade_signer = match env::var("UPGRADE_AUTHORITY_KEYPAIR") {
+                Ok(signer) => Some(SignerSource::parse(&signer)),
+                Err(_) => network_config
+                    .upgrade_authority_keypair
+                    .as_ref()
+                    .map(|keypair| SignerSource::from_path(&keypair.as_path_buf())),
+            };
+            if let Some(signer) = upgrade_signer {
+                signers.push(SignerReport::resolve("upgrade signer", &signer, &solana));
+            }
+            if let Some(fee_payer) = &network_config.fee_payer {
+                signers.push(SignerReport::resolve(
+                    "fee payer",
+                    &SignerSource::from_path(&fee_payer.as_path_buf()),
+                    &solana,
+                ));
+            }
 
-            command::exec(
-                solana_cmd!(workspace)
-                    .arg("program")
-                    .arg("set-upgrade-authority")
-                    .arg(&workspace.program_paths.id)
-                    .arg("--new-upgrade-authority")
-                    .arg(&workspace.network_config.upgrade_authority),
+            print_output(
+                format,
+                &WhoamiReport {
+                    network: &network,
+                    signers: &signers,
+                },
             )?;
 
-            workspace.show_program()?;
-
-            if workspace.has_anchor() {
-                if skip_anchor_idl {
-                    output_header("Skipping Anchor IDL upload.");
-                } else {
-                    output_header("Initializing IDL");
-                    command::exec(
-                        anchor_cmd!(workspace, "idl")
-                            .arg("init")
-                            .arg(&workspace.program_key.to_string())
-                            .arg("--filepath")
-                            .arg(&workspace.program_paths.idl),
-                    )?;
-
-                    output_header("Setting IDL authority");
-                    command::exec(
-                        anchor_cmd!(workspace, "idl")
-                            .arg("set-authority")
-                            .arg("--program-id")
-                            .arg(workspace.program_key.to_string())
-                            .arg("--new-authority")
-                            .arg(&workspace.network_config.upgrade_authority),
-                    )?;
+            let authority = signers
+                .iter()
+                .find(|signer| signer.role == "upgrade authority")
+                .and_then(|signer| signer.pubkey.as_ref());
+            let upgrade_signer = signers
+                .iter()
+                .find(|signer| signer.role == "upgrade signer")
+                .and_then(|signer| signer.pubkey.as_ref());
+            if let (Some(authority), Some(upgrade_signer)) = (authority, upgrade_signer) {
+                if authority != upgrade_signer {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "WARNING: the upgrade signer is {}, not the upgrade authority {}",
+                            upgrade_signer, authority
+                        )
+                        .yellow()
+                    );
                 }
             }
-
-            println!("Deployment success!");
+            if signers.iter().any(|signer| signer.error.is_some()) {
+                return Err(anyhow!("Some signers could not be resolved."));
+            }
+        }
+        SubCommand::Logs {
+            program,
+            version,
+            network,
+            program_id_source,
+            ws_url,
+        } => {
+            let (config, _, _) = workspace_options.discover()?;
+            let network = config.resolve_network(&network)?;
+            let program_id = workspace::resolve_program_id(
+                &program,
+                &network,
+                &LoadOptions {
+                    version,
+                    program_id_source,
+                    ..workspace_options.clone()
+                },
+            )?;
+            let network_config = config.network_config(&network)?;
+            let url = network_config.network_url(&network);
+            let ws_url = ws_url.unwrap_or_else(|| network_config.network_ws_url(&network));
+            eprintln!("Streaming logs of {} on {}", program_id, network);
+            stream_logs(
+                Command::new(config.tools.solana())
+                    .arg("logs")
+                    .arg(program_id.to_string())
+                    .arg("--url")
+                    .arg(url)
+                    .arg("--ws")
+                    .arg(ws_url),
+            )?;
+        }
+        SubCommand::Watch { program, network } => {
+            watch::run(&program, network, &workspace_options, opts.quiet, format)?;
         }
         SubCommand::Upgrade {
             version,
             program,
+            program_id,
             ref network,
             skip_anchor_idl,
+            artifacts,
+            retry,
+            no_health_check,
+            wait_for_lock,
+            deployer_stdin,
+            artifacts_dir,
+            program_id_source,
+            auto_extend,
+            buffer,
+            write_only,
+            preflight,
+            commit,
+            priority_fee,
+            compute_unit_limit,
+            use_rpc,
+            fee_payer,
+            verifiable,
+            commitment_on_verify,
         } => {
-            let upgrade_authority_keypair =
-                env::var("UPGRADE_AUTHORITY_KEYPAIR").map_err(|_| {
-                    format_err!("Must set UPGRADE_AUTHORITY_KEYPAIR environment variable.")
-                })?;
-
-            let workspace = workspace::load(program.as_str(), version.into(), network.clone())?;
-            println!(
-                "Upgrading program {} with version {}",
-                program, workspace.deploy_version
-            );
+            let network = &resolve_network(network, &workspace_options)?;
+            // a program loaded by address is named after it in artifacts and
+            // receipts
+            let programs = match (program, program_id) {
+                (_, Some(program_id)) => vec![program_id.to_string()],
+                (Some(program), None) => expand_programs(
+                    &program,
+                    artifacts.overrides_program() || buffer.is_some(),
+                    &workspace_options,
+                )?,
+                (None, None) => unreachable!("clap requires --program or --program-id"),
+            };
+            let load_options = LoadOptions {
+                version: version.into(),
+                program_id,
+                bin: artifacts.bin,
+                idl: artifacts.idl,
+                idl_build: artifacts.idl_build,
+                expect_sha256: artifacts.expect_sha256,
+                expect_program_id: artifacts.expect_program_id,
+                program_id_source,
+                program_keypair: artifacts.program_keypair,
+                artifacts_dir,
+                deployer_stdin,
+                ..workspace_options.clone()
+            };
+            let options = UpgradeOptions {
+                skip_anchor_idl,
+                auto_extend,
+                buffer,
+                write_only,
+                preflight,
+                commit,
+                priority_fee,
+                compute_unit_limit,
+                use_rpc,
+                fee_payer,
+                verifiable,
+                verify_commitment: commitment_on_verify,
+                upgrade_authority_keypair: None,
+                retry: retry.into(),
+                wait_for_lock,
+                check_health: !no_health_check,
+            };
+            for program in programs {
+                let workspace = workspace::load(&program, network.clone(), &load_options)?;
+                let receipt =
+                    report_deployment(workspace.upgrade(&mut Progress::new(opts.quiet), &options))?;
+                print_output(format, &receipt)?;
+            }
+        }
+    }
 
-            if !workspace.show_program()? {
-                println!("Program does not exist. Use `captain deploy` if you want to deploy the program for the first time.");
-                std::process::exit(1);
+    Ok(exit_code)
+}
+
+/// Expands a `--program` pattern such as `token-*` into the programs it
+/// matches. Plain program names are returned as is.
+///
+/// `single` is set when other flags only make sense for one program.
+fn expand_programs(program: &str, single: bool, options: &LoadOptions) -> Result<Vec<String>> {
+    if !workspace::is_program_pattern(program) {
+        return Ok(vec![program.to_string()]);
+    }
+    let (config, _, root) = options.discover()?;
+    let programs = workspace::select_programs(&config.programs_dir(&root), program)?;
+    if single && programs.len() > 1 {
+        return Err(anyhow!(
+            "{} matches {} programs ({}), but the given flags only apply to one program",
+            program,
+            programs.len(),
+            programs.join(", ")
+        ));
+    }
+    Ok(programs)
+}
+
+/// Runs `solana logs` in place of Captain, so Ctrl-C stops it cleanly and
+/// its output goes straight to stdout.
+#[cfg(unix)]
+fn stream_logs(cmd: &mut Command) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+    command::echo(cmd);
+    let err = cmd.exec();
+    Err(anyhow!("could not run {:?}: {}", cmd, err))
+}
+
+#[cfg(not(unix))]
+fn stream_logs(cmd: &mut Command) -> Result<()> {
+    command::echo(cmd);
+    let status = cmd
+        .status()
+        .map_err(|e| format_err!("could not run {:?}: {}", cmd, e))?;
+    if !status.success() {
+        return Err(CaptainError::CommandFailed {
+            command: format!("{:?}", cmd),
+            code: status.code().unwrap_or(1),
+            stderr: String::new(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Generates a program keypair at `path`, creating its directory if needed.
+fn gen_program_keypair(path: &Path, encrypt: bool) -> Result<Pubkey> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let program_kp = solana_sdk::signer::keypair::Keypair::new();
+    keypair::write_keypair(&program_kp, path, encrypt)?;
+    Ok(program_kp.pubkey())
+}
+
+/// Converts a keypair name such as `my_program-1` to `MY_PROGRAM_1`.
+fn env_var_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
             }
+        })
+        .collect()
+}
 
-            output_header("Writing buffer");
+/// Prints the result of a command to stdout in the `--format` chosen.
+fn print_output<T: Output + ?Sized>(format: Format, result: &T) -> Result<()> {
+    println!("{}", format.render(result)?);
+    Ok(())
+}
 
-            let buffer_kp = solana_sdk::signer::keypair::Keypair::new();
-            let buffer_key = buffer_kp.pubkey();
-            println!("Buffer Pubkey: {}", buffer_key);
+/// The output of `migrate-config`.
+#[derive(Debug, Serialize)]
+struct MigrationReport {
+    path: PathBuf,
+    from_version: u32,
+    to_version: u32,
+    changes: Vec<String>,
+    /// Where the old config was copied to, if it was rewritten.
+    backup: Option<PathBuf>,
+    /// The migrated config, on a dry run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<String>,
+}
 
-            let mut buffer_file = NamedTempFile::new()?;
-            solana_sdk::signer::keypair::write_keypair(&buffer_kp, &mut buffer_file)
-                .map_err(|_| format_err!("could not generate temp buffer keypair"))?;
+impl Output for MigrationReport {
+    fn to_human(&self) -> String {
+        if self.changes.is_empty() {
+            return format!(
+                "{} is already at config version {}.",
+                self.path.display(),
+                self.to_version
+            );
+        }
+        let mut lines = vec![format!(
+            "{} {} from config version {} to {}:",
+            if self.config.is_some() {
+                "Would migrate"
+            } else {
+                "Migrated"
+            },
+            self.path.display(),
+            self.from_version,
+            self.to_version
+        )];
+        lines.extend(self.changes.iter().map(|change| format!("    {}", change)));
+        if let Some(backup) = &self.backup {
+            lines.push(format!("The old config is in {}", backup.display()));
+        }
+        if let Some(config) = &self.config {
+            lines.push(String::new());
+            lines.push(config.trim_end().to_string());
+        }
+        lines.join("\n")
+    }
 
-            command::exec(
-                solana_cmd!(workspace)
-                    .arg("program")
-                    .arg("write-buffer")
-                    .arg(&workspace.artifact_paths.bin)
-                    .arg("--output")
-                    .arg("json")
-                    .arg("--buffer")
-                    .arg(&buffer_file.path()),
-            )?;
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
 
-            output_header("Setting buffer authority");
+/// The output of `diff-idl`.
+#[derive(Debug, Serialize)]
+struct IdlDiffReport {
+    version: Version,
+    changes: Vec<idl::IdlChange>,
+}
 
-            command::exec(
-                solana_cmd!(workspace)
-                    .arg("program")
-                    .arg("set-buffer-authority")
-                    .arg(buffer_key.to_string())
-                    .arg("--new-buffer-authority")
-                    .arg(&workspace.network_config.upgrade_authority),
-            )?;
+impl Output for IdlDiffReport {
+    fn to_human(&self) -> String {
+        if self.changes.is_empty() {
+            return format!("No IDL changes since version {}.", self.version);
+        }
+        let mut lines = vec![format!("IDL changes since version {}:", self.version)];
+        lines.extend(self.changes.iter().map(|change| format!("    {}", change)));
+        lines.join("\n")
+    }
 
-            output_header("Switching to new buffer (please connect your wallet)");
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
 
-            command::exec(
-                Command::new("solana")
-                    .arg("--url")
-                    .arg(&workspace.network_url())
-                    .arg("--keypair")
-                    .arg(&upgrade_authority_keypair)
-                    .arg("program")
-                    .arg("deploy")
-                    .arg("--buffer")
-                    .arg(buffer_key.to_string())
-                    .arg("--program-id")
-                    .arg(workspace.program_key.to_string()),
-            )?;
+/// A program keypair written by `gen-keypair`, `import-keypair` or `bump`.
+#[derive(Debug, Serialize)]
+struct KeypairReport {
+    #[serde(skip)]
+    verb: &'static str,
+    pubkey: String,
+    path: PathBuf,
+    encrypted: bool,
+}
+
+impl Output for KeypairReport {
+    fn to_human(&self) -> String {
+        format!(
+            "{} {}keypair for {} to {}",
+            self.verb,
+            if self.encrypted { "encrypted " } else { "" },
+            self.pubkey,
+            self.path.display()
+        )
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `bump`.
+#[derive(Debug, Serialize)]
+struct BumpReport {
+    program: String,
+    old_version: Version,
+    new_version: Version,
+    manifest_path: PathBuf,
+    keypair_path: PathBuf,
+    /// The keypair generated for the new version, if it needed one.
+    new_keypair: Option<KeypairReport>,
+}
+
+impl Output for BumpReport {
+    fn to_human(&self) -> String {
+        let keypair = match &self.new_keypair {
+            Some(keypair) => keypair.to_human(),
+            None => format!(
+                "{} keeps keypair {}",
+                self.new_version,
+                self.keypair_path.display()
+            ),
+        };
+        format!(
+            "Bumped {} from {} to {} in {}\n{}",
+            self.program,
+            self.old_version,
+            self.new_version,
+            self.manifest_path.display(),
+            keypair
+        )
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `export-keypair-pubkeys`: addresses by keypair name.
+#[derive(Debug, Serialize)]
+struct PubkeyList(BTreeMap<String, String>);
+
+impl Output for PubkeyList {
+    fn to_human(&self) -> String {
+        self.0
+            .iter()
+            .map(|(name, pubkey)| format!("{} {}", name, pubkey))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 
-            workspace.show_program()?;
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The artifacts of a version deleted by `artifacts gc`.
+#[derive(Debug, Serialize)]
+struct RemovedArtifacts {
+    version: Version,
+    root: PathBuf,
+}
 
-            if workspace.has_anchor() {
-                if skip_anchor_idl {
-                    output_header("Skipping Anchor IDL upload.");
+/// The output of `clean`.
+#[derive(Debug, Serialize)]
+struct CleanReport {
+    dry_run: bool,
+    removed: Vec<Removed>,
+}
+
+impl Output for CleanReport {
+    fn to_human(&self) -> String {
+        if self.removed.is_empty() {
+            return "Nothing to remove.".to_string();
+        }
+        self.removed
+            .iter()
+            .map(|removed| {
+                let verb = if self.dry_run {
+                    "Would remove"
                 } else {
-                    output_header("Uploading new IDL");
-                    command::exec(
-                        anchor_cmd!(workspace, "idl")
-                            .arg("write-buffer")
-                            .arg(workspace.program_key.to_string())
-                            .arg("--filepath")
-                            .arg(&workspace.program_paths.idl),
-                    )?;
+                    "Removed"
+                };
+                match &removed.buffer {
+                    Some(buffer) => format!(
+                        "{} {} (keypair of buffer {})",
+                        verb,
+                        removed.path.display(),
+                        buffer
+                    ),
+                    None => format!("{} {}", verb, removed.path.display()),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 
-                    println!(
-                        "WARNING: please manually run `anchor idl set-buffer {} --buffer <BUFFER>`",
-                        workspace.program_key.to_string()
-                    );
-                    println!("TODO: need to be able to hook into anchor for this");
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `artifacts gc`.
+#[derive(Debug, Serialize)]
+struct GcReport {
+    dry_run: bool,
+    removed: Vec<RemovedArtifacts>,
+}
+
+impl Output for GcReport {
+    fn to_human(&self) -> String {
+        if self.removed.is_empty() {
+            return "Nothing to delete.".to_string();
+        }
+        self.removed
+            .iter()
+            .map(|removed| {
+                format!(
+                    "{} {}",
+                    if self.dry_run {
+                        "Would delete"
+                    } else {
+                        "Deleted"
+                    },
+                    removed.root.display()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `idl fetch`.
+#[derive(Debug, Serialize)]
+struct IdlFetchReport {
+    program: String,
+    version: Version,
+    network: Network,
+    path: PathBuf,
+}
+
+impl Output for IdlFetchReport {
+    fn to_human(&self) -> String {
+        format!(
+            "Archived the IDL of {} {} from {} to {}",
+            self.program,
+            self.version,
+            self.network,
+            self.path.display()
+        )
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `verify-signature`.
+#[derive(Debug, Serialize)]
+struct SignatureReport {
+    program: String,
+    version: Version,
+    bin: PathBuf,
+    signer: String,
+}
+
+impl Output for SignatureReport {
+    fn to_human(&self) -> String {
+        format!(
+            "{} {} ({}) is signed by {}",
+            self.program,
+            self.version,
+            self.bin.display(),
+            self.signer
+        )
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `localnet start` and `localnet stop`.
+#[derive(Debug, Serialize)]
+struct LocalnetReport {
+    running: bool,
+    /// The validator's pid, or `None` if `stop` found none to stop.
+    pid: Option<u32>,
+    url: String,
+}
+
+impl Output for LocalnetReport {
+    fn to_human(&self) -> String {
+        match (self.running, self.pid) {
+            (true, Some(pid)) => format!("Validator {} running at {}", pid, self.url),
+            (false, Some(pid)) => format!("Stopped validator {}", pid),
+            _ => "No validator started by Captain is running".to_string(),
+        }
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `program-version`.
+#[derive(Debug, Serialize)]
+struct ProgramVersionReport {
+    program: String,
+    version: Version,
+}
+
+impl Output for ProgramVersionReport {
+    fn to_human(&self) -> String {
+        self.version.to_string()
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// A program as listed by `programs`.
+#[derive(Debug, Serialize)]
+struct ProgramReport {
+    program: String,
+    version: Option<String>,
+    address: Option<String>,
+}
+
+/// The output of `status`: every selected program on every network.
+#[derive(Debug, Serialize)]
+struct StatusMatrix {
+    networks: Vec<Network>,
+    programs: Vec<ProgramStatus>,
+}
+
+impl Output for StatusMatrix {
+    fn to_human(&self) -> String {
+        let mut lines = vec![];
+        let mut program = None;
+        for cell in &self.programs {
+            if program != Some(&cell.program) {
+                if program.is_some() {
+                    lines.push(String::new());
                 }
+                program = Some(&cell.program);
+                lines.push(format!("Program: {}", cell.program));
             }
+            let state = match (&cell.error, cell.deployed) {
+                (Some(_), _) => format!("{}", "unknown".red()),
+                (None, false) => format!("{}", "not deployed".yellow()),
+                (None, true) => {
+                    let version = cell
+                        .version
+                        .as_ref()
+                        .map(|v| v.to_string())
+                        .unwrap_or_else(|| "unknown version".to_string());
+                    let authority = match (&cell.upgrade_authority, cell.authority_matches) {
+                        (None, _) => "immutable".to_string(),
+                        (Some(_), Some(true)) => "authority ok".to_string(),
+                        (Some(authority), Some(false)) => {
+                            format!("{}", format!("authority {}", authority).yellow())
+                        }
+                        (Some(authority), None) => format!("authority {}", authority),
+                    };
+                    format!("{}, {}", version, authority)
+                }
+            };
+            lines.push(format!(
+                "    {:<20} {}",
+                format!("{}:", cell.network),
+                state
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// The output of `programs`.
+#[derive(Debug, Serialize)]
+struct ProgramList(Vec<ProgramReport>);
 
-            println!("Deployment success!");
+impl Output for ProgramList {
+    fn to_human(&self) -> String {
+        let mut lines = vec![];
+        for program in &self.0 {
+            lines.push(format!("Program: {}", program.program));
+            lines.push(format!(
+                "    Version: {}",
+                program
+                    .version
+                    .clone()
+                    .unwrap_or(format!("{}", "version not found".yellow()))
+            ));
+            lines.push(format!(
+                "    Address: {}",
+                program
+                    .address
+                    .clone()
+                    .unwrap_or(format!("{}", "not deployed".yellow()))
+            ));
+            lines.push(String::new());
         }
+        lines.join("\n")
     }
 
-    Ok(())
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
 }
 
-fn output_header(header: &'static str) {
-    println!();
-    println!("{}", "===================================".bold());
-    println!();
-    println!("    {}", header.bold());
-    println!();
-    println!("{}", "===================================".bold());
-    println!();
+/// The output of `whoami`.
+#[derive(Debug, Serialize)]
+struct WhoamiReport<'a> {
+    network: &'a Network,
+    signers: &'a [SignerReport],
 }
 
-fn main() {
-    if let Err(err) = main_with_result() {
-        println!("Error: {}", err);
-        std::process::exit(1);
+impl Output for WhoamiReport<'_> {
+    fn to_human(&self) -> String {
+        let mut lines = vec![format!("Network: {}", self.network)];
+        for signer in self.signers {
+            lines.push(match (&signer.pubkey, &signer.error) {
+                (Some(pubkey), _) => format!("{:<18} {} ({})", signer.role, pubkey, signer.source),
+                (None, error) => format!(
+                    "{:<18} {}",
+                    signer.role,
+                    format!(
+                        "{} ({})",
+                        error.as_deref().unwrap_or("unknown"),
+                        signer.source
+                    )
+                    .red()
+                ),
+            });
+        }
+        lines.join("\n")
+    }
+
+    fn to_json(&self) -> Result<String> {
+        output::to_json(self)
     }
 }
+
+/// A signer as printed by `whoami`.
+#[derive(Debug, Serialize)]
+struct SignerReport {
+    role: &'static str,
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl SignerReport {
+    fn resolve(role: &'static str, source: &SignerSource, solana: &Path) -> Self {
+        let pubkey = source.pubkey(solana);
+        SignerReport {
+            role,
+            source: source.to_string(),
+            pubkey: pubkey.as_ref().ok().map(|pubkey| pubkey.to_string()),
+            error: pubkey.err().map(|err| err.to_string()),
+        }
+    }
+}
+
+/// Prints the outcome of a deploy to each network.
+fn print_network_summary(results: &[(Network, String, Result<Action>)]) {
+    eprintln!();
+    eprintln!("{}", "Summary".bold());
+    for (network, program, result) in results {
+        let outcome = match result {
+            Ok(Action::AlreadyDeployed) => "already deployed".to_string(),
+            Ok(action) => format!("{:?}", action).to_lowercase().green().to_string(),
+            Err(err) => format!("failed: {}", err).red().to_string(),
+        };
+        eprintln!(
+            "    {:<10} {:<30} {}",
+            network.to_string(),
+            program,
+            outcome
+        );
+    }
+}
+
+/// Prints when each program of a concurrent deploy ran, relative to the
+/// start of the deploy.
+fn print_timeline(network: &Network, timings: &[Timing]) {
+    if progress::is_quiet() {
+        return;
+    }
+    eprintln!();
+    eprintln!("{}", format!("Timeline on {}", network).bold());
+    for timing in timings {
+        let outcome = if timing.succeeded {
+            "done".green()
+        } else {
+            "failed".red()
+        };
+        eprintln!(
+            "    {:<30} {:>10} -> {:>10} ({}) {}",
+            timing.program,
+            format_duration(timing.started),
+            format_duration(timing.finished),
+            format_duration(timing.finished - timing.started),
+            outcome
+        );
+    }
+}
+
+/// Prints a red failure line if a deploy or upgrade failed.
+fn report_deployment<T>(result: Result<T>) -> Result<T> {
+    if result.is_err() {
+        eprintln!("{}", "Deployment failed".red());
+    }
+    result
+}
+
+/// Resolves a network given on the command line, with the aliases of the
+/// workspace's config if there is one.
+fn resolve_network(name: &NetworkName, options: &LoadOptions) -> Result<Network> {
+    let config = match options.discover() {
+        Ok((config, _, _)) => config,
+        Err(_) => Config::default(),
+    };
+    config.resolve_network(name)
+}
+
+/// Disables colors if `NO_COLOR` is set or output isn't a terminal, e.g. in
+/// CI logs.
+fn configure_colors() {
+    if env::var_os("NO_COLOR").is_some()
+        || !atty::is(atty::Stream::Stdout)
+        || !atty::is(atty::Stream::Stderr)
+    {
+        colored::control::set_override(false);
+    }
+}
+
+fn main() {
+    configure_colors();
+    interrupt::install();
+    let result = main_with_result();
+    interrupt::wait_for_cleanup();
+    let exit_code = match result {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::of_error(&err)
+        }
+    };
+    std::process::exit(exit_code as i32);
+}
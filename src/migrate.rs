@@ -0,0 +1,371 @@
+//! Upgrades of Captain.toml to the current config schema.
+//!
+//! Migrations work on the raw TOML rather than a parsed [Config], so paths
+//! keep their `~` and environment variables stay unexpanded.
+use crate::config::{self, Config, CONFIG_VERSION};
+use anyhow::{anyhow, format_err, Result};
+use serde_json::Value as Json;
+use toml::value::Table;
+use toml::Value;
+
+/// Keys that moved, by dotted path. Applied to configs older than
+/// [CONFIG_VERSION].
+const RENAMED_KEYS: &[(&str, &str)] = &[
+    // the solana CLI's name for mainnet
+    ("networks.mainnet-beta", "networks.mainnet"),
+];
+
+/// Values of keys that became required, by dotted path. `*` stands for any
+/// key of the table, e.g. every network.
+const REQUIRED_DEFAULTS: &[(&str, &str)] = &[
+    ("paths.artifacts", "./.captain/artifacts/"),
+    ("paths.program_keypairs", "./.captain/program_keypairs"),
+    ("networks.*.upgrade_authority", "~/.config/solana/id.json"),
+];
+
+/// The outcome of migrating a config.
+#[derive(Debug)]
+pub struct Migration {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// What was changed, e.g. `Renamed networks.mainnet-beta to networks.mainnet`.
+    pub changes: Vec<String>,
+    /// Dotted paths of keys Captain doesn't know, which it ignores.
+    pub unknown_keys: Vec<String>,
+    /// The migrated config, with each key commented with its description.
+    pub contents: String,
+}
+
+/// Migrates the contents of a Captain.toml to [CONFIG_VERSION].
+///
+/// Renames moved keys and keys written with dashes instead of underscores,
+/// fills in keys that became required, and sets `version`. Unknown keys are
+/// kept and reported. Errors if the result still isn't a valid config.
+pub fn migrate(contents: &str) -> Result<Migration> {
+    let mut config: Table =
+        toml::from_str(contents).map_err(|e| format_err!("Unable to deserialize config: {}", e))?;
+    let from_version = match config.get("version") {
+        None => 0,
+        Some(Value::Integer(version)) if *version >= 0 => *version as u32,
+        Some(version) => return Err(anyhow!("Invalid config version {}", version)),
+    };
+    if from_version > CONFIG_VERSION {
+        return Err(anyhow!(
+            "Captain.toml is at config version {}, but this Captain only knows up to version {}. Upgrade Captain.",
+            from_version,
+            CONFIG_VERSION
+        ));
+    }
+
+    let schema = serde_json::to_value(schemars::schema_for!(Config))?;
+    let mut changes = vec![];
+    if from_version < CONFIG_VERSION {
+        for (old, new) in RENAMED_KEYS {
+            if rename(&mut config, old, new) {
+                changes.push(format!("Renamed {} to {}", old, new));
+            }
+        }
+    }
+    normalize_keys(&mut config, &schema, &schema, "", &mut changes);
+    if let Some(Value::Table(networks)) = config.get_mut("networks") {
+        for (name, network) in networks.iter_mut() {
+            if let Value::Table(network) = network {
+                for key in config::move_deploy_keys(network) {
+                    changes.push(format!(
+                        "Moved networks.{}.{} to networks.{}.deploy.{}",
+                        name, key, name, key
+                    ));
+                }
+            }
+        }
+    }
+    for (key, default) in REQUIRED_DEFAULTS {
+        let path: Vec<&str> = key.split('.').collect();
+        fill_default(&mut config, &path, default, "", &mut changes);
+    }
+    if from_version != CONFIG_VERSION {
+        config.insert(
+            "version".to_string(),
+            Value::Integer(i64::from(CONFIG_VERSION)),
+        );
+        changes.push(format!("Set version to {}", CONFIG_VERSION));
+    }
+
+    Value::Table(config.clone())
+        .try_into::<Config>()
+        .map_err(|e| format_err!("The migrated config is invalid: {}", e))?;
+    let mut unknown_keys = vec![];
+    find_unknown_keys(&config, &schema, &schema, "", &mut unknown_keys);
+    let mut contents = String::new();
+    write_table(&mut contents, &config, &schema, &schema, &[]);
+    Ok(Migration {
+        from_version,
+        to_version: CONFIG_VERSION,
+        changes,
+        unknown_keys,
+        contents,
+    })
+}
+
+/// Moves the value at dotted path `old` to `new`, unless `new` is taken.
+fn rename(config: &mut Table, old: &str, new: &str) -> bool {
+    let (old_parent, old_key) = split_key(old);
+    let (new_parent, new_key) = split_key(new);
+    let taken = table_at(config, new_parent)
+        .and_then(|table| table.get(new_key))
+        .is_some();
+    if taken {
+        return false;
+    }
+    let value = match table_at_mut(config, old_parent).and_then(|table| table.remove(old_key)) {
+        Some(value) => value,
+        None => return false,
+    };
+    match table_at_mut(config, new_parent) {
+        Some(table) => {
+            table.insert(new_key.to_string(), value);
+            true
+        }
+        None => {
+            // put it back rather than losing it
+            if let Some(table) = table_at_mut(config, old_parent) {
+                table.insert(old_key.to_string(), value);
+            }
+            false
+        }
+    }
+}
+
+fn split_key(key: &str) -> (&str, &str) {
+    match key.rfind('.') {
+        Some(i) => (&key[..i], &key[i + 1..]),
+        None => ("", key),
+    }
+}
+
+fn table_at<'a>(config: &'a Table, path: &str) -> Option<&'a Table> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(config, |table, key| table.get(key)?.as_table())
+}
+
+fn table_at_mut<'a>(config: &'a mut Table, path: &str) -> Option<&'a mut Table> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(config, |table, key| table.get_mut(key)?.as_table_mut())
+}
+
+/// Renames keys such as `ws-url` to the `ws_url` Captain reads, which it
+/// would otherwise silently ignore.
+fn normalize_keys(
+    table: &mut Table,
+    schema: &Json,
+    root: &Json,
+    path: &str,
+    changes: &mut Vec<String>,
+) {
+    let keys: Vec<String> = table.keys().cloned().collect();
+    for key in keys {
+        let snake = key.replace("-", "_");
+        if snake != key
+            && property(schema, root, &key).is_none()
+            && property(schema, root, &snake).is_some()
+            && !table.contains_key(&snake)
+        {
+            if let Some(value) = table.remove(&key) {
+                table.insert(snake.clone(), value);
+                changes.push(format!(
+                    "Renamed {} to {}",
+                    join_key(path, &key),
+                    join_key(path, &snake)
+                ));
+            }
+        }
+    }
+    for (key, value) in table.iter_mut() {
+        if let (Value::Table(child), Some(child_schema)) = (value, child_schema(schema, root, key))
+        {
+            normalize_keys(child, child_schema, root, &join_key(path, key), changes);
+        }
+    }
+}
+
+fn fill_default(
+    table: &mut Table,
+    path: &[&str],
+    default: &str,
+    prefix: &str,
+    changes: &mut Vec<String>,
+) {
+    match path {
+        [] => {}
+        [key] => {
+            if !table.contains_key(*key) {
+                table.insert(key.to_string(), Value::String(default.to_string()));
+                changes.push(format!("Set {} to {:?}", join_key(prefix, key), default));
+            }
+        }
+        ["*", rest @ ..] => {
+            for (key, value) in table.iter_mut() {
+                if let Value::Table(child) = value {
+                    fill_default(child, rest, default, &join_key(prefix, key), changes);
+                }
+            }
+        }
+        [key, rest @ ..] => {
+            let child = table
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if let Value::Table(child) = child {
+                fill_default(child, rest, default, &join_key(prefix, key), changes);
+            }
+        }
+    }
+}
+
+fn find_unknown_keys(
+    table: &Table,
+    schema: &Json,
+    root: &Json,
+    path: &str,
+    unknown: &mut Vec<String>,
+) {
+    for (key, value) in table {
+        match child_schema(schema, root, key) {
+            Some(child) => {
+                if let Value::Table(value) = value {
+                    find_unknown_keys(value, child, root, &join_key(path, key), unknown);
+                }
+            }
+            None => unknown.push(join_key(path, key)),
+        }
+    }
+}
+
+/// Writes a table in TOML, commenting each key with its description.
+fn write_table(out: &mut String, table: &Table, schema: &Json, root: &Json, path: &[String]) {
+    let (tables, values): (Vec<_>, Vec<_>) = table
+        .iter()
+        .partition(|(_, value)| matches!(value, Value::Table(_)));
+    for (key, value) in values {
+        write_description(out, property(schema, root, key));
+        out.push_str(&format!("{} = {}\n", toml_key(key), inline_value(value)));
+    }
+    for (key, value) in tables {
+        let mut child_path = path.to_vec();
+        child_path.push(toml_key(key));
+        let child = child_schema(schema, root, key);
+        let child_table = value.as_table().expect("partitioned into tables");
+        let has_values = child_table.values().any(|value| !value.is_table());
+        if has_values || child_table.is_empty() {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            write_description(out, property(schema, root, key));
+            out.push_str(&format!("[{}]\n", child_path.join(".")));
+        }
+        write_table(
+            out,
+            child_table,
+            child.unwrap_or(&Json::Null),
+            root,
+            &child_path,
+        );
+    }
+}
+
+fn write_description(out: &mut String, property: Option<&Json>) {
+    let description = property
+        .and_then(|property| property.get("description"))
+        .and_then(|description| description.as_str());
+    if let Some(description) = description {
+        for line in description.lines() {
+            if line.is_empty() {
+                out.push_str("#\n");
+            } else {
+                out.push_str(&format!("# {}\n", line));
+            }
+        }
+    }
+}
+
+/// A key as written in TOML: bare if it can be, quoted otherwise.
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+/// A value as written after `key = `, with arrays and tables inline.
+fn inline_value(value: &Value) -> String {
+    match value {
+        Value::Array(values) => format!(
+            "[{}]",
+            values
+                .iter()
+                .map(inline_value)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Table(table) => format!(
+            "{{ {} }}",
+            table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", toml_key(key), inline_value(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        value => value.to_string(),
+    }
+}
+
+fn join_key(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Follows `$ref`s and unwraps `allOf` and `Option`'s `anyOf` to the schema
+/// of the value itself.
+fn resolve<'a>(schema: &'a Json, root: &'a Json) -> &'a Json {
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        let name = reference.trim_start_matches("#/definitions/");
+        return resolve(&root["definitions"][name], root);
+    }
+    let wrapped = schema
+        .get("allOf")
+        .or_else(|| schema.get("anyOf"))
+        .and_then(|schemas| schemas.as_array())
+        .and_then(|schemas| {
+            schemas
+                .iter()
+                .find(|schema| schema.get("type").and_then(|t| t.as_str()) != Some("null"))
+        });
+    match wrapped {
+        Some(wrapped) => resolve(wrapped, root),
+        None => schema,
+    }
+}
+
+/// The schema of a known field of an object, with its description.
+fn property<'a>(schema: &'a Json, root: &'a Json, key: &str) -> Option<&'a Json> {
+    resolve(schema, root).get("properties")?.get(key)
+}
+
+/// The schema of a field or map entry of an object, or `None` if Captain
+/// ignores the key.
+fn child_schema<'a>(schema: &'a Json, root: &'a Json, key: &str) -> Option<&'a Json> {
+    property(schema, root, key).or_else(|| {
+        resolve(schema, root)
+            .get("additionalProperties")
+            .filter(|schema| schema.is_object())
+    })
+}
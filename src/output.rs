@@ -0,0 +1,136 @@
+//! Rendering of command results, as text for people or JSON for scripts.
+use crate::receipt::{Action, Receipt};
+use crate::workspace::DeployCost;
+use anyhow::Result;
+use serde::Serialize;
+use solana_sdk::native_token::lamports_to_sol;
+use strum_macros::{Display, EnumString, EnumVariantNames, IntoStaticStr};
+
+/// How the result of a command is printed to stdout.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Display, EnumString, EnumVariantNames, IntoStaticStr,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Format {
+    /// Text meant to be read.
+    Human,
+    /// Pretty-printed JSON.
+    Json,
+}
+
+impl Format {
+    /// Renders a result in this format.
+    pub fn render<T: Output + ?Sized>(self, output: &T) -> Result<String> {
+        match self {
+            Format::Human => Ok(output.to_human()),
+            Format::Json => output.to_json(),
+        }
+    }
+}
+
+/// The final result of a command.
+pub trait Output {
+    /// The result as text, without a trailing newline.
+    fn to_human(&self) -> String;
+    /// The result as pretty-printed JSON.
+    fn to_json(&self) -> Result<String>;
+}
+
+impl Output for Receipt {
+    fn to_human(&self) -> String {
+        let verb = match self.action {
+            Action::Deployed => "Deployed",
+            Action::AlreadyDeployed => "Already deployed",
+            Action::Upgraded => "Upgraded",
+            Action::Simulated => "Simulated upgrade of",
+            Action::BufferWritten => "Wrote buffer for",
+        };
+        let mut lines = vec![format!(
+            "{} {} {} to {}",
+            verb, self.program, self.version, self.network
+        )];
+        let mut field = |label: &str, value: String| {
+            lines.push(format!("    {:<20} {}", format!("{}:", label), value))
+        };
+        field("Program id", self.program_id.clone());
+        if let Some(buffer) = &self.buffer {
+            field("Buffer", buffer.clone());
+        }
+        if self.immutable {
+            field("Upgrade authority", "none (immutable)".to_string());
+        } else if let Some(authority) = &self.upgrade_authority {
+            field("Upgrade authority", authority.clone());
+        }
+        if let Some(authority) = &self.idl_authority {
+            field("IDL authority", authority.clone());
+        }
+        if let Some(hash) = &self.verifiable_hash {
+            field("Verifiable hash", hash.clone());
+        }
+        if let Some(len) = self.program_data_len {
+            field("Program data", format!("{} bytes", len));
+        }
+        if let Some(rent) = self.rent_lamports {
+            field("Rent", format!("{} SOL", lamports_to_sol(rent)));
+        }
+        if let Some(commit) = &self.git_commit {
+            let dirty = if self.git_dirty == Some(true) {
+                " (dirty)"
+            } else {
+                ""
+            };
+            field("Git commit", format!("{}{}", commit, dirty));
+        }
+        for signature in &self.signatures {
+            field("Signature", signature.clone());
+        }
+        for companion in &self.companions {
+            lines.push(companion.to_human());
+        }
+        lines.join("\n")
+    }
+
+    fn to_json(&self) -> Result<String> {
+        to_json(self)
+    }
+}
+
+impl Output for DeployCost {
+    fn to_human(&self) -> String {
+        let mut lines = vec![format!(
+            "Estimated cost of deploying {} {} to {}",
+            self.program, self.version, self.network
+        )];
+        let costs = [
+            ("Program account rent".to_string(), self.program_rent),
+            (
+                format!("Program data rent ({} bytes)", self.program_data_len),
+                self.program_data_rent,
+            ),
+            (
+                format!("Buffer rent ({} bytes, refunded)", self.bin_len),
+                self.buffer_rent,
+            ),
+            ("Transaction fees".to_string(), self.fees),
+            ("Needed up front".to_string(), self.required()),
+            ("Total after refund".to_string(), self.total()),
+        ];
+        for (label, lamports) in &costs {
+            lines.push(format!(
+                "    {:<40} {:>14} SOL",
+                label,
+                lamports_to_sol(*lamports)
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn to_json(&self) -> Result<String> {
+        to_json(self)
+    }
+}
+
+/// Serializes a result as pretty-printed JSON, for [Output::to_json].
+pub fn to_json<T: Serialize + ?Sized>(output: &T) -> Result<String> {
+    Ok(serde_json::to_string_pretty(output)?)
+}
@@ -0,0 +1,119 @@
+//! Progress reporting for multi-step operations.
+use anyhow::Result;
+use colored::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Whether `--quiet` silenced progress output.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Silences progress messages and the output of the commands Captain runs,
+/// leaving results, warnings and errors.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether progress output is silenced. See [set_quiet].
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// A single completed phase of an operation.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub header: &'static str,
+    pub start: Instant,
+    pub end: Instant,
+}
+
+impl Step {
+    pub fn elapsed(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Prints step banners and records how long each step took.
+///
+/// A quiet progress prints nothing, but still records the steps.
+pub struct Progress {
+    quiet: bool,
+    start: Instant,
+    steps: Vec<Step>,
+}
+
+impl Progress {
+    pub fn new(quiet: bool) -> Self {
+        Progress {
+            quiet,
+            start: Instant::now(),
+            steps: vec![],
+        }
+    }
+
+    /// Runs a step, printing its banner before and its elapsed time after.
+    pub fn step<T>(&mut self, header: &'static str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        if !self.quiet {
+            output_header(header);
+        }
+        let start = Instant::now();
+        let result = f()?;
+        let step = Step {
+            header,
+            start,
+            end: Instant::now(),
+        };
+        if !self.quiet {
+            eprintln!();
+            eprintln!(
+                "{}",
+                format!("{} in {}", header, format_duration(step.elapsed())).green()
+            );
+        }
+        self.steps.push(step);
+        Ok(result)
+    }
+
+    /// Prints a header for something that isn't timed, e.g. a skipped step.
+    pub fn note(&self, header: &str) {
+        if !self.quiet {
+            output_header(header);
+        }
+    }
+
+    /// Prints the time taken by every step and the total elapsed time.
+    pub fn summary(&self) {
+        if self.quiet {
+            return;
+        }
+        let total = format_duration(self.start.elapsed());
+        eprintln!();
+        eprintln!("{}", "Timing".bold());
+        for step in &self.steps {
+            eprintln!(
+                "    {:<40} {}",
+                step.header,
+                format_duration(step.elapsed())
+            );
+        }
+        eprintln!("    {:<40} {}", "Total".bold(), total.bold());
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 60.0 {
+        format!("{:.2}s", secs)
+    } else {
+        format!("{}m {:.1}s", (secs / 60.0).floor(), secs % 60.0)
+    }
+}
+
+pub fn output_header(header: &str) {
+    eprintln!();
+    eprintln!("{}", "===================================".bold());
+    eprintln!();
+    eprintln!("    {}", header.bold());
+    eprintln!();
+    eprintln!("{}", "===================================".bold());
+    eprintln!();
+}
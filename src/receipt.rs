@@ -0,0 +1,78 @@
+//! Records of what a deploy or upgrade did.
+use crate::config::Network;
+use anyhow::Result;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// The program was deployed for the first time.
+    Deployed,
+    /// The program already existed, so nothing was done.
+    AlreadyDeployed,
+    /// The program was upgraded to a new buffer.
+    Upgraded,
+    /// The upgrade was only simulated.
+    Simulated,
+    /// A buffer was written for a later upgrade, which wasn't applied yet.
+    BufferWritten,
+}
+
+/// The outcome of a deploy or upgrade.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    pub program: String,
+    pub version: Version,
+    pub network: Network,
+    pub program_id: String,
+    pub action: Action,
+    /// The buffer the program was upgraded from.
+    pub buffer: Option<String>,
+    /// The upgrade authority a deploy handed the program to. `None` if the
+    /// deployer was left as the upgrade authority.
+    #[serde(default)]
+    pub upgrade_authority: Option<String>,
+    /// The authority of the IDL account a deploy uploaded: the upgrade
+    /// authority it was handed to, or the deployer if it was kept. `None` if
+    /// no IDL was uploaded.
+    #[serde(default)]
+    pub idl_authority: Option<String>,
+    /// Hash of the verifiable build of the binary, if it had one.
+    #[serde(default)]
+    pub verifiable_hash: Option<String>,
+    /// Whether the deploy gave up the upgrade authority, making the program
+    /// immutable.
+    #[serde(default)]
+    pub immutable: bool,
+    /// Bytes allocated for the program data by a deploy.
+    #[serde(default)]
+    pub program_data_len: Option<usize>,
+    /// Rent-exempt balance a deploy locked in the program and program data
+    /// accounts, in lamports.
+    #[serde(default)]
+    pub rent_lamports: Option<u64>,
+    /// The commit the workspace was at, if it is a git repository.
+    #[serde(default)]
+    pub git_commit: Option<String>,
+    /// Whether tracked files had uncommitted changes.
+    #[serde(default)]
+    pub git_dirty: Option<bool>,
+    /// Signatures of the deploy, buffer write and upgrade transactions, as
+    /// far as the solana CLI reported them.
+    #[serde(default)]
+    pub signatures: Vec<String>,
+    /// Receipts of the companion programs deployed along with the program.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub companions: Vec<Receipt>,
+}
+
+impl Receipt {
+    /// Writes the receipt as pretty-printed JSON.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
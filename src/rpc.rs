@@ -0,0 +1,137 @@
+//! Minimal client for the Solana JSON RPC API.
+use crate::config::Commitment;
+use anyhow::{format_err, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+use std::time::Duration;
+
+pub struct RpcClient {
+    url: String,
+    commitment: Commitment,
+    client: reqwest::blocking::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+/// A result wrapped with the slot it was read at.
+#[derive(Debug, Deserialize)]
+struct RpcContextValue<T> {
+    value: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcAccount {
+    /// The account data and its encoding.
+    data: (String, String),
+}
+
+/// The outcome of simulating a transaction.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Simulation {
+    /// Why the transaction failed, if it did.
+    pub err: Option<Value>,
+    #[serde(default)]
+    pub logs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcClient {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(RpcClient {
+            url: url.to_string(),
+            commitment: Commitment::default(),
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+        })
+    }
+
+    /// Reads accounts and simulates transactions at the given commitment.
+    pub fn with_commitment(mut self, commitment: Commitment) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    /// Sends a JSON RPC request and deserializes its result.
+    pub fn request<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let response: RpcResponse<T> = self
+            .client
+            .post(&self.url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": params,
+            }))
+            .send()
+            .map_err(|e| format_err!("RPC {} is unreachable: {}", self.url, e))?
+            .error_for_status()?
+            .json()?;
+        match (response.result, response.error) {
+            (_, Some(err)) => Err(format_err!(
+                "RPC {} returned error {}: {}",
+                self.url,
+                err.code,
+                err.message
+            )),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(format_err!("RPC {} returned no result", self.url)),
+        }
+    }
+
+    /// Returns an error if the node is not healthy.
+    pub fn get_health(&self) -> Result<()> {
+        let health: String = self.request("getHealth", json!([]))?;
+        if health != "ok" {
+            return Err(format_err!("RPC {} is unhealthy: {}", self.url, health));
+        }
+        Ok(())
+    }
+
+    /// Returns the data of an account, or `None` if it doesn't exist.
+    pub fn get_account_data(&self, pubkey: &Pubkey) -> Result<Option<Vec<u8>>> {
+        let account: RpcContextValue<Option<RpcAccount>> = self.request(
+            "getAccountInfo",
+            json!([
+                pubkey.to_string(),
+                { "encoding": "base64", "commitment": self.commitment.as_ref() },
+            ]),
+        )?;
+        account
+            .value
+            .map(|account| {
+                base64::decode(&account.data.0)
+                    .map_err(|e| format_err!("could not decode data of {}: {}", pubkey, e))
+            })
+            .transpose()
+    }
+
+    /// Simulates an unsigned transaction against the latest blockhash.
+    pub fn simulate_transaction(&self, transaction: &Transaction) -> Result<Simulation> {
+        let simulation: RpcContextValue<Simulation> = self.request(
+            "simulateTransaction",
+            json!([
+                base64::encode(bincode::serialize(transaction)?),
+                {
+                    "encoding": "base64",
+                    "sigVerify": false,
+                    "replaceRecentBlockhash": true,
+                    "commitment": self.commitment.as_ref(),
+                },
+            ]),
+        )?;
+        Ok(simulation.value)
+    }
+}
@@ -0,0 +1,171 @@
+//! Deploying several programs at once, in the order set by their
+//! `depends_on`.
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// When a program's task ran, relative to the start of [run].
+#[derive(Debug, Clone)]
+pub struct Timing {
+    pub program: String,
+    pub started: Duration,
+    pub finished: Duration,
+    pub succeeded: bool,
+}
+
+/// Orders programs so each comes after the programs it depends on, keeping
+/// the given order otherwise.
+///
+/// `depends_on` maps programs to their dependencies. Dependencies that
+/// aren't among `programs` are ignored, as they are expected to be deployed
+/// already. Dashes and underscores in names are interchangeable. Errors if
+/// the dependencies form a cycle.
+pub fn order(
+    programs: &[String],
+    depends_on: &BTreeMap<String, Vec<String>>,
+) -> Result<Vec<String>> {
+    let mut remaining: Vec<&String> = programs.iter().collect();
+    let mut ordered: Vec<String> = vec![];
+    while !remaining.is_empty() {
+        let placed: BTreeSet<String> = ordered.iter().map(|p| normalize(p)).collect();
+        let next = remaining.iter().position(|program| {
+            dependencies(program, programs, depends_on)
+                .iter()
+                .all(|dependency| placed.contains(dependency))
+        });
+        match next {
+            Some(i) => ordered.push(remaining.remove(i).clone()),
+            None => return Err(cycle(&remaining, programs, depends_on)),
+        }
+    }
+    Ok(ordered)
+}
+
+/// Runs `task` for each program of `order`, up to `jobs` at a time, starting
+/// each once the programs it depends on succeeded.
+///
+/// `done` is called with each result as it comes in, and returns whether to
+/// start more tasks. Programs whose dependencies failed are not run, and
+/// `done` gets an error for them instead. Returns when the running tasks
+/// finish, with when each of them ran.
+pub fn run<T, F, D>(
+    order: &[String],
+    depends_on: &BTreeMap<String, Vec<String>>,
+    jobs: usize,
+    task: F,
+    mut done: D,
+) -> Result<Vec<Timing>>
+where
+    T: Send + 'static,
+    F: Fn(&str) -> Result<T> + Send + Sync + 'static,
+    D: FnMut(&str, Result<T>) -> Result<bool>,
+{
+    let task = Arc::new(task);
+    let start = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    let mut pending: Vec<String> = order.to_vec();
+    let mut outcomes: BTreeMap<String, bool> = BTreeMap::new();
+    let mut timings = vec![];
+    let mut running = 0;
+    let mut stopped = false;
+    loop {
+        let mut i = 0;
+        while !stopped && i < pending.len() {
+            let dependencies = dependencies(&pending[i], order, depends_on);
+            if let Some(failed) = dependencies
+                .iter()
+                .find(|dependency| outcomes.get(*dependency) == Some(&false))
+            {
+                let program = pending.remove(i);
+                outcomes.insert(normalize(&program), false);
+                let err = anyhow!("Not deployed because {} failed", failed);
+                stopped = !done(&program, Err(err))?;
+                continue;
+            }
+            let ready = dependencies
+                .iter()
+                .all(|dependency| outcomes.get(dependency) == Some(&true));
+            if !ready || running >= jobs.max(1) {
+                i += 1;
+                continue;
+            }
+            let program = pending.remove(i);
+            let task = task.clone();
+            let sender = sender.clone();
+            running += 1;
+            thread::spawn(move || {
+                let started = start.elapsed();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| task(&program)))
+                    .unwrap_or_else(|_| Err(anyhow!("Deploying {} panicked", program)));
+                let _ = sender.send((program, started, start.elapsed(), result));
+            });
+        }
+        if running == 0 {
+            break;
+        }
+        let (program, started, finished, result) = receiver
+            .recv()
+            .map_err(|_| anyhow!("lost track of a running deploy"))?;
+        running -= 1;
+        let succeeded = result.is_ok();
+        outcomes.insert(normalize(&program), succeeded);
+        timings.push(Timing {
+            program: program.clone(),
+            started,
+            finished,
+            succeeded,
+        });
+        if !done(&program, result)? {
+            stopped = true;
+        }
+    }
+    Ok(timings)
+}
+
+/// The normalized dependencies of a program that are among `programs`.
+fn dependencies(
+    program: &str,
+    programs: &[String],
+    depends_on: &BTreeMap<String, Vec<String>>,
+) -> Vec<String> {
+    let selected: BTreeSet<String> = programs.iter().map(|p| normalize(p)).collect();
+    depends_on
+        .iter()
+        .filter(|(name, _)| normalize(name) == normalize(program))
+        .flat_map(|(_, dependencies)| dependencies.iter().map(|d| normalize(d)))
+        .filter(|dependency| selected.contains(dependency))
+        .collect()
+}
+
+/// Describes a cycle among programs that can't be ordered, each of which
+/// depends on another of them.
+fn cycle(
+    remaining: &[&String],
+    programs: &[String],
+    depends_on: &BTreeMap<String, Vec<String>>,
+) -> anyhow::Error {
+    let remaining: BTreeSet<String> = remaining.iter().map(|p| normalize(p)).collect();
+    let mut path: Vec<String> = vec![];
+    let mut current = remaining.iter().next().cloned().unwrap_or_default();
+    while !path.contains(&current) {
+        path.push(current.clone());
+        current = dependencies(&current, programs, depends_on)
+            .into_iter()
+            .find(|dependency| remaining.contains(dependency))
+            .unwrap_or_default();
+    }
+    let start = path.iter().position(|p| *p == current).unwrap_or(0);
+    let mut cycle = path[start..].to_vec();
+    cycle.push(current);
+    anyhow!(
+        "Programs depend on each other in a cycle: {}",
+        cycle.join(" -> ")
+    )
+}
+
+fn normalize(program: &str) -> String {
+    program.replace("-", "_")
+}
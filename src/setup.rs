@@ -0,0 +1,168 @@
+//! The interactive `captain setup` wizard.
+use crate::config::{CaptainPath, Config, Network, NetworkConfig, Tools};
+use crate::keypair::SignerSource;
+use anyhow::{anyhow, format_err, Result};
+use colored::*;
+use solana_sdk::signer::keypair::{read_keypair_file, write_keypair_file, Keypair};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Networks the wizard configures, in the order it asks about them.
+const NETWORKS: &[Network] = &[
+    Network::Mainnet,
+    Network::Devnet,
+    Network::Testnet,
+    Network::Localnet,
+];
+
+/// Upgrade authority suggested when none is configured, as in `captain init`.
+const DEFAULT_UPGRADE_AUTHORITY: &str = "~/.config/solana/id.json";
+
+/// Prompts on stderr for the deployer, upgrade authority and RPC URL of each
+/// network, and writes the answers to the `Captain.toml` at `path`.
+///
+/// If `path` already exists, it is loaded first and only the values missing
+/// from it are asked for.
+pub fn run(path: &Path, input: &mut impl BufRead) -> Result<()> {
+    let mut config: Config = if path.exists() {
+        fs::read_to_string(path)
+            .map_err(|e| format_err!("could not read {}: {}", path.display(), e))?
+            .parse()?
+    } else {
+        Config::default()
+    };
+
+    for network in NETWORKS {
+        let network_config = match config.networks.remove(network) {
+            Some(mut network_config) => {
+                if network_config.url.is_none() {
+                    eprintln!("{}", format!("Configuring {}", network).bold());
+                    set_url(&mut network_config, prompt_url(input, network)?, network);
+                }
+                network_config
+            }
+            None => {
+                eprintln!("{}", format!("Configuring {}", network).bold());
+                let mut network_config = NetworkConfig {
+                    deployer: Some(prompt_deployer(input, network)?),
+                    upgrade_authority: prompt_upgrade_authority(input, network)?,
+                    ..Default::default()
+                };
+                set_url(&mut network_config, prompt_url(input, network)?, network);
+                network_config
+            }
+        };
+        config.networks.insert(network.clone(), network_config);
+    }
+
+    fs::write(path, toml::to_string(&config)?)
+        .map_err(|e| format_err!("could not write {}: {}", path.display(), e))?;
+    eprintln!("{}", format!("Wrote {}", path.display()).green());
+    Ok(())
+}
+
+/// Asks `question` until `parse` accepts the trimmed answer, printing why
+/// each rejected answer was rejected.
+fn prompt<T>(
+    input: &mut impl BufRead,
+    question: &str,
+    mut parse: impl FnMut(&str) -> Result<T>,
+) -> Result<T> {
+    loop {
+        eprint!("{}: ", question);
+        io::stderr().flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(anyhow!("Setup aborted before it was complete."));
+        }
+        match parse(line.trim()) {
+            Ok(value) => return Ok(value),
+            Err(e) => eprintln!("{}", e.to_string().red()),
+        }
+    }
+}
+
+/// Asks for the path of a network's deployer keypair. An empty answer
+/// generates one under `.captain/deployers/`, or reuses it if it exists.
+fn prompt_deployer(input: &mut impl BufRead, network: &Network) -> Result<CaptainPath> {
+    let default = PathBuf::from(format!("./.captain/deployers/{}/deployer.json", network));
+    let question = format!("Deployer keypair for {} [{}]", network, default.display());
+    prompt(input, &question, |answer| {
+        if answer.is_empty() {
+            if !default.exists() {
+                if let Some(parent) = default.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                write_keypair_file(&Keypair::new(), &default)
+                    .map_err(|_| format_err!("could not write {}", default.display()))?;
+                eprintln!("Generated {}", default.display());
+            }
+            return Ok(CaptainPath(default.clone()));
+        }
+        let path: CaptainPath = answer.parse()?;
+        if let SignerSource::Uri(_) = SignerSource::parse(answer) {
+            // hardware wallets and prompts can't be checked without asking
+            return Ok(path);
+        }
+        if !path.0.exists() {
+            return Err(anyhow!("{} does not exist", path.0.display()));
+        }
+        read_keypair_file(&path.0)
+            .map_err(|_| format_err!("{} is not a keypair file", path.0.display()))?;
+        Ok(path)
+    })
+}
+
+/// Asks for a network's upgrade authority, as an address or a keypair path.
+fn prompt_upgrade_authority(input: &mut impl BufRead, network: &Network) -> Result<String> {
+    let question = format!(
+        "Upgrade authority for {}, as an address, keypair path or signer URI [{}]",
+        network, DEFAULT_UPGRADE_AUTHORITY
+    );
+    prompt(input, &question, |answer| {
+        let upgrade_authority = if answer.is_empty() {
+            DEFAULT_UPGRADE_AUTHORITY
+        } else {
+            answer
+        };
+        if let SignerSource::Uri(_) = SignerSource::parse(upgrade_authority) {
+            return Ok(upgrade_authority.to_string());
+        }
+        NetworkConfig {
+            upgrade_authority: upgrade_authority.to_string(),
+            ..Default::default()
+        }
+        .upgrade_authority_pubkey(&Tools::default().solana())?;
+        Ok(upgrade_authority.to_string())
+    })
+}
+
+/// Asks for a network's RPC URL. An empty answer keeps the default URL.
+fn prompt_url(input: &mut impl BufRead, network: &Network) -> Result<Option<String>> {
+    let question = format!("RPC URL for {} [{}]", network, network.url());
+    prompt(input, &question, |answer| {
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        if !answer.starts_with("http://") && !answer.starts_with("https://") {
+            return Err(anyhow!("{} is not an http(s) URL", answer));
+        }
+        Ok(Some(answer.to_string()))
+    })
+}
+
+/// Sets a custom RPC URL, or the network's default URLs as `captain init`
+/// does. A custom URL leaves the websocket URL to be derived from it.
+fn set_url(network_config: &mut NetworkConfig, url: Option<String>, network: &Network) {
+    match url {
+        Some(url) => {
+            network_config.url = Some(url);
+            network_config.ws_url = None;
+        }
+        None => {
+            network_config.url = Some(network.url());
+            network_config.ws_url = Some(network.ws_url());
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Detached signatures of released program binaries, proving a binary was
+//! released by the holder of the `[security]` signing key.
+use crate::keypair;
+use anyhow::{anyhow, format_err, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A signature of a binary, stored next to it as `<bin>.sig`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DetachedSignature {
+    /// Address of the key that signed the binary.
+    pub signer: String,
+    /// Base58 ed25519 signature of the binary's bytes.
+    pub signature: String,
+}
+
+/// Where the signature of a binary is stored, e.g. `program.so.sig`.
+pub fn signature_path(bin: &Path) -> PathBuf {
+    let mut path = bin.as_os_str().to_os_string();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Signs a binary with the keypair at `key`, which may be encrypted, and
+/// writes the signature next to it. Returns the signer's address.
+pub fn sign(bin: &Path, key: &Path) -> Result<Pubkey> {
+    let keypair = keypair::read_keypair(key)?;
+    let contents =
+        fs::read(bin).map_err(|e| format_err!("could not read {}: {}", bin.display(), e))?;
+    let signature = DetachedSignature {
+        signer: keypair.pubkey().to_string(),
+        signature: keypair.sign_message(&contents).to_string(),
+    };
+    let path = signature_path(bin);
+    fs::write(&path, serde_json::to_string_pretty(&signature)?)
+        .map_err(|e| format_err!("could not write {}: {}", path.display(), e))?;
+    Ok(keypair.pubkey())
+}
+
+/// Checks the signature next to a binary, and that it was made by
+/// `expected_signer`. Returns the signer's address.
+pub fn verify(bin: &Path, expected_signer: &Pubkey) -> Result<Pubkey> {
+    let path = signature_path(bin);
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format_err!("could not read {}: {}", path.display(), e))?;
+    let detached: DetachedSignature = serde_json::from_str(&contents)
+        .map_err(|e| format_err!("could not parse {}: {}", path.display(), e))?;
+    let signer = Pubkey::from_str(&detached.signer).map_err(|_| {
+        format_err!(
+            "{} has an invalid signer {}",
+            path.display(),
+            detached.signer
+        )
+    })?;
+    let signature = Signature::from_str(&detached.signature)
+        .map_err(|_| format_err!("{} has an invalid signature", path.display()))?;
+    if signer != *expected_signer {
+        return Err(anyhow!(
+            "{} was signed by {}, not the expected signer {}",
+            bin.display(),
+            signer,
+            expected_signer
+        ));
+    }
+    let bytes =
+        fs::read(bin).map_err(|e| format_err!("could not read {}: {}", bin.display(), e))?;
+    if !signature.verify(signer.as_ref(), &bytes) {
+        return Err(anyhow!(
+            "The signature of {} does not match its contents",
+            bin.display()
+        ));
+    }
+    Ok(signer)
+}
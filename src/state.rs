@@ -0,0 +1,173 @@
+//! Progress of deploys, so a deploy that failed partway can be resumed, and
+//! buffers written ahead of an upgrade.
+use crate::config::Network;
+use anyhow::{format_err, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A step of a deploy that is skipped when resuming once it completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeployStep {
+    /// `solana program deploy`.
+    Deploy,
+    /// Handing the upgrade authority over, or giving it up.
+    SetAuthority,
+    /// `anchor idl init` or `anchor idl upgrade`.
+    Idl,
+    /// Handing the IDL authority over.
+    IdlAuthority,
+}
+
+/// The completed steps of a deploy, stored in
+/// `.captain/state/<program>-<version>-<network>.json` until the deploy
+/// succeeds.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeployState {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Hex-encoded SHA-256 of the binary being deployed, so a different
+    /// binary isn't resumed.
+    pub bin_sha256: String,
+    pub completed: Vec<DeployStep>,
+    /// Rent-exempt balance locked by the deploy step, in lamports.
+    #[serde(default)]
+    pub rent_lamports: u64,
+    /// Signatures of the deploy step.
+    #[serde(default)]
+    pub signatures: Vec<String>,
+}
+
+impl DeployState {
+    /// Where the state of deploying a program version to a network is kept.
+    pub fn path(root: &Path, program: &str, version: &Version, network: &Network) -> PathBuf {
+        root.join(".captain")
+            .join("state")
+            .join(format!("{}-{}-{}.json", program, version, network))
+    }
+
+    /// A state with no completed steps, saved to `path` as steps complete.
+    pub fn new(path: PathBuf, bin_sha256: String) -> Self {
+        DeployState {
+            path,
+            bin_sha256,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the state at `path`, or `None` if no deploy left one.
+    pub fn load(path: PathBuf) -> Result<Option<Self>> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format_err!("could not read {}: {}", path.display(), e)),
+        };
+        let state: DeployState = serde_json::from_str(&contents)
+            .map_err(|e| format_err!("could not parse {}: {}", path.display(), e))?;
+        Ok(Some(DeployState { path, ..state }))
+    }
+
+    pub fn is_done(&self, step: DeployStep) -> bool {
+        self.completed.contains(&step)
+    }
+
+    /// Records a completed step.
+    pub fn complete(&mut self, step: DeployStep) -> Result<()> {
+        if !self.is_done(step) {
+            self.completed.push(step);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format_err!("could not create {}: {}", parent.display(), e))?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)
+            .map_err(|e| format_err!("could not write {}: {}", self.path.display(), e))
+    }
+
+    /// Removes the state once the deploy succeeded.
+    pub fn clear(self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format_err!(
+                "could not remove {}: {}",
+                self.path.display(),
+                e
+            )),
+        }
+    }
+}
+
+/// A buffer written by `captain upgrade --write-only`, stored in
+/// `.captain/state/<program>-<version>-<network>-buffer.json` until an
+/// upgrade from it succeeds.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PendingBuffer {
+    #[serde(skip)]
+    path: PathBuf,
+    pub buffer: String,
+    /// Hex-encoded SHA-256 of the binary written to the buffer.
+    pub bin_sha256: String,
+    /// Signatures of writing the buffer.
+    #[serde(default)]
+    pub signatures: Vec<String>,
+}
+
+impl PendingBuffer {
+    /// Where the buffer written for a program version and network is kept.
+    pub fn path(root: &Path, program: &str, version: &Version, network: &Network) -> PathBuf {
+        root.join(".captain")
+            .join("state")
+            .join(format!("{}-{}-{}-buffer.json", program, version, network))
+    }
+
+    pub fn new(path: PathBuf, buffer: String, bin_sha256: String, signatures: Vec<String>) -> Self {
+        PendingBuffer {
+            path,
+            buffer,
+            bin_sha256,
+            signatures,
+        }
+    }
+
+    /// Reads the buffer recorded at `path`, or `None` if there is none.
+    pub fn load(path: PathBuf) -> Result<Option<Self>> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format_err!("could not read {}: {}", path.display(), e)),
+        };
+        let pending: PendingBuffer = serde_json::from_str(&contents)
+            .map_err(|e| format_err!("could not parse {}: {}", path.display(), e))?;
+        Ok(Some(PendingBuffer { path, ..pending }))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format_err!("could not create {}: {}", parent.display(), e))?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)
+            .map_err(|e| format_err!("could not write {}: {}", self.path.display(), e))
+    }
+
+    /// Removes the record once the buffer was upgraded from.
+    pub fn clear(self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format_err!(
+                "could not remove {}: {}",
+                self.path.display(),
+                e
+            )),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! The state of programs across networks, for `captain status`.
+use crate::artifacts;
+use crate::config::{Config, KeypairScheme, Network};
+use crate::keypair;
+use crate::rpc::RpcClient;
+use anyhow::{format_err, Result};
+use semver::Version;
+use serde::Serialize;
+use solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The state of a program on one network.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgramStatus {
+    pub program: String,
+    pub network: Network,
+    /// Address the program is deployed at, or its newest address if it isn't
+    /// deployed.
+    pub program_id: Option<String>,
+    pub deployed: bool,
+    /// Version found on chain, if it can be told from the released artifacts
+    /// or the on-chain IDL.
+    pub version: Option<Version>,
+    /// On-chain upgrade authority, or `None` if immutable or not deployed.
+    pub upgrade_authority: Option<String>,
+    /// Whether the on-chain upgrade authority is the configured one. `None`
+    /// if not deployed or if the configured one can't be resolved.
+    pub authority_matches: Option<bool>,
+    /// Why the state could not be fetched, e.g. an unreachable network.
+    pub error: Option<String>,
+}
+
+/// The addresses of a program's keypairs, with the version each is for.
+type Keypairs = Vec<(Option<Version>, Result<Pubkey>)>;
+
+/// Lists the programs that have a keypair, with their addresses, newest
+/// keypair first. Programs none of whose keypairs can be read get the error
+/// reading one of them.
+pub fn programs(config: &Config) -> Result<BTreeMap<String, Result<Vec<Pubkey>>>> {
+    let mut programs: BTreeMap<String, Keypairs> = BTreeMap::new();
+    for (name, pubkey) in keypair::read_pubkeys(&config.paths.program_keypairs.0)? {
+        let (program, version) = split_keypair_name(&name, config.paths.keypair_scheme);
+        programs.entry(program).or_default().push((version, pubkey));
+    }
+    Ok(programs
+        .into_iter()
+        .map(|(program, mut keypairs)| {
+            keypairs.sort_by(|(a, _), (b, _)| b.cmp(a));
+            let mut pubkeys = vec![];
+            let mut error = None;
+            for (_, pubkey) in keypairs {
+                match pubkey {
+                    Ok(pubkey) => pubkeys.push(pubkey),
+                    Err(err) => error = error.or(Some(err)),
+                }
+            }
+            let pubkeys = match error {
+                Some(err) if pubkeys.is_empty() => Err(err),
+                _ => Ok(pubkeys),
+            };
+            (program, pubkeys)
+        })
+        .collect())
+}
+
+/// Splits the name of a keypair file, without the `.json` (and `.x`)
+/// suffix, into its program and the version it is for. Per-major keypairs
+/// are for version `<major>.0.0`.
+fn split_keypair_name(name: &str, scheme: KeypairScheme) -> (String, Option<Version>) {
+    let version = |suffix: &str| match scheme {
+        KeypairScheme::PerMajor => suffix.parse().ok().map(|major| Version::new(major, 0, 0)),
+        KeypairScheme::PerVersion => Version::parse(suffix).ok(),
+        KeypairScheme::PerProgram => None,
+    };
+    name.match_indices('-')
+        .find_map(|(i, _)| version(&name[i + 1..]).map(|v| (name[..i].to_string(), Some(v))))
+        .unwrap_or_else(|| (name.to_string(), None))
+}
+
+/// Fetches the state of a program on a network. Errors, including those
+/// reading its keypairs, are recorded in the returned status rather than
+/// returned, so one unreachable network doesn't hide the others.
+pub fn fetch(
+    config: &Config,
+    root: &Path,
+    program: &str,
+    program_ids: &Result<Vec<Pubkey>>,
+    network: &Network,
+) -> ProgramStatus {
+    let mut status = ProgramStatus {
+        program: program.to_string(),
+        network: network.clone(),
+        program_id: None,
+        deployed: false,
+        version: None,
+        upgrade_authority: None,
+        authority_matches: None,
+        error: None,
+    };
+    let result = match program_ids {
+        Ok(program_ids) => {
+            status.program_id = program_ids.first().map(|id| id.to_string());
+            fill(&mut status, config, root, program_ids, network)
+        }
+        Err(err) => Err(format_err!("{}", err)),
+    };
+    if let Err(err) = result {
+        status.error = Some(err.to_string());
+    }
+    status
+}
+
+fn fill(
+    status: &mut ProgramStatus,
+    config: &Config,
+    root: &Path,
+    program_ids: &[Pubkey],
+    network: &Network,
+) -> Result<()> {
+    let network_config = config.network_config(network)?;
+    let client = RpcClient::new(&network_config.network_url(network))?
+        .with_commitment(config.deploy_config(network)?.commitment());
+    let offset = UpgradeableLoaderState::programdata_data_offset()?;
+    for program_id in program_ids {
+        let (programdata, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        let data = match client.get_account_data(&programdata)? {
+            Some(data) if data.len() >= offset => data,
+            _ => continue,
+        };
+        let authority = match bincode::deserialize(&data[..offset]) {
+            Ok(UpgradeableLoaderState::ProgramData {
+                upgrade_authority_address,
+                ..
+            }) => upgrade_authority_address,
+            _ => {
+                return Err(format_err!(
+                    "could not decode program data of {}",
+                    program_id
+                ))
+            }
+        };
+        status.program_id = Some(program_id.to_string());
+        status.deployed = true;
+        status.upgrade_authority = authority.map(|a| a.to_string());
+        status.authority_matches = network_config
+            .upgrade_authority_pubkey(&config.tools.solana())
+            .ok()
+            .map(|expected| authority == Some(expected));
+        status.version = artifacts::onchain_version(config, root, &status.program, network)?;
+        break;
+    }
+    Ok(())
+}
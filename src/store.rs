@@ -0,0 +1,212 @@
+//! Where released artifacts are kept: the local artifacts directory, or a
+//! remote store shared across machines.
+use crate::config::ArtifactPaths;
+use crate::signing::signature_path;
+use crate::workspace::verifiable_hash_path;
+use anyhow::{anyhow, format_err, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Stores the artifacts of released program versions.
+///
+/// Deploys always read artifacts from their local [ArtifactPaths], so remote
+/// stores keep a local copy and download to it with [ArtifactStore::get].
+pub trait ArtifactStore {
+    /// Stores a program binary, and its verifiable build hash if it has one.
+    fn put_bin(&self, bin: &Path, paths: &ArtifactPaths) -> Result<()>;
+    /// Stores a program IDL.
+    fn put_idl(&self, idl: &Path, paths: &ArtifactPaths) -> Result<()>;
+    /// Returns true if any artifact of the version is stored.
+    fn exists(&self, paths: &ArtifactPaths) -> Result<bool>;
+    /// Makes the stored artifacts of the version available at their local
+    /// paths.
+    fn get(&self, paths: &ArtifactPaths) -> Result<()>;
+}
+
+/// Keeps artifacts in the local artifacts directory.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalStore;
+
+impl ArtifactStore for LocalStore {
+    fn put_bin(&self, bin: &Path, paths: &ArtifactPaths) -> Result<()> {
+        copy(bin, &paths.bin)?;
+        let hash_path = verifiable_hash_path(bin);
+        if hash_path.exists() {
+            copy(&hash_path, &verifiable_hash_path(&paths.bin))?;
+        }
+        let signature_path = signature_path(bin);
+        if signature_path.exists() {
+            copy(&signature_path, &self::signature_path(&paths.bin))?;
+        }
+        Ok(())
+    }
+
+    fn put_idl(&self, idl: &Path, paths: &ArtifactPaths) -> Result<()> {
+        copy(idl, &paths.idl)
+    }
+
+    fn exists(&self, paths: &ArtifactPaths) -> Result<bool> {
+        Ok(paths.exist())
+    }
+
+    fn get(&self, _paths: &ArtifactPaths) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(from, to).map_err(|e| {
+        format_err!(
+            "could not copy {} to {}: {}",
+            from.display(),
+            to.display(),
+            e
+        )
+    })?;
+    Ok(())
+}
+
+/// Opens the store at an artifacts URL, keeping local copies of its
+/// artifacts under `artifacts_dir`.
+pub fn from_url(url: &str, artifacts_dir: &Path, aws: PathBuf) -> Result<Box<dyn ArtifactStore>> {
+    if url.starts_with("s3://") {
+        return s3_store(url, artifacts_dir, aws);
+    }
+    Err(anyhow!(
+        "Unsupported artifacts URL {}. Expected s3://<bucket>/<prefix>.",
+        url
+    ))
+}
+
+#[cfg(feature = "s3")]
+fn s3_store(url: &str, artifacts_dir: &Path, aws: PathBuf) -> Result<Box<dyn ArtifactStore>> {
+    Ok(Box::new(s3::S3Store {
+        url: url.trim_end_matches('/').to_string(),
+        artifacts_dir: artifacts_dir.to_path_buf(),
+        aws,
+    }))
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_store(url: &str, _artifacts_dir: &Path, _aws: PathBuf) -> Result<Box<dyn ArtifactStore>> {
+    Err(anyhow!(
+        "Storing artifacts at {} requires S3 support. Reinstall captain with `--features s3`.",
+        url
+    ))
+}
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use super::{ArtifactStore, LocalStore};
+    use crate::command;
+    use crate::config::ArtifactPaths;
+    use crate::signing::signature_path;
+    use crate::workspace::verifiable_hash_path;
+    use anyhow::{format_err, Result};
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    /// Keeps artifacts in an S3 bucket with the aws CLI, mirroring the
+    /// artifact layout under the URL's prefix.
+    ///
+    /// Artifacts are also copied to the local artifacts directory, which acts
+    /// as a cache of the bucket.
+    #[derive(Clone, Debug)]
+    pub struct S3Store {
+        /// `s3://<bucket>/<prefix>`, without a trailing slash.
+        pub url: String,
+        pub artifacts_dir: PathBuf,
+        pub aws: PathBuf,
+    }
+
+    impl S3Store {
+        /// The S3 URL of a local artifact path.
+        fn key(&self, path: &Path) -> Result<String> {
+            let relative = path.strip_prefix(&self.artifacts_dir).map_err(|_| {
+                format_err!(
+                    "{} is not under {}",
+                    path.display(),
+                    self.artifacts_dir.display()
+                )
+            })?;
+            let components: Vec<_> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect();
+            Ok(format!("{}/{}", self.url, components.join("/")))
+        }
+
+        fn upload(&self, path: &Path) -> Result<()> {
+            command::exec(
+                Command::new(&self.aws)
+                    .arg("s3")
+                    .arg("cp")
+                    .arg("--only-show-errors")
+                    .arg(path)
+                    .arg(self.key(path)?),
+            )?;
+            Ok(())
+        }
+    }
+
+    impl ArtifactStore for S3Store {
+        fn put_bin(&self, bin: &Path, paths: &ArtifactPaths) -> Result<()> {
+            LocalStore.put_bin(bin, paths)?;
+            self.upload(&paths.bin)?;
+            let hash_path = verifiable_hash_path(&paths.bin);
+            if hash_path.exists() {
+                self.upload(&hash_path)?;
+            }
+            let signature_path = signature_path(&paths.bin);
+            if signature_path.exists() {
+                self.upload(&signature_path)?;
+            }
+            Ok(())
+        }
+
+        fn put_idl(&self, idl: &Path, paths: &ArtifactPaths) -> Result<()> {
+            LocalStore.put_idl(idl, paths)?;
+            self.upload(&paths.idl)
+        }
+
+        fn exists(&self, paths: &ArtifactPaths) -> Result<bool> {
+            let mut cmd = Command::new(&self.aws);
+            cmd.arg("s3")
+                .arg("ls")
+                .arg(format!("{}/", self.key(&paths.root)?));
+            command::echo(&cmd);
+            let output = cmd
+                .output()
+                .map_err(|e| format_err!("could not run {}: {}", self.aws.display(), e))?;
+            // `aws s3 ls` exits with 1 and no output if nothing matches
+            match output.status.code() {
+                Some(0) => Ok(!output.stdout.is_empty()),
+                Some(1) if output.stderr.is_empty() => Ok(false),
+                _ => Err(format_err!(
+                    "could not list {}: {}",
+                    self.key(&paths.root)?,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )),
+            }
+        }
+
+        fn get(&self, paths: &ArtifactPaths) -> Result<()> {
+            if paths.exist() {
+                return Ok(());
+            }
+            command::exec(
+                Command::new(&self.aws)
+                    .arg("s3")
+                    .arg("cp")
+                    .arg("--recursive")
+                    .arg("--only-show-errors")
+                    .arg(format!("{}/", self.key(&paths.root)?))
+                    .arg(&paths.root),
+            )?;
+            Ok(())
+        }
+    }
+}
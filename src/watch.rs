@@ -0,0 +1,134 @@
+//! Redeploys a program to a development cluster whenever it is rebuilt.
+use crate::config::Network;
+use crate::output::Format;
+use crate::progress::Progress;
+use crate::receipt::{Action, Receipt};
+use crate::workspace::{self, DeployOptions, LoadOptions, UpgradeOptions};
+use anyhow::{anyhow, format_err, Result};
+use colored::*;
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, DebouncedEventKind};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, SystemTime};
+
+/// How long the binary must stay unchanged before it is deployed, so a build
+/// that is still writing it isn't picked up halfway.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Watches `target/deploy/<program>.so` and deploys it whenever it changes,
+/// upgrading the program if it already exists. Runs until interrupted.
+/// `load_options` are used for every deployment, with the watched binary.
+///
+/// Only localnet and devnet are allowed, so a rebuild can never redeploy a
+/// mainnet program. The deployer is kept as the upgrade authority, so
+/// redeploys don't need the network's upgrade authority signer.
+pub fn run(
+    program: &str,
+    network: Network,
+    load_options: &LoadOptions,
+    quiet: bool,
+    format: Format,
+) -> Result<()> {
+    if network != Network::Localnet && network != Network::Devnet {
+        return Err(anyhow!(
+            "captain watch only deploys to localnet or devnet, not {}",
+            network
+        ));
+    }
+    let (_, _, root) = load_options.discover()?;
+    let deploy_dir = root.join("target").join("deploy");
+    // the binary may not be built yet, in which case the program's own name
+    // is watched
+    let bin = workspace::find_target_output(&deploy_dir, program, "so")
+        .unwrap_or_else(|_| deploy_dir.join(format!("{}.so", program)));
+    let load_options = LoadOptions {
+        bin: Some(bin.clone()),
+        ..load_options.clone()
+    };
+
+    // builds may replace the binary rather than write to it, so its
+    // directory is watched
+    fs::create_dir_all(&deploy_dir)?;
+    let (events, changes) = mpsc::channel();
+    let mut debouncer = new_debouncer(DEBOUNCE, events)
+        .map_err(|e| format_err!("could not watch {}: {}", deploy_dir.display(), e))?;
+    debouncer
+        .watcher()
+        .watch(&deploy_dir, RecursiveMode::NonRecursive)
+        .map_err(|e| format_err!("could not watch {}: {}", deploy_dir.display(), e))?;
+
+    let mut deployed = None;
+    loop {
+        let modified = wait_for_change(&bin, deployed, &changes)?;
+        info!(
+            "{}",
+            format!("{} changed, deploying to {}", bin.display(), network).bold()
+        );
+        match redeploy(program, &network, &load_options, quiet) {
+            Ok(receipt) => println!("{}", format.render(&receipt)?),
+            Err(err) => eprintln!("{}", format!("Deployment failed: {}", err).red()),
+        }
+        deployed = Some(modified);
+        info!("Watching {} for changes...", bin.display());
+    }
+}
+
+/// Deploys the program, or upgrades it if it was already deployed.
+fn redeploy(
+    program: &str,
+    network: &Network,
+    load_options: &LoadOptions,
+    quiet: bool,
+) -> Result<Receipt> {
+    let workspace = workspace::load(program, network.clone(), load_options)?;
+    let receipt = workspace.deploy(
+        &mut Progress::new(quiet),
+        &DeployOptions {
+            skip_anchor_idl: true,
+            no_set_authority: true,
+            ..Default::default()
+        },
+    )?;
+    if receipt.action != Action::AlreadyDeployed {
+        return Ok(receipt);
+    }
+    workspace.upgrade(
+        &mut Progress::new(quiet),
+        &UpgradeOptions {
+            skip_anchor_idl: true,
+            upgrade_authority_keypair: Some(workspace.deployer_path.clone()),
+            ..Default::default()
+        },
+    )
+}
+
+/// Blocks until the binary exists with a modification time other than
+/// `last`, and returns it. Unless it already does, this waits for a change
+/// to the binary that has been stable for [DEBOUNCE].
+fn wait_for_change(
+    bin: &Path,
+    last: Option<SystemTime>,
+    changes: &Receiver<DebounceEventResult>,
+) -> Result<SystemTime> {
+    loop {
+        if let Ok(modified) = fs::metadata(bin).and_then(|m| m.modified()) {
+            if Some(modified) != last {
+                return Ok(modified);
+            }
+        }
+        // continuous events mean the binary is still being written to
+        let mut settled = false;
+        while !settled {
+            settled = changes
+                .recv()?
+                .map_err(|e| format_err!("could not watch {}: {}", bin.display(), e))?
+                .iter()
+                .any(|event| {
+                    event.kind == DebouncedEventKind::Any
+                        && event.path.file_name() == bin.file_name()
+                });
+        }
+    }
+}
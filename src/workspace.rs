@@ -1,19 +1,52 @@
+use crate::clean;
 use crate::command;
+use crate::command::{CommandRunner, RetryPolicy, SystemRunner};
 use crate::config::ArtifactPaths;
+use crate::config::Commitment;
+use crate::config::DeployConfig;
 use crate::config::NetworkConfig;
+use crate::error::CaptainError;
+use crate::hooks;
+use crate::idl;
+use crate::interrupt::{self, BufferGuard};
+use crate::keypair::{self, SignerSource};
+use crate::lock::DeployLock;
+use crate::progress::{format_duration, Progress};
+use crate::receipt::{Action, Receipt};
+use crate::rpc::{RpcClient, Simulation};
+use crate::signing;
+use crate::state::{DeployState, DeployStep, PendingBuffer};
+use crate::store::{ArtifactStore, LocalStore};
 use crate::Config;
 use crate::Network;
 use anyhow::{anyhow, format_err, Result};
 use cargo_toml::Manifest;
+use colored::*;
+use reqwest::Url;
 use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use solana_sdk::message::Message;
+use solana_sdk::native_token::lamports_to_sol;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::Signer;
+use solana_sdk::signature::{Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use std::env;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::SystemTime;
+use std::time::{Duration, Instant};
+use strum_macros::{Display, EnumString, EnumVariantNames, IntoStaticStr};
+use tempfile::NamedTempFile;
 
 /// Deploys a program.
 pub struct Workspace {
+    pub program: String,
     pub root: PathBuf,
     pub network: Network,
     pub deployer_path: PathBuf,
@@ -22,40 +55,287 @@ pub struct Workspace {
     pub config: Config,
     pub network_config: NetworkConfig,
     pub artifact_paths: ArtifactPaths,
+    /// Where artifacts are released to and fetched from.
+    pub artifact_store: Box<dyn ArtifactStore>,
+    /// The program binary written on-chain by deploy and upgrade.
+    pub deploy_bin: PathBuf,
     pub program_key: Pubkey,
+    /// Plaintext copy of an encrypted program keypair, deleted on drop.
+    /// `program_paths.id` points at it while the workspace is alive.
+    pub decrypted_program_keypair: Option<NamedTempFile>,
+    /// Copy of a deployer keypair read from stdin, deleted on drop.
+    /// `deployer_path` points at it while the workspace is alive.
+    pub stdin_deployer_keypair: Option<NamedTempFile>,
+    /// Runs the solana and anchor commands of deploys and upgrades.
+    pub runner: Box<dyn CommandRunner>,
+}
+
+/// On-chain state of a deployed program, as reported by
+/// `solana program show --output json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramInfo {
+    /// Bytes available for the program binary.
+    pub data_len: usize,
+}
+
+/// On-chain state of a program buffer, as reported by
+/// `solana program show <buffer> --output json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BufferInfo {
+    pub authority: String,
+    pub data_len: usize,
+}
+
+/// Rent of an account, as reported by `solana rent --output json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RentInfo {
+    pub rent_exempt_minimum_lamports: u64,
 }
 
 pub struct ProgramPaths {
     pub bin: PathBuf,
-    pub idl: PathBuf,
-    pub id: PathBuf,
+    /// The Anchor IDL. Not set for programs without one, e.g. native
+    /// programs, in which case the IDL steps are skipped.
+    pub idl: Option<PathBuf>,
+    /// The program keypair. Not set when the program ID is read from
+    /// Anchor.toml, in which case the program can only be upgraded.
+    pub id: Option<PathBuf>,
 }
 
-pub fn load(program: &str, version: Option<Version>, network: Network) -> Result<Workspace> {
-    let (config, _, root) = Config::discover()?;
+/// Where the version of a program is read from.
+#[derive(
+    Clone, Copy, Debug, Display, EnumString, EnumVariantNames, Eq, IntoStaticStr, PartialEq,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum VersionSource {
+    /// The `version` of the program's Cargo.toml.
+    Cargo,
+    /// A `version` key on the program's entry in Anchor.toml.
+    Anchor,
+    /// The most recent git tag, e.g. `v1.2.3`.
+    GitTag,
+}
 
-    let deploy_version = get_deploy_version(program, &root, version)?;
-    let program_paths = check_and_get_program_paths(&config, program, &root, &deploy_version)?;
+/// Where the address of a program is read from.
+#[derive(
+    Clone, Copy, Debug, Display, EnumString, EnumVariantNames, Eq, IntoStaticStr, PartialEq,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ProgramIdSource {
+    /// The program keypair in the program keypairs directory.
+    Keypair,
+    /// The program's address under `[programs.<network>]` in Anchor.toml.
+    Anchor,
+}
 
-    let network_config = config.network_config(&network)?;
-    let deployer_path = network_config.deployer.as_path_buf();
-    if !deployer_path.exists() {
+/// Options for loading a [Workspace].
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// Version to use instead of resolving it from `version_from`.
+    pub version: Option<Version>,
+    pub version_from: VersionSource,
+    /// Deploy this binary instead of the archived artifact.
+    pub bin: Option<PathBuf>,
+    /// Use this IDL instead of `target/idl/<program>.json`.
+    pub idl: Option<PathBuf>,
+    /// Expected hex-encoded SHA-256 of the binary being deployed.
+    pub expect_sha256: Option<String>,
+    /// Expected address of the program. Overrides the program's config.
+    pub expect_program_id: Option<Pubkey>,
+    pub program_id_source: ProgramIdSource,
+    /// Program keypair to use instead of the one in the program keypairs
+    /// directory.
+    pub program_keypair: Option<PathBuf>,
+    /// Address of a program that isn't built in this workspace. Nothing is
+    /// derived from the program name, so `version` and `bin` are required.
+    pub program_id: Option<Pubkey>,
+    /// Read and write artifacts here instead of the configured directory.
+    pub artifacts_dir: Option<PathBuf>,
+    /// Read the deployer keypair from stdin instead of the network's config.
+    pub deployer_stdin: bool,
+    /// Build the IDL with `anchor idl build` if the default IDL is missing,
+    /// e.g. with Anchor versions that embed it instead of writing it out.
+    pub idl_build: bool,
+    /// Directory containing the program crates, relative to the workspace
+    /// root, instead of the config's `program_dir`.
+    pub programs_dir: Option<PathBuf>,
+    /// Read shared network definitions from this file instead of the
+    /// config's `networks_include`.
+    pub network_config_file: Option<PathBuf>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            version: None,
+            version_from: VersionSource::Cargo,
+            bin: None,
+            idl: None,
+            expect_sha256: None,
+            expect_program_id: None,
+            program_id_source: ProgramIdSource::Keypair,
+            program_keypair: None,
+            program_id: None,
+            artifacts_dir: None,
+            deployer_stdin: false,
+            idl_build: false,
+            programs_dir: None,
+            network_config_file: None,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Discovers the workspace's config, with the overrides of these options
+    /// applied.
+    pub fn discover(&self) -> Result<(Config, Manifest, PathBuf)> {
+        let (mut config, manifest, root) =
+            Config::discover_with(self.network_config_file.as_deref())?;
+        if let Some(dir) = &self.programs_dir {
+            config.paths.program_dir = Some(dir.clone());
+        }
+        Ok((config, manifest, root))
+    }
+}
+
+pub fn load(program: &str, network: Network, options: &LoadOptions) -> Result<Workspace> {
+    let (config, _, root) = options.discover()?;
+
+    if options.program_id.is_some() && options.version.is_none() {
         return Err(anyhow!(
-            "Deployer path {} does not exist",
-            deployer_path.display()
+            "Loading a program by its address requires a version"
         ));
     }
+    let deploy_version = get_deploy_version(
+        program,
+        &root,
+        &config.programs_dir(&root),
+        options.version.clone(),
+        options.version_from,
+    )?;
+    let mut program_paths =
+        check_and_get_program_paths(&config, program, &root, &deploy_version, options)?;
+
+    let network_config = config.network_config(&network)?;
+    let stdin_deployer_keypair = if options.deployer_stdin || network_config.deployer_from_stdin() {
+        Some(keypair::stdin_to_temp_file()?)
+    } else {
+        None
+    };
+    let deployer_path = match &stdin_deployer_keypair {
+        Some(file) => file.path().to_path_buf(),
+        None => {
+            let deployer = network_config.deployer_path(&network)?;
+            match SignerSource::from_path(&deployer) {
+                SignerSource::File(path) if !path.exists() => {
+                    return Err(CaptainError::KeypairNotFound {
+                        role: "Deployer",
+                        path,
+                    }
+                    .into());
+                }
+                SignerSource::File(path) => path,
+                // signer URIs such as `usb://ledger` have no file to check
+                _ => deployer,
+            }
+        }
+    };
 
-    let artifact_paths = config.artifact_paths(&deploy_version, program);
+    let artifact_paths = match &options.artifacts_dir {
+        Some(artifacts_dir) => ArtifactPaths::new(
+            artifacts_dir,
+            config.paths.artifact_layout(),
+            &network,
+            &deploy_version,
+            program,
+        ),
+        None => config.artifact_paths(&network, &deploy_version, program),
+    };
     fs::create_dir_all(&artifact_paths.root)?;
+    // an artifacts directory override is always local
+    let artifact_store: Box<dyn ArtifactStore> = match &options.artifacts_dir {
+        Some(_) => Box::new(LocalStore),
+        None => config.artifact_store()?,
+    };
 
-    // TODO(igm): allow specifying pubkey without requiring the keyfile
-    let program_id_path_display = program_paths.id.display();
-    let program_key = solana_sdk::signer::keypair::read_keypair_file(&program_paths.id)
-        .map_err(|_| format_err!("could not read kp file {}", program_id_path_display))?
-        .pubkey();
+    let deploy_bin = match &options.bin {
+        Some(_) => program_paths.bin.clone(),
+        None => {
+            artifact_store.get(&artifact_paths)?;
+            artifact_paths.bin.clone()
+        }
+    };
+    if let Some(expected) = &options.expect_sha256 {
+        let actual = sha256_file(&deploy_bin)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(anyhow!(
+                "SHA-256 of {} is {}, expected {}",
+                deploy_bin.display(),
+                actual,
+                expected
+            ));
+        }
+    }
+
+    let program_kp_path = program_paths.id.clone();
+    let decrypted_program_keypair = match &program_paths.id {
+        Some(id) if keypair::is_encrypted(id)? => Some(keypair::decrypt_to_temp_file(id)?),
+        _ => None,
+    };
+    if let Some(decrypted) = &decrypted_program_keypair {
+        program_paths.id = Some(decrypted.path().to_path_buf());
+    }
+    let program_key = match (options.program_id, &program_paths.id) {
+        (Some(program_id), _) => program_id,
+        (None, Some(id)) => keypair::read_keypair(id)?.pubkey(),
+        (None, None) => get_anchor_program_id(program, &root, &network)?,
+    };
+    if let Some(path) = &options.program_keypair {
+        // a keypair outside the workspace, e.g. on a vault mount, could be
+        // swapped while it is in use
+        let reread = keypair::read_keypair(path)?.pubkey();
+        if reread != program_key {
+            return Err(anyhow!(
+                "Program keypair {} changed while it was read: {} then {}",
+                path.display(),
+                program_key,
+                reread
+            ));
+        }
+    }
+    let expected_program_id = match options.expect_program_id {
+        Some(expected) => Some(expected),
+        None => config
+            .program_config(program)
+            .expected_program_id
+            .map(|expected| {
+                Pubkey::from_str(&expected)
+                    .map_err(|_| format_err!("invalid expected_program_id {}", expected))
+            })
+            .transpose()?,
+    };
+    if let Some(expected) = expected_program_id {
+        if program_key != expected {
+            return Err(anyhow!(
+                "Program address is {}, expected {}. Check that {} is the right keypair.",
+                program_key,
+                expected,
+                program_kp_path
+                    .as_ref()
+                    .map(|id| id.display().to_string())
+                    .unwrap_or_else(|| "Anchor.toml".to_string())
+            ));
+        }
+    }
+    if options.program_id.is_none() {
+        check_declared_program_id(program, &root, &config, &deploy_version, &program_key)?;
+    }
 
     Ok(Workspace {
+        program: program.to_string(),
         config: config.clone(),
         network,
         root,
@@ -64,87 +344,2181 @@ pub fn load(program: &str, version: Option<Version>, network: Network) -> Result
         deploy_version,
         program_paths,
         artifact_paths,
+        artifact_store,
+        deploy_bin,
         program_key,
+        decrypted_program_keypair,
+        stdin_deployer_keypair,
+        runner: Box::new(SystemRunner),
     })
 }
 
+/// Deploys a program and then each of its `companions`, at the same version,
+/// returning the program's receipt with the companions' receipts in it.
+///
+/// Artifact overrides in `load_options` only apply to the program itself.
+/// If a companion fails, the programs deployed before it are left deployed,
+/// since a closed program's address can't be deployed to again; deploying
+/// again skips them as already deployed.
+pub fn deploy_with_companions(
+    program: &str,
+    network: &Network,
+    load_options: &LoadOptions,
+    options: &DeployOptions,
+    quiet: bool,
+) -> Result<Receipt> {
+    let workspace = load(program, network.clone(), load_options)?;
+    let companions = workspace.config.program_config(program).companions;
+    let mut receipt = workspace.deploy(&mut Progress::new(quiet), options)?;
+    let companion_options = LoadOptions {
+        version: Some(receipt.version.clone()),
+        bin: None,
+        idl: None,
+        expect_sha256: None,
+        expect_program_id: None,
+        program_keypair: None,
+        ..load_options.clone()
+    };
+    for companion in &companions {
+        info!(
+            "{}",
+            format!("Deploying {}, a companion of {}", companion, program).bold()
+        );
+        let deployed = load(companion, network.clone(), &companion_options)
+            .and_then(|workspace| workspace.deploy(&mut Progress::new(quiet), options));
+        match deployed {
+            Ok(companion_receipt) => receipt.companions.push(companion_receipt),
+            Err(err) => {
+                let done: Vec<&str> = std::iter::once(program)
+                    .chain(receipt.companions.iter().map(|r| r.program.as_str()))
+                    .collect();
+                return Err(format_err!(
+                    "Deploying {}, a companion of {}, failed: {}. Already deployed: {}. Deploy {} again to retry.",
+                    companion,
+                    program,
+                    err,
+                    done.join(", "),
+                    program
+                ));
+            }
+        }
+    }
+    Ok(receipt)
+}
+
 fn check_and_get_program_paths(
     config: &Config,
     program: &str,
     root: &Path,
     deploy_version: &Version,
+    options: &LoadOptions,
 ) -> Result<ProgramPaths> {
-    let program_bin_path = root
-        .join("target")
-        .join("deploy")
-        .join(format!("{}.so", program));
-    let program_idl_path = root
-        .join("target")
-        .join("idl")
-        .join(format!("{}.json", program));
-    let program_id_path = config.program_kp_path(deploy_version, program);
+    if options.program_id.is_some() {
+        return check_and_get_external_program_paths(options);
+    }
+    let program_bin_path = match &options.bin {
+        Some(bin) => bin.clone(),
+        None => find_target_output(&root.join("target").join("deploy"), program, "so")?,
+    };
+    let configured_idl_path = options.idl.clone().or_else(|| {
+        config
+            .program_config(program)
+            .idl_path
+            .map(|idl| root.join(idl))
+    });
+    let program_idl_path = match &configured_idl_path {
+        Some(idl) => idl.clone(),
+        None => {
+            let idl_dir = root.join("target").join("idl");
+            find_target_output(&idl_dir, program, "json")
+                .unwrap_or_else(|_| idl_dir.join(format!("{}.json", program)))
+        }
+    };
+    let program_id_path = match (&options.program_keypair, options.program_id_source) {
+        (Some(path), ProgramIdSource::Keypair) => Some(path.clone()),
+        (None, ProgramIdSource::Keypair) => Some(config.program_kp_path(deploy_version, program)),
+        (Some(_), ProgramIdSource::Anchor) => {
+            return Err(anyhow!(
+                "--program-keypair can't be used with --program-id-source anchor"
+            ))
+        }
+        (None, ProgramIdSource::Anchor) => None,
+    };
 
     if !program_bin_path.exists() {
-        return Err(anyhow!(
-            "Program bin path {} does not exist",
-            program_bin_path.display()
-        ));
+        return Err(CaptainError::ProgramBinNotFound(program_bin_path).into());
     }
-    if !program_idl_path.exists() {
-        return Err(anyhow!(
-            "Program idl path {} does not exist",
-            program_idl_path.display()
-        ));
+    if configured_idl_path.is_none()
+        && !program_idl_path.exists()
+        && options.idl_build
+        && root.join("Anchor.toml").exists()
+    {
+        build_idl(config, program, root, &program_idl_path)?;
     }
-    if !program_id_path.exists() {
-        return Err(anyhow!(
-            "Program id path {} does not exist",
-            program_id_path.display()
-        ));
+    // a configured IDL must exist, but programs may have no IDL at all
+    if configured_idl_path.is_some() && !program_idl_path.exists() {
+        return Err(CaptainError::IdlNotFound(program_idl_path).into());
+    }
+    if let Some(program_id_path) = &program_id_path {
+        if !program_id_path.exists() {
+            return Err(CaptainError::KeypairNotFound {
+                role: "Program id",
+                path: program_id_path.clone(),
+            }
+            .into());
+        }
     }
 
     Ok(ProgramPaths {
         bin: program_bin_path,
-        idl: program_idl_path,
+        idl: Some(program_idl_path).filter(|idl| idl.exists()),
         id: program_id_path,
     })
 }
 
-pub fn get_program_version(program: &str, root: &Path) -> Result<Version> {
-    let mf_path = &root.join("programs").join(program).join("Cargo.toml");
-    let program_manifest_path = if mf_path.exists() {
-        mf_path.clone()
-    } else {
-        root.join("programs")
-            .join(&program.replace("_", "-"))
-            .join("Cargo.toml")
+/// Paths of a program loaded by its address, which come only from the
+/// overrides in `options`.
+fn check_and_get_external_program_paths(options: &LoadOptions) -> Result<ProgramPaths> {
+    let program_bin_path = options
+        .bin
+        .clone()
+        .ok_or_else(|| anyhow!("Loading a program by its address requires its binary"))?;
+    if options.program_keypair.is_some() {
+        return Err(anyhow!("--program-keypair can't be used with --program-id"));
+    }
+    if !program_bin_path.exists() {
+        return Err(CaptainError::ProgramBinNotFound(program_bin_path).into());
+    }
+    if let Some(idl) = &options.idl {
+        if !idl.exists() {
+            return Err(CaptainError::IdlNotFound(idl.clone()).into());
+        }
+    }
+    Ok(ProgramPaths {
+        bin: program_bin_path,
+        idl: options.idl.clone(),
+        id: None,
+    })
+}
+
+/// Resolves the address of a keypair file or signer URI.
+fn signer_pubkey(signer: &Path, solana: &Path) -> Result<Pubkey> {
+    match SignerSource::from_path(signer) {
+        SignerSource::File(path) => Ok(solana_sdk::signer::keypair::read_keypair_file(&path)
+            .map_err(|_| format_err!("could not read kp file {}", path.display()))?
+            .pubkey()),
+        source => source.pubkey(solana),
+    }
+}
+
+/// Writes a program's IDL to `out` with `anchor idl build`.
+fn build_idl(config: &Config, program: &str, root: &Path, out: &Path) -> Result<()> {
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    info!(
+        "{} not found. Building it with `anchor idl build`.",
+        out.display()
+    );
+    command::exec(
+        Command::new(config.tools.anchor())
+            .current_dir(root)
+            .arg("idl")
+            .arg("build")
+            .arg("--program-name")
+            .arg(program)
+            .arg("--out")
+            .arg(out),
+    )
+    .map_err(|e| format_err!("could not build the IDL of {}: {}", program, e))?;
+    Ok(())
+}
+
+/// Computes the hex-encoded SHA-256 digest of a file.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let contents =
+        fs::read(path).map_err(|e| format_err!("could not read {}: {}", path.display(), e))?;
+    Ok(sha256_hex(&contents))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    solana_sdk::hash::hash(data)
+        .to_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hashes a program the way verifiable builds do: the SHA-256 of the
+/// executable without trailing zeros, so a binary and the zero-padded program
+/// data it was deployed to hash the same.
+pub fn executable_hash(executable: &[u8]) -> String {
+    let len = executable
+        .iter()
+        .rposition(|b| *b != 0)
+        .map_or(0, |i| i + 1);
+    sha256_hex(&executable[..len])
+}
+
+/// Where the verifiable build hash of a binary is stored, e.g.
+/// `target/deploy/my_program.so.hash`.
+pub fn verifiable_hash_path(bin: &Path) -> PathBuf {
+    let mut path = bin.as_os_str().to_os_string();
+    path.push(".hash");
+    PathBuf::from(path)
+}
+
+/// Finds the most recently modified file under a directory.
+fn newest_file(dir: &Path) -> Result<Option<(PathBuf, SystemTime)>> {
+    let mut newest: Option<(PathBuf, SystemTime)> = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let candidate = if path.is_dir() {
+            newest_file(&path)?
+        } else {
+            Some((path.clone(), fs::metadata(&path)?.modified()?))
+        };
+        match (&newest, candidate) {
+            (Some((_, newest_modified)), Some((_, modified))) if modified <= *newest_modified => {}
+            (_, Some(candidate)) => newest = Some(candidate),
+            (_, None) => {}
+        }
+    }
+    Ok(newest)
+}
+
+/// Finds a build output of a program in `dir`, named `<program>.<extension>`
+/// or the same with dashes and underscores swapped, since Anchor may name
+/// outputs after the crate rather than the program.
+///
+/// Errors with every path tried if none of them exists.
+pub fn find_target_output(dir: &Path, program: &str, extension: &str) -> Result<PathBuf> {
+    let mut candidates = vec![dir.join(format!("{}.{}", program, extension))];
+    for name in &[program.replace("-", "_"), program.replace("_", "-")] {
+        let candidate = dir.join(format!("{}.{}", name, extension));
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+    if let Some(path) = candidates.iter().find(|path| path.exists()) {
+        return Ok(path.clone());
+    }
+    let tried: Vec<String> = candidates
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    Err(format_err!(
+        "{}.{} not found at paths {}",
+        program,
+        extension,
+        tried.join(", ")
+    ))
+}
+
+/// Finds the crate of a program: `<programs_dir>/<program>`, the same with
+/// dashes instead of underscores, or the workspace root if its package is the
+/// program, for repos that don't nest programs.
+///
+/// Errors with every path tried if none of them is the program's crate.
+pub fn find_program_crate(program: &str, root: &Path, programs_dir: &Path) -> Result<PathBuf> {
+    let mut candidates = vec![programs_dir.join(program)];
+    if program.contains('_') {
+        candidates.push(programs_dir.join(program.replace("_", "-")));
+    }
+    if let Some(dir) = candidates
+        .iter()
+        .find(|dir| dir.join("Cargo.toml").exists())
+    {
+        return Ok(dir.clone());
+    }
+    let root_manifest = root.join("Cargo.toml");
+    let is_root_package = read_cargo_toml(&root_manifest)
+        .ok()
+        .and_then(|manifest| {
+            manifest
+                .get("package")
+                .and_then(|package| package.get("name"))
+                .and_then(|name| name.as_str())
+                .map(|name| name.replace("-", "_") == program.replace("-", "_"))
+        })
+        .unwrap_or(false);
+    if is_root_package {
+        return Ok(root.to_path_buf());
+    }
+    let tried: Vec<String> = candidates
+        .iter()
+        .map(|dir| dir.join("Cargo.toml"))
+        .chain(std::iter::once(root_manifest))
+        .map(|path| path.display().to_string())
+        .collect();
+    Err(format_err!(
+        "Program Cargo.toml not found at paths {}. Programs are looked up in {}; \
+         set program_dir under [paths] or pass --programs-dir if they live elsewhere.",
+        tried.join(", "),
+        programs_dir.display()
+    ))
+}
+
+/// Reads the version of a program from its Cargo.toml, resolving
+/// `version.workspace = true` from the Cargo workspace's root.
+pub fn get_program_version(program: &str, root: &Path, programs_dir: &Path) -> Result<Version> {
+    let crate_dir = find_program_crate(program, root, programs_dir)?;
+    let manifest_path = crate_dir.join("Cargo.toml");
+    let version = read_cargo_toml(&manifest_path)?
+        .get("package")
+        .and_then(|package| package.get("version"))
+        .cloned()
+        .ok_or_else(|| format_err!("{} has no package version", manifest_path.display()))?;
+    let version = match version {
+        toml::Value::String(version) => version,
+        toml::Value::Table(version)
+            if version.get("workspace").and_then(|w| w.as_bool()) == Some(true) =>
+        {
+            get_workspace_version(&crate_dir)?
+        }
+        _ => {
+            return Err(anyhow!(
+                "{} has an invalid package version",
+                manifest_path.display()
+            ))
+        }
     };
-    let program_manifest = Manifest::from_path(&program_manifest_path).map_err(|_| {
+    Ok(Version::parse(&version)?)
+}
+
+/// Rewrites the `[package]` version in a program's Cargo.toml, leaving the
+/// rest of the file untouched, and returns the path of the Cargo.toml.
+///
+/// Errors if the version is inherited from the workspace, since bumping it
+/// would bump every crate in the workspace.
+pub fn set_program_version(
+    program: &str,
+    root: &Path,
+    programs_dir: &Path,
+    version: &Version,
+) -> Result<PathBuf> {
+    let manifest_path = find_program_crate(program, root, programs_dir)?.join("Cargo.toml");
+    let contents = fs::read_to_string(&manifest_path)
+        .map_err(|e| format_err!("Could not read {}: {}", manifest_path.display(), e))?;
+
+    let mut in_package = false;
+    let mut replaced = false;
+    let mut lines = vec![];
+    for line in contents.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_package = trimmed == "[package]";
+        } else if in_package && !replaced && trimmed.starts_with("version") {
+            let value = trimmed["version".len()..].trim_start();
+            if value.starts_with('=') && value[1..].trim_start().starts_with('"') {
+                let start = line.find('"').unwrap_or_default() + 1;
+                let end = start + line[start..].find('"').unwrap_or_default();
+                lines.push(format!("{}{}{}", &line[..start], version, &line[end..]));
+                replaced = true;
+                continue;
+            }
+            if value.starts_with(".workspace") || value.starts_with('=') {
+                return Err(anyhow!(
+                    "{} inherits its version from the Cargo workspace. Bump [workspace.package].version instead.",
+                    manifest_path.display()
+                ));
+            }
+        }
+        lines.push(line.to_string());
+    }
+    if !replaced {
+        return Err(anyhow!(
+            "{} has no package version",
+            manifest_path.display()
+        ));
+    }
+    fs::write(&manifest_path, lines.concat())
+        .map_err(|e| format_err!("Could not write {}: {}", manifest_path.display(), e))?;
+    Ok(manifest_path)
+}
+
+/// Reads `[workspace.package].version` from the nearest Cargo workspace root
+/// at or above a crate.
+fn get_workspace_version(crate_dir: &Path) -> Result<String> {
+    for dir in crate_dir.ancestors() {
+        let manifest_path = dir.join("Cargo.toml");
+        if !manifest_path.exists() {
+            continue;
+        }
+        if let Some(workspace) = read_cargo_toml(&manifest_path)?.get("workspace") {
+            return workspace
+                .get("package")
+                .and_then(|package| package.get("version"))
+                .and_then(|version| version.as_str())
+                .map(|version| version.to_string())
+                .ok_or_else(|| {
+                    format_err!(
+                        "{} has no [workspace.package] version to inherit",
+                        manifest_path.display()
+                    )
+                });
+        }
+    }
+    Err(format_err!(
+        "No Cargo workspace found above {} to inherit the version from",
+        crate_dir.display()
+    ))
+}
+
+/// Reads a Cargo.toml as plain TOML, so keys inherited from the workspace
+/// can be resolved by hand.
+fn read_cargo_toml(path: &Path) -> Result<toml::Value> {
+    fs::read_to_string(path)
+        .map_err(|e| format_err!("Could not read {}: {}", path.display(), e))?
+        .parse()
+        .map_err(|e| format_err!("Could not parse {}: {}", path.display(), e))
+}
+
+/// Errors if the program's source declares a different address with
+/// `declare_id!`, since such a program fails at runtime.
+///
+/// Only checked when the source is at the version being deployed, as older
+/// versions may have been built for a different address.
+fn check_declared_program_id(
+    program: &str,
+    root: &Path,
+    config: &Config,
+    deploy_version: &Version,
+    program_key: &Pubkey,
+) -> Result<()> {
+    let programs_dir = config.programs_dir(root);
+    let crate_dir = match find_program_crate(program, root, &programs_dir) {
+        Ok(crate_dir) => crate_dir,
+        Err(_) => return Ok(()),
+    };
+    if get_program_version(program, root, &programs_dir)
+        .ok()
+        .as_ref()
+        != Some(deploy_version)
+    {
+        return Ok(());
+    }
+    match get_declared_program_id(&crate_dir)? {
+        Some(declared) if declared != *program_key => Err(anyhow!(
+            "Program address is {}, but its source declares {} with declare_id!",
+            program_key,
+            declared
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the address declared with `declare_id!("...")` in a crate's
+/// `src/lib.rs`, if any.
+pub fn get_declared_program_id(crate_dir: &Path) -> Result<Option<Pubkey>> {
+    let lib = match fs::read_to_string(crate_dir.join("src").join("lib.rs")) {
+        Ok(lib) => lib,
+        Err(_) => return Ok(None),
+    };
+    let declared = lib
+        .split("declare_id!(")
+        .nth(1)
+        .and_then(|rest| rest.split('"').nth(1));
+    match declared {
+        Some(declared) => Pubkey::from_str(declared)
+            .map(Some)
+            .map_err(|_| format_err!("invalid declare_id! address {}", declared)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the version of a program from Anchor.toml.
+///
+/// Looks for a `version` key on the program's entry under any
+/// `[programs.<cluster>]` table, e.g.
+/// `my_program = { address = "...", version = "1.2.3" }`.
+pub fn get_anchor_program_version(program: &str, root: &Path) -> Result<Version> {
+    let anchor_toml_path = root.join("Anchor.toml");
+    let anchor_toml: toml::Value = fs::read_to_string(&anchor_toml_path)
+        .map_err(|_| format_err!("Anchor.toml not found at {}", anchor_toml_path.display()))?
+        .parse()?;
+    let names = [program.to_string(), program.replace("-", "_")];
+    anchor_toml
+        .get("programs")
+        .and_then(|programs| programs.as_table())
+        .into_iter()
+        .flat_map(|clusters| clusters.values())
+        .filter_map(|cluster| cluster.as_table())
+        .flat_map(|cluster| names.iter().filter_map(move |name| cluster.get(name)))
+        .find_map(|entry| entry.get("version").and_then(|v| v.as_str()))
+        .ok_or_else(|| format_err!("No version for program {} found in Anchor.toml", program))
+        .and_then(|v| Ok(Version::parse(v)?))
+}
+
+/// Returns true if a `--program` argument is a pattern rather than a name.
+pub fn is_program_pattern(program: &str) -> bool {
+    program.contains(&['*', '?'][..])
+}
+
+/// Lists the programs under `programs_dir` whose names match a pattern, where
+/// `*` matches any run of characters and `?` any single character.
+///
+/// Names are returned as they appear in `target/deploy/`, i.e. with
+/// underscores instead of dashes. Errors if nothing matches.
+pub fn select_programs(programs_dir: &Path, pattern: &str) -> Result<Vec<String>> {
+    let mut programs = vec![];
+    for entry in fs::read_dir(programs_dir).map_err(|e| {
+        format_err!(
+            "could not list programs directory {}: {}",
+            programs_dir.display(),
+            e
+        )
+    })? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let program = name.replace("-", "_");
+        if glob_match(pattern, &name) || glob_match(pattern, &program) {
+            programs.push(program);
+        }
+    }
+    if programs.is_empty() {
+        return Err(anyhow!(
+            "No programs in {} match {}",
+            programs_dir.display(),
+            pattern
+        ));
+    }
+    programs.sort();
+    Ok(programs)
+}
+
+/// Matches a name against a pattern of literal characters, `*` and `?`.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Position to resume from after the last `*`: (pattern index, name index).
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Resolves the address of a program the same way [load] does, without
+/// requiring its binary or IDL.
+pub fn resolve_program_id(
+    program: &str,
+    network: &Network,
+    options: &LoadOptions,
+) -> Result<Pubkey> {
+    let (config, _, root) = options.discover()?;
+    match options.program_id_source {
+        ProgramIdSource::Keypair => {
+            let version = get_deploy_version(
+                program,
+                &root,
+                &config.programs_dir(&root),
+                options.version.clone(),
+                options.version_from,
+            )?;
+            Ok(keypair::read_keypair(&config.program_kp_path(&version, program))?.pubkey())
+        }
+        ProgramIdSource::Anchor => get_anchor_program_id(program, &root, network),
+    }
+}
+
+/// Reads the address of a program from the `[programs.<network>]` table of
+/// Anchor.toml, e.g. `my_program = "..."` or
+/// `my_program = { address = "..." }`.
+pub fn get_anchor_program_id(program: &str, root: &Path, network: &Network) -> Result<Pubkey> {
+    let anchor_toml_path = root.join("Anchor.toml");
+    let anchor_toml: toml::Value = fs::read_to_string(&anchor_toml_path)
+        .map_err(|_| format_err!("Anchor.toml not found at {}", anchor_toml_path.display()))?
+        .parse()?;
+    let names = [program.to_string(), program.replace("-", "_")];
+    let cluster = anchor_toml
+        .get("programs")
+        .and_then(|programs| programs.get(network.to_string()));
+    let address = names
+        .iter()
+        .filter_map(|name| cluster.and_then(|cluster| cluster.get(name)))
+        .find_map(|entry| {
+            entry
+                .as_str()
+                .or_else(|| entry.get("address").and_then(|a| a.as_str()))
+        })
+        .ok_or_else(|| {
+            format_err!(
+                "No address for program {} found under [programs.{}] in Anchor.toml",
+                program,
+                network
+            )
+        })?;
+    address.parse().map_err(|_| {
         format_err!(
-            "Program Cargo.toml not found at paths {} or {}",
-            &mf_path.display(),
-            &program_manifest_path.display()
+            "Invalid address {} for program {} in Anchor.toml",
+            address,
+            program
         )
-    })?;
-    Ok(Version::parse(
-        program_manifest
-            .package
-            .ok_or_else(|| anyhow!("invalid package"))?
-            .version
-            .as_str(),
-    )?)
+    })
+}
+
+/// Reads the version from the most recent git tag reachable from HEAD.
+pub fn get_git_tag_version(root: &Path) -> Result<Version> {
+    let tag = command::exec_stdout(
+        Command::new("git")
+            .current_dir(root)
+            .arg("describe")
+            .arg("--tags")
+            .arg("--abbrev=0"),
+    )?;
+    Version::parse(tag.trim_start_matches('v'))
+        .map_err(|e| format_err!("Git tag {} is not a valid semver version: {}", tag, e))
+}
+
+/// The commit a workspace is at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitRevision {
+    pub commit: String,
+    /// Whether tracked files have uncommitted changes. Untracked files, such
+    /// as receipts of earlier deploys, are ignored.
+    pub dirty: bool,
 }
 
-fn get_deploy_version(program: &str, root: &Path, version: Option<Version>) -> Result<Version> {
+/// Returns the commit the workspace is at, or `None` if it isn't a git
+/// repository or git isn't installed.
+pub fn git_revision(root: &Path) -> Option<GitRevision> {
+    let commit = command::exec_stdout(
+        Command::new("git")
+            .current_dir(root)
+            .arg("rev-parse")
+            .arg("HEAD"),
+    )
+    .ok()?;
+    let status = command::exec_stdout(
+        Command::new("git")
+            .current_dir(root)
+            .arg("status")
+            .arg("--porcelain")
+            .arg("--untracked-files=no"),
+    )
+    .ok()?;
+    Some(GitRevision {
+        commit,
+        dirty: !status.is_empty(),
+    })
+}
+
+/// Checks that the workspace is a clean checkout of a git tag, so that what
+/// is deployed can be traced back to it.
+pub fn check_git_tag(root: &Path, tag: &str) -> Result<()> {
+    let tag_commit = command::exec_stdout(
+        Command::new("git")
+            .current_dir(root)
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg(format!("{}^{{commit}}", tag)),
+    )
+    .map_err(|_| format_err!("Git tag {} not found", tag))?;
+    let revision = git_revision(root)
+        .ok_or_else(|| format_err!("{} is not a git repository", root.display()))?;
+    if revision.commit != tag_commit {
+        return Err(anyhow!(
+            "HEAD is at {}, not at {} ({}). Run `git checkout {}` before deploying.",
+            revision.commit,
+            tag,
+            tag_commit,
+            tag
+        ));
+    }
+    if revision.dirty {
+        return Err(anyhow!(
+            "The working tree has uncommitted changes. Commit or stash them before deploying {}.",
+            tag
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves the version of a program from the given source.
+///
+/// If the Cargo.toml lookup fails in an Anchor workspace, Anchor.toml is tried
+/// before giving up.
+pub fn resolve_program_version(
+    program: &str,
+    root: &Path,
+    programs_dir: &Path,
+    version_from: VersionSource,
+) -> Result<Version> {
+    match version_from {
+        VersionSource::Cargo => get_program_version(program, root, programs_dir).or_else(|err| {
+            if root.join("Anchor.toml").exists() {
+                get_anchor_program_version(program, root).map_err(|_| err)
+            } else {
+                Err(err)
+            }
+        }),
+        VersionSource::Anchor => get_anchor_program_version(program, root),
+        VersionSource::GitTag => get_git_tag_version(root),
+    }
+}
+
+fn get_deploy_version(
+    program: &str,
+    root: &Path,
+    programs_dir: &Path,
+    version: Option<Version>,
+    version_from: VersionSource,
+) -> Result<Version> {
     match version {
         Some(v) => Ok(v),
-        None => get_program_version(program, root),
+        None => resolve_program_version(program, root, programs_dir, version_from),
+    }
+}
+
+/// Reads of the deployed program when verifying it, while it keeps changing.
+const VERIFY_ATTEMPTS: u32 = 5;
+/// Wait between reads of the deployed program when verifying it.
+const VERIFY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reads the transaction signatures from the stdout of a solana command:
+/// `Signature: <signature>` lines, or the `signature` field of its JSON
+/// output.
+pub fn parse_signatures(stdout: &[u8]) -> Vec<String> {
+    let stdout = String::from_utf8_lossy(stdout);
+    let mut signatures = vec![];
+    if let Ok(Value::Object(output)) = serde_json::from_str(stdout.trim()) {
+        if let Some(Value::String(signature)) = output.get("signature") {
+            signatures.push(signature.clone());
+        }
+    }
+    for line in stdout.lines() {
+        if let Some(signature) = line.trim().strip_prefix("Signature:") {
+            signatures.push(signature.trim().to_string());
+        }
+    }
+    signatures.retain(|signature| Signature::from_str(signature).is_ok());
+    signatures
+}
+
+/// Bytes of program data written per transaction, for estimating fees.
+const WRITE_CHUNK_LEN: u64 = 1000;
+/// Fee charged per transaction signature.
+const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+/// Estimated fees of writing an account of the given size, at roughly one
+/// transaction per kilobyte.
+fn write_fees(len: usize) -> u64 {
+    (len as u64 / WRITE_CHUNK_LEN + 1) * LAMPORTS_PER_SIGNATURE
+}
+
+/// Estimated cost of deploying a program, in lamports.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeployCost {
+    pub program: String,
+    pub version: Version,
+    pub network: Network,
+    pub bin_len: usize,
+    /// Bytes allocated for the program data.
+    pub program_data_len: usize,
+    pub program_rent: u64,
+    pub program_data_rent: u64,
+    /// Rent of the buffer the binary is written to, refunded once the
+    /// program is deployed.
+    pub buffer_rent: u64,
+    /// Fees of the write transactions, excluding priority fees.
+    pub fees: u64,
+}
+
+impl DeployCost {
+    /// Lamports the payer needs before deploying.
+    pub fn required(&self) -> u64 {
+        self.program_rent + self.program_data_rent + self.buffer_rent + self.fees
+    }
+
+    /// Lamports spent or locked in rent once the buffer is refunded.
+    pub fn total(&self) -> u64 {
+        self.required() - self.buffer_rent
+    }
+}
+
+/// Options for [Workspace::deploy].
+#[derive(Debug, Default, Clone)]
+pub struct DeployOptions {
+    pub skip_anchor_idl: bool,
+    /// Bytes to allocate for the program data. Overrides the program's config.
+    pub max_len: Option<usize>,
+    /// Priority fee in micro-lamports per compute unit. Overrides the
+    /// network's config.
+    pub priority_fee: Option<u64>,
+    /// Compute units each transaction requests. Overrides the network's
+    /// config.
+    pub compute_unit_limit: Option<u32>,
+    /// Send transactions through the RPC instead of the TPU, even if the
+    /// network's config doesn't.
+    pub use_rpc: bool,
+    /// Keypair that pays transaction fees. Overrides the network's config.
+    pub fee_payer: Option<PathBuf>,
+    /// Leave the deployer as the upgrade authority of the program and IDL
+    /// instead of handing them to the network's `upgrade_authority`.
+    pub no_set_authority: bool,
+    /// Give up the upgrade authority after deploying, making the program
+    /// immutable. The IDL authority is still handed to the network's
+    /// `upgrade_authority`.
+    pub finalize: bool,
+    /// Leave the deployer as the IDL authority, even if the program's upgrade
+    /// authority is handed over.
+    pub keep_idl_authority: bool,
+    /// Check the binary against its verifiable build hash before deploying,
+    /// and the deployed program against it afterwards.
+    pub verifiable: bool,
+    /// Commitment to read the deployed program at when verifying it.
+    /// Defaults to the network's commitment.
+    pub verify_commitment: Option<Commitment>,
+    /// How the deploy transaction is retried if it fails.
+    pub retry: RetryPolicy,
+    /// Wait for another deploy of the program to the network to finish
+    /// instead of failing.
+    pub wait_for_lock: bool,
+    /// Check that the network's RPC is healthy before doing anything else.
+    pub check_health: bool,
+    /// Don't check whether the program already exists before deploying,
+    /// saving a round-trip. `solana program deploy` then fails if it does.
+    pub skip_show: bool,
+    /// Fail unless the workspace is a clean checkout of this git tag.
+    pub from_tag: Option<String>,
+    /// Skip the steps a previous deploy of this version completed before it
+    /// failed.
+    pub resume: bool,
+    /// Wait for the deploy to be finalized before returning.
+    pub wait_finalized: Option<FinalizedWait>,
+}
+
+/// How [Workspace::deploy] waits for a deploy to be finalized.
+#[derive(Debug, Clone, Copy)]
+pub struct FinalizedWait {
+    /// How often to poll the RPC.
+    pub poll_interval: Duration,
+    /// How long to wait before failing.
+    pub timeout: Duration,
+}
+
+/// Options for [Workspace::upgrade].
+#[derive(Debug, Default, Clone)]
+pub struct UpgradeOptions {
+    pub skip_anchor_idl: bool,
+    /// Extend the program data account if the new binary doesn't fit.
+    pub auto_extend: bool,
+    /// Switch to this already-written buffer instead of writing a new one,
+    /// e.g. to retry an upgrade that failed after `write-buffer` or to
+    /// finalize one started with `write_only`.
+    pub buffer: Option<Pubkey>,
+    /// Stop once the buffer is written and handed to the upgrade authority,
+    /// leaving the switch to a later upgrade from the buffer.
+    pub write_only: bool,
+    /// Priority fee in micro-lamports per compute unit. Overrides the
+    /// network's config.
+    pub priority_fee: Option<u64>,
+    /// Compute units each transaction requests. Overrides the network's
+    /// config.
+    pub compute_unit_limit: Option<u32>,
+    /// Send transactions through the RPC instead of the TPU, even if the
+    /// network's config doesn't.
+    pub use_rpc: bool,
+    /// Keypair that pays transaction fees. Overrides the network's config.
+    pub fee_payer: Option<PathBuf>,
+    /// Write the buffer and simulate the upgrade instead of applying it.
+    pub preflight: bool,
+    /// With `preflight`, apply the upgrade if the simulation succeeds.
+    pub commit: bool,
+    /// Check the binary against its verifiable build hash before upgrading,
+    /// and the upgraded program against it afterwards.
+    pub verifiable: bool,
+    /// Commitment to read the upgraded program at when verifying it.
+    /// Defaults to the network's commitment.
+    pub verify_commitment: Option<Commitment>,
+    /// Keypair of the upgrade authority. Overrides `UPGRADE_AUTHORITY_KEYPAIR`.
+    pub upgrade_authority_keypair: Option<PathBuf>,
+    /// How writing the buffer and switching to it are retried if they fail.
+    pub retry: RetryPolicy,
+    /// Wait for another deploy of the program to the network to finish
+    /// instead of failing.
+    pub wait_for_lock: bool,
+    /// Check that the network's RPC is healthy before doing anything else.
+    pub check_health: bool,
+}
+
+/// Most compute units a transaction can request.
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// Compute budget of deploy transactions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ComputeBudget {
+    /// Priority fee in micro-lamports per compute unit.
+    pub priority_fee: Option<u64>,
+    /// Compute units each transaction requests.
+    pub compute_unit_limit: Option<u32>,
+}
+
+/// Appends the priority fee and compute unit limit, if any, to a solana
+/// command.
+fn with_compute_budget(cmd: &mut Command, budget: ComputeBudget) -> &mut Command {
+    if let Some(priority_fee) = budget.priority_fee {
+        cmd.arg("--with-compute-unit-price")
+            .arg(priority_fee.to_string());
     }
+    if let Some(compute_unit_limit) = budget.compute_unit_limit {
+        cmd.arg("--compute-unit-limit")
+            .arg(compute_unit_limit.to_string());
+    }
+    cmd
+}
+
+/// Appends the fee payer, if any, to a solana command.
+fn with_fee_payer<'a>(cmd: &'a mut Command, fee_payer: Option<&Path>) -> &'a mut Command {
+    if let Some(fee_payer) = fee_payer {
+        cmd.arg("--fee-payer").arg(fee_payer);
+    }
+    cmd
+}
+
+/// Appends `--use-rpc` to a solana command if set.
+fn with_use_rpc(cmd: &mut Command, use_rpc: bool) -> &mut Command {
+    if use_rpc {
+        cmd.arg("--use-rpc");
+    }
+    cmd
+}
+
+/// Appends `--skip-preflight` to a solana command if set.
+fn with_skip_preflight(cmd: &mut Command, skip_preflight: bool) -> &mut Command {
+    if skip_preflight {
+        cmd.arg("--skip-preflight");
+    }
+    cmd
 }
 
 impl Workspace {
+    /// Deploys the program for the first time, then hands the upgrade (and IDL)
+    /// authority to the configured upgrade authority.
+    pub fn deploy(&self, progress: &mut Progress, options: &DeployOptions) -> Result<Receipt> {
+        if let Some(tag) = &options.from_tag {
+            check_git_tag(&self.root, tag)?;
+        }
+        if options.check_health {
+            self.check_health()?;
+        }
+        let _lock = self.lock(options.wait_for_lock)?;
+        // with --final, the IDL authority is still handed over
+        if !options.no_set_authority {
+            self.network_config
+                .check_allowed_upgrade_authority(&self.network, &self.config.tools.solana())?;
+        }
+        info!(
+            "Deploying program {} with version {}",
+            self.program, self.deploy_version
+        );
+
+        info!("Address: {}", self.program_key);
+
+        let program_kp_path = self.program_paths.id.as_ref().ok_or_else(|| {
+            anyhow!("Deploying a program requires its keypair. Use `--program-id-source keypair`.")
+        })?;
+
+        let verifiable_hash = self.check_verifiable_hash(options.verifiable)?;
+
+        let state_path = DeployState::path(
+            &self.root,
+            &self.program,
+            &self.deploy_version,
+            &self.network,
+        );
+        let bin_sha256 = sha256_file(&self.deploy_bin)?;
+        let mut state = match DeployState::load(state_path.clone())? {
+            Some(state) if options.resume => {
+                if state.bin_sha256 != bin_sha256 {
+                    return Err(anyhow!(
+                        "{} was recorded for a different binary. Delete it to deploy from scratch.",
+                        state_path.display()
+                    ));
+                }
+                state
+            }
+            Some(state)
+                if state.is_done(DeployStep::Deploy)
+                    && !options.skip_show
+                    && self.show_program()? =>
+            {
+                return Err(anyhow!(
+                    "Program already deployed by a deploy that stopped partway ({}). Pass --resume to finish it.",
+                    state_path.display()
+                ));
+            }
+            _ => DeployState::new(state_path, bin_sha256),
+        };
+        if !state.completed.is_empty() {
+            progress.note(&format!(
+                "Resuming after {} completed steps.",
+                state.completed.len()
+            ));
+        }
+
+        if !state.is_done(DeployStep::Deploy) && !options.skip_show && self.show_program()? {
+            info!("Program already deployed. Use `captain upgrade` if you want to upgrade the program.");
+            return Ok(self.receipt(Action::AlreadyDeployed, None));
+        }
+
+        let max_len = self.max_len(options.max_len);
+        let deploy_config = self.deploy_config();
+        let budget = self.compute_budget(
+            &deploy_config,
+            options.priority_fee,
+            options.compute_unit_limit,
+        )?;
+        let use_rpc = self.use_rpc(&deploy_config, options.use_rpc);
+        let fee_payer = self.fee_payer(options.fee_payer.as_deref())?;
+        let fee_payer = fee_payer.as_deref();
+
+        let (program_len, account_lens) = self.deploy_account_lens(max_len)?;
+        if !state.is_done(DeployStep::Deploy) {
+            let rents = self.check_balance(fee_payer, &account_lens)?;
+            // The buffer's rent is refunded once the program is deployed.
+            state.rent_lamports = rents[0] + rents[1];
+            let deploy = progress.step("Deploying program", || {
+                let mut cmd = solana_cmd!(self);
+                cmd.arg("program")
+                    .arg("deploy")
+                    .arg(&self.deploy_bin)
+                    .arg("--program-id")
+                    .arg(program_kp_path);
+                if let Some(max_len) = max_len {
+                    cmd.arg("--max-len").arg(max_len.to_string());
+                }
+                with_skip_preflight(&mut cmd, deploy_config.skip_preflight());
+                self.runner.exec_retrying(
+                    with_compute_budget(
+                        with_use_rpc(with_fee_payer(&mut cmd, fee_payer), use_rpc),
+                        budget,
+                    ),
+                    options.retry,
+                )
+            })?;
+            state.signatures = parse_signatures(&deploy.stdout);
+            self.print_signatures(&state.signatures);
+            state.complete(DeployStep::Deploy)?;
+        }
+
+        // handing the authority to the deployer itself would be a wasted
+        // transaction, or fail
+        let deployer_is_authority = !options.no_set_authority
+            && !options.finalize
+            && self.deployer_is_upgrade_authority()?;
+
+        if !state.is_done(DeployStep::SetAuthority) {
+            if options.finalize {
+                progress.step("Making program immutable", || {
+                    self.runner.exec(with_fee_payer(
+                        solana_cmd!(self)
+                            .arg("program")
+                            .arg("set-upgrade-authority")
+                            .arg(self.program_key.to_string())
+                            .arg("--final"),
+                        fee_payer,
+                    ))
+                })?;
+            } else if options.no_set_authority {
+                progress.note("Leaving the deployer as the upgrade authority.");
+            } else if deployer_is_authority {
+                progress.note("The deployer is already the upgrade authority.");
+            } else {
+                progress.step("Setting upgrade authority", || {
+                    self.runner.exec(with_fee_payer(
+                        solana_cmd!(self)
+                            .arg("program")
+                            .arg("set-upgrade-authority")
+                            .arg(self.program_key.to_string())
+                            .arg("--new-upgrade-authority")
+                            .arg(&self.network_config.upgrade_authority),
+                        fee_payer,
+                    ))
+                })?;
+            }
+            state.complete(DeployStep::SetAuthority)?;
+        }
+
+        self.show_program()?;
+
+        if let Some(hash) = verifiable_hash.as_ref().filter(|_| options.verifiable) {
+            progress.step("Verifying deployed program", || {
+                self.verify_deployed_hash(
+                    hash,
+                    options
+                        .verify_commitment
+                        .unwrap_or(deploy_config.commitment()),
+                )
+            })?;
+        }
+
+        let mut idl_authority = None;
+        if self.has_anchor() {
+            match &self.program_paths.idl {
+                _ if options.skip_anchor_idl => progress.note("Skipping Anchor IDL upload."),
+                None => progress.note("No IDL found. Skipping Anchor IDL upload."),
+                Some(idl) => {
+                    if !state.is_done(DeployStep::Idl) {
+                        self.upload_idl(progress, idl, options.retry)?;
+                        state.complete(DeployStep::Idl)?;
+                    }
+                    let keep = options.no_set_authority
+                        || options.keep_idl_authority
+                        || deployer_is_authority;
+                    if keep {
+                        if options.keep_idl_authority {
+                            progress.note("Leaving the deployer as the IDL authority.");
+                        }
+                        idl_authority = Some(self.deployer_pubkey()?.to_string());
+                    } else {
+                        if !state.is_done(DeployStep::IdlAuthority) {
+                            self.set_idl_authority(progress, options.retry)?;
+                            state.complete(DeployStep::IdlAuthority)?;
+                        }
+                        idl_authority = Some(self.network_config.upgrade_authority.clone());
+                    }
+                }
+            }
+        }
+
+        if let Some(wait) = &options.wait_finalized {
+            progress.step("Waiting for finalization", || self.wait_finalized(wait))?;
+        }
+
+        progress.summary();
+        info!("{}", "Deployment success!".green());
+        info!(
+            "Program data: {} bytes, {} SOL locked in rent",
+            program_len,
+            lamports_to_sol(state.rent_lamports)
+        );
+        let mut receipt = self.receipt(Action::Deployed, None);
+        receipt.program_data_len = Some(program_len);
+        receipt.rent_lamports = Some(state.rent_lamports);
+        if options.finalize {
+            receipt.immutable = true;
+        } else if !options.no_set_authority {
+            receipt.upgrade_authority = Some(self.network_config.upgrade_authority.clone());
+        }
+        receipt.idl_authority = idl_authority;
+        receipt.verifiable_hash = verifiable_hash;
+        receipt.signatures = state.signatures.clone();
+        state.clear()?;
+        self.archive_receipt(receipt)
+    }
+
+    /// Upgrades an existing program by writing a new buffer and switching the
+    /// program to it with the upgrade authority.
+    ///
+    /// The upgrade authority keypair is read from `UPGRADE_AUTHORITY_KEYPAIR`
+    /// unless [UpgradeOptions::upgrade_authority_keypair] is set, or else is
+    /// the network's [NetworkConfig::upgrade_authority_signer].
+    ///
+    /// If the new binary is larger than the program data account, the account
+    /// is extended before switching to the new buffer when
+    /// [UpgradeOptions::auto_extend] is set; otherwise this errors before
+    /// anything is written.
+    pub fn upgrade(&self, progress: &mut Progress, options: &UpgradeOptions) -> Result<Receipt> {
+        if options.write_only && (options.buffer.is_some() || options.preflight) {
+            return Err(anyhow!(
+                "--write-only can't be combined with --from-buffer or --preflight"
+            ));
+        }
+        // a --write-only upgrade can do without the upgrade authority's
+        // keypair, which is then only used to close an interrupted buffer
+        let upgrade_authority_keypair = match &options.upgrade_authority_keypair {
+            Some(keypair) => Some(keypair.to_string_lossy().to_string()),
+            None => match env::var("UPGRADE_AUTHORITY_KEYPAIR") {
+                Ok(keypair) => Some(keypair),
+                Err(_) => match self.network_config.upgrade_authority_signer() {
+                    Some(signer) => {
+                        if !options.write_only {
+                            self.network_config.check_upgrade_authority_keypair(
+                                &self.network,
+                                &self.config.tools.solana(),
+                            )?;
+                        }
+                        Some(signer.to_string())
+                    }
+                    None if options.write_only => None,
+                    None => {
+                        return Err(format_err!(
+                            "Must set UPGRADE_AUTHORITY_KEYPAIR environment variable or upgrade_authority_keypair under [networks.{}].",
+                            self.network
+                        ))
+                    }
+                },
+            },
+        };
+        if options.check_health {
+            self.check_health()?;
+        }
+        let _lock = self.lock(options.wait_for_lock)?;
+        // the buffer is handed to the upgrade authority before the switch
+        self.network_config
+            .check_allowed_upgrade_authority(&self.network, &self.config.tools.solana())?;
+
+        info!(
+            "Upgrading program {} with version {}",
+            self.program, self.deploy_version
+        );
+
+        if !self.show_program()? {
+            return Err(CaptainError::ProgramNotFound.into());
+        }
+
+        let verifiable_hash = self.check_verifiable_hash(options.verifiable)?;
+
+        let additional_bytes = self.required_extension()?;
+        if additional_bytes > 0 && options.preflight {
+            return Err(anyhow!(
+                "The new program binary is {} bytes larger than the program data account, which can't be simulated. Run `solana program extend {} {}` first.",
+                additional_bytes,
+                self.program_key,
+                additional_bytes
+            ));
+        }
+        if additional_bytes > 0 && !options.auto_extend && !options.write_only {
+            return Err(anyhow!(
+                "The new program binary is {} bytes larger than the program data account. Run `solana program extend {} {}` or pass --auto-extend.",
+                additional_bytes,
+                self.program_key,
+                additional_bytes
+            ));
+        }
+        if let Some(idl) = self
+            .program_paths
+            .idl
+            .as_ref()
+            .filter(|_| self.has_anchor() && !options.skip_anchor_idl)
+        {
+            self.check_idl_account_len(idl);
+        }
+        let deploy_config = self.deploy_config();
+        let budget = self.compute_budget(
+            &deploy_config,
+            options.priority_fee,
+            options.compute_unit_limit,
+        )?;
+        let use_rpc = self.use_rpc(&deploy_config, options.use_rpc);
+        let fee_payer = self.fee_payer(options.fee_payer.as_deref())?;
+        let fee_payer = fee_payer.as_deref();
+
+        let mut accounts = vec![];
+        if options.buffer.is_none() {
+            let bin_len = fs::metadata(&self.deploy_bin)?.len() as usize;
+            accounts.push(UpgradeableLoaderState::buffer_len(bin_len)?);
+        }
+        if additional_bytes > 0 && !options.write_only {
+            accounts.push(additional_bytes);
+        }
+        self.check_balance(fee_payer, &accounts)?;
+
+        let pending_path = PendingBuffer::path(
+            &self.root,
+            &self.program,
+            &self.deploy_version,
+            &self.network,
+        );
+        let bin_sha256 = sha256_file(&self.deploy_bin)?;
+        let pending = match options.buffer {
+            Some(buffer_key) => PendingBuffer::load(pending_path.clone())?.filter(|pending| {
+                if pending.buffer != buffer_key.to_string() {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "WARNING: {} records buffer {}, not {}.",
+                            pending_path.display(),
+                            pending.buffer,
+                            buffer_key
+                        )
+                        .yellow()
+                    );
+                    return false;
+                }
+                if pending.bin_sha256 != bin_sha256 {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "WARNING: buffer {} was written from a different binary than {}.",
+                            buffer_key,
+                            self.deploy_bin.display()
+                        )
+                        .yellow()
+                    );
+                }
+                true
+            }),
+            None => None,
+        };
+
+        // buffers passed with --buffer are left for the caller to reuse, even
+        // if interrupted
+        let (buffer_key, mut signatures, _buffer_guard) = match options.buffer {
+            Some(buffer_key) => {
+                self.check_buffer(&buffer_key)?;
+                progress.note(&format!("Resuming with existing buffer {}.", buffer_key));
+                // the receipt covers both halves of a --write-only upgrade
+                let signatures = pending
+                    .as_ref()
+                    .map(|pending| pending.signatures.clone())
+                    .unwrap_or_default();
+                (buffer_key, signatures, None)
+            }
+            None => {
+                let (buffer_key, signatures, guard) = self.write_buffer(
+                    progress,
+                    budget,
+                    use_rpc,
+                    fee_payer,
+                    upgrade_authority_keypair.as_deref(),
+                    options.retry,
+                )?;
+                (buffer_key, signatures, guard)
+            }
+        };
+
+        if options.write_only {
+            PendingBuffer::new(
+                pending_path,
+                buffer_key.to_string(),
+                bin_sha256,
+                signatures.clone(),
+            )
+            .save()?;
+            progress.summary();
+            info!(
+                "Buffer {} is ready. Finalize the upgrade with `captain upgrade --from-buffer {}`.",
+                buffer_key, buffer_key
+            );
+            let mut receipt = self.receipt(Action::BufferWritten, Some(buffer_key.to_string()));
+            receipt.verifiable_hash = verifiable_hash;
+            receipt.signatures = signatures;
+            return Ok(receipt);
+        }
+        let upgrade_authority_keypair = upgrade_authority_keypair.ok_or_else(|| {
+            anyhow!("upgrade authority keypair is only optional with --write-only")
+        })?;
+
+        if options.preflight {
+            let simulation =
+                progress.step("Simulating upgrade", || self.simulate_upgrade(&buffer_key))?;
+            for log in &simulation.logs {
+                info!("    {}", log);
+            }
+            let failure = simulation.err.map(|err| err.to_string());
+            if !options.commit || failure.is_some() {
+                // buffers passed with --buffer are left for the caller to reuse
+                if options.buffer.is_none() {
+                    progress.step("Closing buffer", || {
+                        self.runner.exec(&mut self.close_buffer_cmd(
+                            &buffer_key,
+                            Path::new(&upgrade_authority_keypair),
+                            fee_payer,
+                        )?)
+                    })?;
+                }
+                progress.summary();
+                if let Some(failure) = failure {
+                    return Err(anyhow!("Upgrade simulation failed: {}", failure));
+                }
+                info!("{}", "Preflight passed.".green());
+                let mut receipt = self.receipt(Action::Simulated, Some(buffer_key.to_string()));
+                receipt.signatures = signatures;
+                return Ok(receipt);
+            }
+        }
+
+        if additional_bytes > 0 {
+            progress.step("Extending program data", || {
+                self.runner.exec(with_compute_budget(
+                    with_fee_payer(
+                        solana_cmd!(self)
+                            .arg("program")
+                            .arg("extend")
+                            .arg(self.program_key.to_string())
+                            .arg(additional_bytes.to_string()),
+                        fee_payer,
+                    ),
+                    budget,
+                ))
+            })?;
+        }
+
+        let switch = progress.step(
+            "Switching to new buffer (please connect your wallet)",
+            || {
+                self.runner.exec_retrying(
+                    with_compute_budget(
+                        with_use_rpc(
+                            with_fee_payer(
+                                with_skip_preflight(
+                                    solana_cmd!(self, &upgrade_authority_keypair)
+                                        .arg("program")
+                                        .arg("deploy")
+                                        .arg("--buffer")
+                                        .arg(buffer_key.to_string())
+                                        .arg("--program-id")
+                                        .arg(self.program_key.to_string()),
+                                    deploy_config.skip_preflight(),
+                                ),
+                                fee_payer,
+                            ),
+                            use_rpc,
+                        ),
+                        budget,
+                    ),
+                    options.retry,
+                )
+            },
+        )?;
+        let switch_signatures = parse_signatures(&switch.stdout);
+        self.print_signatures(&switch_signatures);
+        signatures.extend(switch_signatures);
+
+        self.show_program()?;
+        if let Some(pending) = pending {
+            pending.clear()?;
+        }
+
+        if let Some(hash) = verifiable_hash.as_ref().filter(|_| options.verifiable) {
+            progress.step("Verifying upgraded program", || {
+                self.verify_deployed_hash(
+                    hash,
+                    options
+                        .verify_commitment
+                        .unwrap_or(deploy_config.commitment()),
+                )
+            })?;
+        }
+
+        if self.has_anchor() {
+            match &self.program_paths.idl {
+                _ if options.skip_anchor_idl => progress.note("Skipping Anchor IDL upload."),
+                None => progress.note("No IDL found. Skipping Anchor IDL upload."),
+                Some(idl) if !self.idl_exists() => {
+                    // e.g. the IDL upload was skipped on the first deploy
+                    self.init_or_upgrade_idl(progress, idl, true, options.retry)?;
+                }
+                Some(idl) => {
+                    progress.step("Uploading new IDL", || {
+                        self.runner.exec_retrying(
+                            anchor_cmd!(self, "idl")
+                                .arg("write-buffer")
+                                .arg(self.program_key.to_string())
+                                .arg("--filepath")
+                                .arg(idl),
+                            options.retry.anchor(),
+                        )
+                    })?;
+
+                    eprintln!(
+                        "WARNING: please manually run `anchor idl set-buffer {} --buffer <BUFFER>`",
+                        self.program_key
+                    );
+                    eprintln!("TODO: need to be able to hook into anchor for this");
+                }
+            }
+        }
+
+        progress.summary();
+        info!("{}", "Deployment success!".green());
+        let mut receipt = self.receipt(Action::Upgraded, Some(buffer_key.to_string()));
+        receipt.verifiable_hash = verifiable_hash;
+        receipt.signatures = signatures;
+        self.archive_receipt(receipt)
+    }
+
+    /// Reads the verifiable build hash stored next to the binary, if any.
+    ///
+    /// If `verify` is set, errors unless the hash exists and matches the
+    /// binary.
+    fn check_verifiable_hash(&self, verify: bool) -> Result<Option<String>> {
+        let hash_path = verifiable_hash_path(&self.deploy_bin);
+        if !hash_path.exists() {
+            if verify {
+                return Err(anyhow!(
+                    "No verifiable build hash at {}. Build with `anchor build --verifiable` and release again.",
+                    hash_path.display()
+                ));
+            }
+            return Ok(None);
+        }
+        let expected = fs::read_to_string(&hash_path)
+            .map_err(|e| format_err!("could not read {}: {}", hash_path.display(), e))?
+            .trim()
+            .to_lowercase();
+        if verify {
+            let actual = executable_hash(&fs::read(&self.deploy_bin)?);
+            if actual != expected {
+                return Err(anyhow!(
+                    "Hash of {} is {}, but its verifiable build hash is {}",
+                    self.deploy_bin.display(),
+                    actual,
+                    expected
+                ));
+            }
+        }
+        Ok(Some(expected))
+    }
+
+    /// Checks that the program data on chain has the given verifiable build
+    /// hash, reading it at `commitment`.
+    ///
+    /// A lagging RPC node may still serve the old program data right after
+    /// a deploy, so the check is retried while the data keeps changing, up
+    /// to [VERIFY_ATTEMPTS] times.
+    fn verify_deployed_hash(&self, expected: &str, commitment: Commitment) -> Result<()> {
+        let offset = UpgradeableLoaderState::programdata_data_offset()?;
+        let mut last = None;
+        for attempt in 1..=VERIFY_ATTEMPTS {
+            let actual = self
+                .fetch_program_data(commitment)?
+                .map(|data| executable_hash(&data[offset..]));
+            if actual.as_deref() == Some(expected) {
+                return Ok(());
+            }
+            // the same mismatch twice in a row is not going to go away
+            if actual.is_some() && actual == last {
+                break;
+            }
+            last = actual;
+            if attempt < VERIFY_ATTEMPTS {
+                thread::sleep(VERIFY_INTERVAL);
+            }
+        }
+        match last {
+            Some(actual) => Err(anyhow!(
+                "Deployed program hash is {}, expected verifiable build hash {}",
+                actual,
+                expected
+            )),
+            None => Err(anyhow!(
+                "Program data account of {} not found at {} commitment",
+                self.program_key,
+                commitment
+            )),
+        }
+    }
+
+    /// Fetches the program data account at a commitment, or `None` if it
+    /// doesn't exist at it.
+    fn fetch_program_data(&self, commitment: Commitment) -> Result<Option<Vec<u8>>> {
+        let (programdata, _) = Pubkey::find_program_address(
+            &[self.program_key.as_ref()],
+            &bpf_loader_upgradeable::id(),
+        );
+        let data = RpcClient::new(&self.network_url())?
+            .with_commitment(commitment)
+            .get_account_data(&programdata)?;
+        if let Some(data) = &data {
+            if data.len() < UpgradeableLoaderState::programdata_data_offset()? {
+                return Err(anyhow!("Program data account {} is too small", programdata));
+            }
+        }
+        Ok(data)
+    }
+
+    /// Reads the state of the program data account at a commitment, or
+    /// `None` if the account doesn't exist at it.
+    fn program_data_state(&self, commitment: Commitment) -> Result<Option<UpgradeableLoaderState>> {
+        let offset = UpgradeableLoaderState::programdata_data_offset()?;
+        self.fetch_program_data(commitment)?
+            .map(|data| {
+                bincode::deserialize(&data[..offset]).map_err(|e| {
+                    format_err!(
+                        "could not decode program data of {}: {}",
+                        self.program_key,
+                        e
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Polls the RPC until the program data is finalized with the deploy
+    /// slot and upgrade authority seen at the network's commitment, so a
+    /// deploy that is later dropped isn't reported as a success.
+    pub fn wait_finalized(&self, wait: &FinalizedWait) -> Result<()> {
+        let deployed = self
+            .program_data_state(self.deploy_config().commitment())?
+            .ok_or_else(|| format_err!("Program data account of {} not found", self.program_key))?;
+        let started = Instant::now();
+        loop {
+            if self.program_data_state(Commitment::Finalized)? == Some(deployed) {
+                return Ok(());
+            }
+            if started.elapsed() >= wait.timeout {
+                return Err(anyhow!(
+                    "Deploy of {} to {} was not finalized after {}",
+                    self.program_key,
+                    self.network,
+                    format_duration(wait.timeout)
+                ));
+            }
+            thread::sleep(wait.poll_interval);
+        }
+    }
+
+    /// Checks that the payer (the fee payer if set, else the deployer) can pay
+    /// rent for accounts of the given sizes, plus transaction fees for writing
+    /// them, before anything is sent. Returns the rent of each account.
+    fn check_balance(&self, fee_payer: Option<&Path>, account_lens: &[usize]) -> Result<Vec<u64>> {
+        let payer = match fee_payer {
+            Some(fee_payer) => signer_pubkey(fee_payer, &self.config.tools.solana())?,
+            None => self.deployer_pubkey()?,
+        };
+        let mut required = 0;
+        let mut rents = vec![];
+        for len in account_lens {
+            let rent = self.rent_exempt_minimum(*len)?;
+            rents.push(rent);
+            required += rent + write_fees(*len);
+        }
+        let balance = self.balance(&payer)?;
+        if balance < required {
+            return Err(anyhow!(
+                "{} {} needs about {} SOL but only has {} SOL",
+                if fee_payer.is_some() {
+                    "Fee payer"
+                } else {
+                    "Deployer"
+                },
+                payer,
+                lamports_to_sol(required),
+                lamports_to_sol(balance)
+            ));
+        }
+        Ok(rents)
+    }
+
+    /// Bytes allocated for the program data by a deploy, and the sizes of the
+    /// program, program data and buffer accounts it creates.
+    fn deploy_account_lens(&self, max_len: Option<usize>) -> Result<(usize, [usize; 3])> {
+        let bin_len = fs::metadata(&self.deploy_bin)?.len() as usize;
+        // `solana program deploy` allocates twice the binary by default.
+        let program_len = max_len.unwrap_or(bin_len * 2);
+        Ok((
+            program_len,
+            [
+                UpgradeableLoaderState::program_len()?,
+                UpgradeableLoaderState::programdata_len(program_len)?,
+                UpgradeableLoaderState::buffer_len(bin_len)?,
+            ],
+        ))
+    }
+
+    /// Estimates what deploying the program would cost at current rent
+    /// rates, without sending anything. `max_len` overrides the program's
+    /// config, as in [DeployOptions].
+    pub fn estimate_deploy_cost(&self, max_len: Option<usize>) -> Result<DeployCost> {
+        let (program_data_len, account_lens) = self.deploy_account_lens(self.max_len(max_len))?;
+        let rents = account_lens
+            .iter()
+            .map(|len| self.rent_exempt_minimum(*len))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(DeployCost {
+            program: self.program.clone(),
+            version: self.deploy_version.clone(),
+            network: self.network.clone(),
+            bin_len: fs::metadata(&self.deploy_bin)?.len() as usize,
+            program_data_len,
+            program_rent: rents[0],
+            program_data_rent: rents[1],
+            buffer_rent: rents[2],
+            fees: account_lens.iter().map(|len| write_fees(*len)).sum(),
+        })
+    }
+
+    /// Simulates switching the program to a buffer, paid for by the deployer.
+    pub fn simulate_upgrade(&self, buffer_key: &Pubkey) -> Result<Simulation> {
+        let deployer = self.deployer_pubkey()?;
+        let instruction = bpf_loader_upgradeable::upgrade(
+            &self.program_key,
+            buffer_key,
+            &self
+                .network_config
+                .upgrade_authority_pubkey(&self.config.tools.solana())?,
+            &deployer,
+        );
+        let transaction = Transaction::new_unsigned(Message::new(&[instruction], Some(&deployer)));
+        RpcClient::new(&self.network_url())?
+            .with_commitment(self.deploy_config().commitment())
+            .simulate_transaction(&transaction)
+    }
+
+    /// Fails early if the network's RPC is down or misconfigured, rather
+    /// than halfway through a deploy.
+    pub fn check_health(&self) -> Result<()> {
+        let url = self.network_url();
+        RpcClient::new(&url)?.get_health().map_err(|e| {
+            format_err!(
+                "RPC {} is unhealthy/unreachable: {}\nCheck the url of {} in Captain.toml, or pass --no-health-check to skip this check.",
+                url,
+                e,
+                self.network
+            )
+        })
+    }
+
+    /// Whether the deployer and the network's upgrade authority are the same
+    /// key. False if either is a signer URI, which would have to be asked
+    /// for its key, or if the upgrade authority can't be read here, which is
+    /// left for the solana CLI to report.
+    fn deployer_is_upgrade_authority(&self) -> Result<bool> {
+        let signers = [
+            SignerSource::parse(&self.network_config.upgrade_authority),
+            SignerSource::from_path(&self.deployer_path),
+        ];
+        if signers
+            .iter()
+            .any(|signer| matches!(signer, SignerSource::Uri(_)))
+        {
+            return Ok(false);
+        }
+        match self
+            .network_config
+            .upgrade_authority_pubkey(&self.config.tools.solana())
+        {
+            Ok(authority) => Ok(self.deployer_pubkey()? == authority),
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn deployer_pubkey(&self) -> Result<Pubkey> {
+        signer_pubkey(&self.deployer_path, &self.config.tools.solana())
+    }
+
+    /// Fetches the balance of an account in lamports.
+    pub fn balance(&self, pubkey: &Pubkey) -> Result<u64> {
+        // e.g. `1500000000 lamports`
+        let output = self.runner.exec_stdout(
+            solana_cmd!(self)
+                .arg("balance")
+                .arg(pubkey.to_string())
+                .arg("--lamports"),
+        )?;
+        output
+            .split_whitespace()
+            .next()
+            .and_then(|lamports| lamports.parse().ok())
+            .ok_or_else(|| format_err!("could not parse `solana balance` output: {}", output))
+    }
+
+    /// Fetches the lamports an account of `data_len` bytes needs to be
+    /// rent-exempt.
+    pub fn rent_exempt_minimum(&self, data_len: usize) -> Result<u64> {
+        let output = self.runner.exec_stdout(
+            solana_cmd!(self)
+                .arg("rent")
+                .arg(data_len.to_string())
+                .arg("--lamports")
+                .arg("--output")
+                .arg("json"),
+        )?;
+        let rent: RentInfo = serde_json::from_str(&output)
+            .map_err(|e| format_err!("could not parse `solana rent` output: {}", e))?;
+        Ok(rent.rent_exempt_minimum_lamports)
+    }
+
+    /// Writes the program binary to a new buffer and hands the buffer
+    /// authority to the upgrade authority.
+    ///
+    /// The buffer is closed if Captain is interrupted before the returned
+    /// guard is dropped. Without `upgrade_authority_keypair`, it can't be
+    /// closed once handed over, so no guard is returned.
+    fn write_buffer(
+        &self,
+        progress: &mut Progress,
+        budget: ComputeBudget,
+        use_rpc: bool,
+        fee_payer: Option<&Path>,
+        upgrade_authority_keypair: Option<&str>,
+        retry: RetryPolicy,
+    ) -> Result<(Pubkey, Vec<String>, Option<BufferGuard>)> {
+        let buffer_kp = solana_sdk::signer::keypair::Keypair::new();
+        let buffer_key = buffer_kp.pubkey();
+
+        let mut buffer_file = tempfile::Builder::new()
+            .prefix(clean::BUFFER_KEYPAIR_PREFIX)
+            .tempfile()?;
+        solana_sdk::signer::keypair::write_keypair(&buffer_kp, &mut buffer_file)
+            .map_err(|_| format_err!("could not generate temp buffer keypair"))?;
+        let guard = interrupt::track_buffer(
+            buffer_key,
+            buffer_file.path().to_path_buf(),
+            self.close_buffer_cmd(&buffer_key, &self.deployer_path, fee_payer)?,
+        );
+
+        let write = progress.step("Writing buffer", || {
+            info!("Buffer Pubkey: {}", buffer_key);
+            self.runner.exec_retrying(
+                with_compute_budget(
+                    with_use_rpc(
+                        with_fee_payer(
+                            solana_cmd!(self)
+                                .arg("program")
+                                .arg("write-buffer")
+                                .arg(&self.deploy_bin)
+                                .arg("--output")
+                                .arg("json")
+                                .arg("--buffer")
+                                .arg(buffer_file.path()),
+                            fee_payer,
+                        ),
+                        use_rpc,
+                    ),
+                    budget,
+                ),
+                retry,
+            )
+        });
+        if interrupt::interrupted() {
+            // the buffer may not close, in which case its keypair is needed
+            let _ = buffer_file.keep();
+        }
+        let signatures = parse_signatures(&write?.stdout);
+        self.print_signatures(&signatures);
+
+        progress.step("Setting buffer authority", || {
+            self.runner.exec(with_fee_payer(
+                solana_cmd!(self)
+                    .arg("program")
+                    .arg("set-buffer-authority")
+                    .arg(buffer_key.to_string())
+                    .arg("--new-buffer-authority")
+                    .arg(&self.network_config.upgrade_authority),
+                fee_payer,
+            ))
+        })?;
+        match upgrade_authority_keypair {
+            Some(keypair) => {
+                guard.set_close(self.close_buffer_cmd(
+                    &buffer_key,
+                    Path::new(keypair),
+                    fee_payer,
+                )?);
+                Ok((buffer_key, signatures, Some(guard)))
+            }
+            None => {
+                progress.note(&format!(
+                    "Buffer {} now belongs to the upgrade authority and is no longer closed if interrupted.",
+                    buffer_key
+                ));
+                Ok((buffer_key, signatures, None))
+            }
+        }
+    }
+
+    /// `solana program close` for a buffer, refunding its rent to the
+    /// deployer.
+    fn close_buffer_cmd(
+        &self,
+        buffer_key: &Pubkey,
+        buffer_authority: &Path,
+        fee_payer: Option<&Path>,
+    ) -> Result<Command> {
+        let mut cmd = solana_cmd!(self);
+        cmd.arg("program")
+            .arg("close")
+            .arg(buffer_key.to_string())
+            .arg("--buffer-authority")
+            .arg(buffer_authority)
+            .arg("--recipient")
+            .arg(self.deployer_pubkey()?.to_string());
+        with_fee_payer(&mut cmd, fee_payer);
+        Ok(cmd)
+    }
+
+    /// Checks that an existing buffer can be used to upgrade the program: it
+    /// must exist and be owned by the upgrade authority.
+    fn check_buffer(&self, buffer_key: &Pubkey) -> Result<()> {
+        let output = self
+            .runner
+            .exec_stdout(
+                solana_cmd!(self)
+                    .arg("program")
+                    .arg("show")
+                    .arg(buffer_key.to_string())
+                    .arg("--output")
+                    .arg("json"),
+            )
+            .map_err(|e| format_err!("could not find buffer {}: {}", buffer_key, e))?;
+        let buffer: BufferInfo = serde_json::from_str(&output)
+            .map_err(|e| format_err!("{} is not a program buffer: {}", buffer_key, e))?;
+        let upgrade_authority = self
+            .network_config
+            .upgrade_authority_pubkey(&self.config.tools.solana())?;
+        if buffer.authority != upgrade_authority.to_string() {
+            return Err(anyhow!(
+                "Buffer {} has authority {}, expected the upgrade authority {}",
+                buffer_key,
+                buffer.authority,
+                upgrade_authority
+            ));
+        }
+        Ok(())
+    }
+
+    fn receipt(&self, action: Action, buffer: Option<String>) -> Receipt {
+        let git = git_revision(&self.root);
+        Receipt {
+            program: self.program.clone(),
+            version: self.deploy_version.clone(),
+            network: self.network.clone(),
+            program_id: self.program_key.to_string(),
+            action,
+            buffer,
+            upgrade_authority: None,
+            idl_authority: None,
+            verifiable_hash: None,
+            immutable: false,
+            program_data_len: None,
+            rent_lamports: None,
+            git_commit: git.as_ref().map(|git| git.commit.clone()),
+            git_dirty: git.map(|git| git.dirty),
+            signatures: vec![],
+            companions: vec![],
+        }
+    }
+
+    /// Prints a link to each transaction on the Solana explorer.
+    fn print_signatures(&self, signatures: &[String]) {
+        for signature in signatures {
+            info!("Transaction: {}", self.explorer_url(signature));
+        }
+    }
+
+    /// Link to a transaction on the Solana explorer. Clusters other than
+    /// mainnet, devnet and testnet are linked by their RPC URL.
+    pub fn explorer_url(&self, signature: &str) -> String {
+        let url = format!("https://explorer.solana.com/tx/{}", signature);
+        match self.network {
+            Network::Mainnet => url,
+            Network::Devnet | Network::Testnet => format!("{}?cluster={}", url, self.network),
+            _ => Url::parse_with_params(
+                &url,
+                &[("cluster", "custom"), ("customUrl", &self.network_url())],
+            )
+            .map(String::from)
+            .unwrap_or(url),
+        }
+    }
+
+    /// Takes the lock on deploying this program to this network.
+    fn lock(&self, wait: bool) -> Result<DeployLock> {
+        DeployLock::acquire(&self.root, &self.program, &self.network, wait)
+    }
+
+    /// Where the receipt of a deploy or upgrade to this network is archived.
+    pub fn receipt_path(&self) -> PathBuf {
+        self.artifact_paths
+            .root
+            .join(format!("receipt-{}.json", self.network))
+    }
+
+    /// Saves the receipt next to the artifacts and runs the post-deploy hooks.
+    fn archive_receipt(&self, receipt: Receipt) -> Result<Receipt> {
+        receipt.save(&self.receipt_path())?;
+        hooks::post_deploy(&self.config.hooks, &receipt);
+        Ok(receipt)
+    }
+
+    /// Returns true if the program already has an Anchor IDL account.
+    pub fn idl_exists(&self) -> bool {
+        self.runner
+            .exec_stdout(
+                anchor_cmd!(self, "idl")
+                    .arg("fetch")
+                    .arg(self.program_key.to_string()),
+            )
+            .is_ok()
+    }
+
+    /// Warns if the new IDL won't fit in the program's IDL account, which
+    /// Anchor versions handle differently: some resize the account, others
+    /// fail or truncate the IDL. Nothing is checked if the account can't be
+    /// read.
+    fn check_idl_account_len(&self, idl_path: &Path) {
+        let current = idl::address(&self.program_key).and_then(|address| {
+            RpcClient::new(&self.network_url())?
+                .with_commitment(self.deploy_config().commitment())
+                .get_account_data(&address)
+        });
+        let current_len = match current {
+            Ok(Some(data)) => data.len(),
+            _ => return,
+        };
+        let needed_len = match idl::read(idl_path).and_then(|idl| idl::account_len(&idl)) {
+            Ok(len) => len,
+            Err(_) => return,
+        };
+        if needed_len > current_len {
+            eprintln!(
+                "{}",
+                format!(
+                    "WARNING: the new IDL needs about {} bytes, but the IDL account of {} holds {}. Anchor 0.30 and later resize it when upgrading the IDL; older versions fail or truncate it. In that case, run `anchor idl close {}` and deploy the IDL again.",
+                    needed_len, self.program_key, current_len, self.program_key
+                )
+                .yellow()
+            );
+        }
+    }
+
+    /// Creates the IDL account, or upgrades it in place if it already exists
+    /// (e.g. when re-running a deploy that failed after `anchor idl init`),
+    /// then hands the IDL authority to the upgrade authority if
+    /// `set_authority` is set. Failed anchor commands are retried as allowed
+    /// by [RetryPolicy::anchor].
+    fn init_or_upgrade_idl(
+        &self,
+        progress: &mut Progress,
+        idl: &Path,
+        set_authority: bool,
+        retry: RetryPolicy,
+    ) -> Result<()> {
+        self.upload_idl(progress, idl, retry)?;
+        if set_authority {
+            self.set_idl_authority(progress, retry)?;
+        }
+        Ok(())
+    }
+
+    /// Creates the IDL account, or upgrades it in place if it already exists.
+    fn upload_idl(&self, progress: &mut Progress, idl: &Path, retry: RetryPolicy) -> Result<()> {
+        let retry = retry.anchor();
+        if self.idl_exists() {
+            progress.step("Upgrading existing IDL", || {
+                self.runner.exec_retrying(
+                    anchor_cmd!(self, "idl")
+                        .arg("upgrade")
+                        .arg(self.program_key.to_string())
+                        .arg("--filepath")
+                        .arg(idl),
+                    retry,
+                )
+            })?;
+        } else {
+            progress.step("Initializing IDL", || {
+                self.runner.exec_retrying(
+                    anchor_cmd!(self, "idl")
+                        .arg("init")
+                        .arg(self.program_key.to_string())
+                        .arg("--filepath")
+                        .arg(idl),
+                    retry,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Hands the IDL authority to the upgrade authority.
+    fn set_idl_authority(&self, progress: &mut Progress, retry: RetryPolicy) -> Result<()> {
+        progress.step("Setting IDL authority", || {
+            self.runner.exec_retrying(
+                anchor_cmd!(self, "idl")
+                    .arg("set-authority")
+                    .arg("--program-id")
+                    .arg(self.program_key.to_string())
+                    .arg("--new-authority")
+                    .arg(&self.network_config.upgrade_authority),
+                retry.anchor(),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Bytes to allocate for the program data: the override if given, else
+    /// the program's.
+    pub fn max_len(&self, max_len: Option<usize>) -> Option<usize> {
+        max_len.or(self.config.program_config(&self.program).max_len)
+    }
+
+    /// The deploy settings of the network: `[deploy]` overridden by
+    /// `[networks.<network>.deploy]`.
+    pub fn deploy_config(&self) -> DeployConfig {
+        self.config.deploy.merge(&self.network_config.deploy)
+    }
+
+    /// The compute budget to use: the overrides if given, else the deploy
+    /// settings'. Errors if the compute unit limit is more than a
+    /// transaction can request.
+    pub fn compute_budget(
+        &self,
+        deploy_config: &DeployConfig,
+        priority_fee: Option<u64>,
+        compute_unit_limit: Option<u32>,
+    ) -> Result<ComputeBudget> {
+        let compute_unit_limit = compute_unit_limit.or(deploy_config.compute_unit_limit);
+        if let Some(limit) = compute_unit_limit {
+            if limit == 0 || limit > MAX_COMPUTE_UNIT_LIMIT {
+                return Err(anyhow!(
+                    "Compute unit limit {} must be between 1 and {}",
+                    limit,
+                    MAX_COMPUTE_UNIT_LIMIT
+                ));
+            }
+        }
+        Ok(ComputeBudget {
+            priority_fee: priority_fee.or(deploy_config.priority_fee),
+            compute_unit_limit,
+        })
+    }
+
+    /// The fee payer to use: the override if given, else the network's.
+    ///
+    /// When there is a separate fee payer, this checks that it is a readable
+    /// keypair and that the upgrade authority resolves, so a misconfigured
+    /// signer is caught before anything is sent.
+    pub fn fee_payer(&self, fee_payer: Option<&Path>) -> Result<Option<PathBuf>> {
+        let fee_payer = match fee_payer {
+            Some(fee_payer) => fee_payer.to_path_buf(),
+            None => match &self.network_config.fee_payer {
+                Some(fee_payer) => fee_payer.as_path_buf(),
+                None => return Ok(None),
+            },
+        };
+        if let SignerSource::File(path) = SignerSource::from_path(&fee_payer) {
+            solana_sdk::signer::keypair::read_keypair_file(&path)
+                .map_err(|_| format_err!("could not read fee payer kp file {}", path.display()))?;
+        }
+        self.network_config
+            .upgrade_authority_pubkey(&self.config.tools.solana())?;
+        Ok(Some(fee_payer))
+    }
+
+    /// Whether to pass `--use-rpc`: if forced, or if the deploy settings
+    /// enable it.
+    pub fn use_rpc(&self, deploy_config: &DeployConfig, use_rpc: bool) -> bool {
+        use_rpc || deploy_config.use_rpc()
+    }
+
     pub fn show_program(&self) -> Result<bool> {
-        let exit = command::exec_unhandled(
+        let exit = self.runner.exec_unhandled(
             solana_cmd!(self)
                 .arg("program")
                 .arg("show")
@@ -153,29 +2527,78 @@ impl Workspace {
         Ok(exit.status.success())
     }
 
-    pub fn copy_artifacts(&self) -> Result<()> {
-        command::exec(
-            std::process::Command::new("cp")
-                .arg(&self.program_paths.bin)
-                .arg(&self.artifact_paths.bin),
-        )?;
-        command::exec(
-            std::process::Command::new("cp")
-                .arg(&self.program_paths.idl)
-                .arg(&self.artifact_paths.idl),
+    /// Fetches the on-chain state of the program.
+    pub fn fetch_program_info(&self) -> Result<ProgramInfo> {
+        let output = self.runner.exec_stdout(
+            solana_cmd!(self)
+                .arg("program")
+                .arg("show")
+                .arg(self.program_key.to_string())
+                .arg("--output")
+                .arg("json"),
         )?;
+        serde_json::from_str(&output)
+            .map_err(|e| format_err!("could not parse `solana program show` output: {}", e))
+    }
+
+    /// Bytes the program data account must grow by to fit the binary being
+    /// deployed.
+    pub fn required_extension(&self) -> Result<usize> {
+        let bin_len = fs::metadata(&self.deploy_bin)?.len() as usize;
+        let data_len = self.fetch_program_info()?.data_len;
+        Ok(bin_len.saturating_sub(data_len))
+    }
+
+    /// Returns true if artifacts of this version were already released.
+    pub fn artifacts_exist(&self) -> Result<bool> {
+        self.artifact_store.exists(&self.artifact_paths)
+    }
+
+    /// Releases the built binary, its verifiable build hash and the IDL to
+    /// the artifact store, signing the binary first if a signing key is
+    /// configured.
+    pub fn copy_artifacts(&self) -> Result<()> {
+        if let Some(key) = &self.config.security.signing_key {
+            let signer = signing::sign(&self.program_paths.bin, &key.0)?;
+            info!(
+                "Signed {} with {}",
+                self.program_paths.bin.display(),
+                signer
+            );
+        }
+        self.artifact_store
+            .put_bin(&self.program_paths.bin, &self.artifact_paths)?;
+        if let Some(idl) = &self.program_paths.idl {
+            self.artifact_store.put_idl(idl, &self.artifact_paths)?;
+        }
         Ok(())
     }
 
+    /// Returns the newest source file of the program if it was modified after
+    /// the program binary was built.
+    pub fn find_stale_source(&self) -> Result<Option<PathBuf>> {
+        let programs_dir = self.config.programs_dir(&self.root);
+        let src_dir = match find_program_crate(&self.program, &self.root, &programs_dir) {
+            Ok(dir) if dir.join("src").is_dir() => dir.join("src"),
+            _ => return Ok(None),
+        };
+
+        let built = fs::metadata(&self.program_paths.bin)?.modified()?;
+        Ok(newest_file(&src_dir)?
+            .filter(|(_, modified)| *modified > built)
+            .map(|(path, _)| path))
+    }
+
     /// Returns true if this is also an Anchor workspace.
     pub fn has_anchor(&self) -> bool {
         self.root.join("Anchor.toml").exists()
     }
 
     pub fn network_url(&self) -> String {
-        self.network_config
-            .url
-            .clone()
-            .unwrap_or_else(|| self.network.url().to_string())
+        self.network_config.network_url(&self.network)
+    }
+
+    pub fn network_ws_url(&self) -> String {
+        self.network_config.network_ws_url(&self.network)
     }
 }
@@ -0,0 +1,79 @@
+use captain::clean;
+use captain::config::{CaptainPath, Config};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Creates `target/deploy` and `target/idl` outputs for two programs.
+fn build_outputs(root: &Path) {
+    let deploy = root.join("target").join("deploy");
+    let idl = root.join("target").join("idl");
+    fs::create_dir_all(&deploy).unwrap();
+    fs::create_dir_all(&idl).unwrap();
+    for name in &["my_program.so", "my_program-keypair.json", "other.so"] {
+        fs::write(deploy.join(name), b"").unwrap();
+    }
+    for name in &["my_program.json", "other.json"] {
+        fs::write(idl.join(name), b"{}").unwrap();
+    }
+}
+
+fn workspace_paths(removed: &[clean::Removed], root: &Path) -> Vec<PathBuf> {
+    removed
+        .iter()
+        .filter(|removed| removed.path.starts_with(root))
+        .map(|removed| removed.path.strip_prefix(root).unwrap().to_path_buf())
+        .collect()
+}
+
+#[test]
+fn dry_run_lists_program_outputs_without_removing_them() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    build_outputs(root);
+
+    let removed = clean::clean(&Config::default(), root, Some("my-program"), false, true).unwrap();
+
+    assert_eq!(
+        workspace_paths(&removed, root),
+        vec![
+            PathBuf::from("target/deploy/my_program.so"),
+            PathBuf::from("target/idl/my_program.json"),
+        ]
+    );
+    assert!(root.join("target/deploy/my_program.so").exists());
+}
+
+#[test]
+fn removes_all_outputs_but_keeps_keypairs_and_artifacts() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    build_outputs(root);
+    let mut config = Config::default();
+    config.paths.artifacts = CaptainPath(root.join("artifacts"));
+    fs::create_dir_all(root.join("artifacts/my_program/1.0.0")).unwrap();
+
+    let removed = clean::clean(&config, root, None, false, false).unwrap();
+
+    assert_eq!(workspace_paths(&removed, root).len(), 4);
+    assert!(!root.join("target/deploy/other.so").exists());
+    assert!(!root.join("target/idl/my_program.json").exists());
+    assert!(root.join("target/deploy/my_program-keypair.json").exists());
+    assert!(root.join("artifacts/my_program/1.0.0").exists());
+}
+
+#[test]
+fn removes_artifacts_if_asked() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    let mut config = Config::default();
+    config.paths.artifacts = CaptainPath(root.join("artifacts"));
+    fs::create_dir_all(root.join("artifacts")).unwrap();
+
+    let removed = clean::clean(&config, root, None, true, true).unwrap();
+
+    assert_eq!(
+        workspace_paths(&removed, root),
+        vec![PathBuf::from("artifacts")]
+    );
+}
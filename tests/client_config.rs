@@ -0,0 +1,36 @@
+use captain::client_config::ClientConfig;
+use captain::Network;
+use std::path::Path;
+
+#[test]
+fn renders_typescript_with_relative_idl_paths() {
+    let mut config = ClientConfig::new(Network::Devnet);
+    let path = Path::new("/work/app/src/programs.ts");
+    config.add(
+        "my_program",
+        "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin",
+        Some(Path::new(
+            "/work/.captain/artifacts/my_program/1.0.0/idl.json",
+        )),
+        path,
+    );
+    config.add("no_idl", "11111111111111111111111111111111", None, path);
+
+    assert_eq!(
+        config.render(path).unwrap(),
+        r#"// Generated by `captain deploy`. Do not edit.
+
+export const NETWORK = "devnet" as const;
+
+export const PROGRAMS = {
+  "my_program": {
+    programId: "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin",
+    idl: "../../.captain/artifacts/my_program/1.0.0/idl.json",
+  },
+  "no_idl": {
+    programId: "11111111111111111111111111111111",
+  },
+} as const;
+"#
+    );
+}
@@ -0,0 +1,26 @@
+use captain::command::{is_blockhash_error, is_signer_error};
+
+#[test]
+fn classifies_hardware_wallet_failures() {
+    assert!(is_signer_error(
+        "Error: Remote wallet error: Ledger operation not supported"
+    ));
+    assert!(is_signer_error(
+        "Error: hidapi error: hid_error is not implemented yet"
+    ));
+    assert!(is_signer_error("Error: No device found"));
+    assert!(!is_signer_error(
+        "Error: Remote wallet operation rejected by the user"
+    ));
+    assert!(!is_signer_error(
+        "Error: Account has insufficient funds for spend"
+    ));
+}
+
+#[test]
+fn classifies_expired_blockhashes() {
+    assert!(is_blockhash_error(
+        "Error: RPC response error -32002: Blockhash not found"
+    ));
+    assert!(!is_blockhash_error("Error: No device found"));
+}
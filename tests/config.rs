@@ -0,0 +1,264 @@
+use captain::config::{CaptainPath, Commitment, Config, NetworkConfig, NetworkName};
+use captain::keypair::SignerSource;
+use captain::Network;
+use solana_sdk::signature::{Keypair, Signer};
+use std::path::Path;
+use tempfile::TempDir;
+
+const CONFIG: &str = r#"
+[paths]
+artifacts = "./.captain/artifacts/"
+program_keypairs = "./.captain/program_keypairs"
+
+[deploy]
+priority_fee = 1000
+commitment = "finalized"
+
+[networks.devnet]
+upgrade_authority = "~/.config/solana/id.json"
+use_rpc = true
+
+[networks.mainnet]
+upgrade_authority = "~/.config/solana/id.json"
+priority_fee = 20000
+
+[networks.mainnet.deploy]
+compute_unit_limit = 200000
+priority_fee = 50000
+"#;
+
+#[test]
+fn network_deploy_settings_override_global_ones() {
+    let config: Config = CONFIG.parse().unwrap();
+
+    let devnet = config.deploy_config(&Network::Devnet).unwrap();
+    assert_eq!(devnet.priority_fee, Some(1000));
+    assert_eq!(devnet.commitment(), Commitment::Finalized);
+    // set directly on the network, as older configs do
+    assert!(devnet.use_rpc());
+
+    let mainnet = config.deploy_config(&Network::Mainnet).unwrap();
+    assert_eq!(mainnet.priority_fee, Some(50000));
+    assert_eq!(mainnet.compute_unit_limit, Some(200000));
+    assert!(!mainnet.use_rpc());
+}
+
+#[test]
+fn upgrade_authority_keypair_signs_for_address() {
+    let dir = TempDir::new().unwrap();
+    let keypair = Keypair::new();
+    let path = dir.path().join("authority.json");
+    solana_sdk::signer::keypair::write_keypair_file(&keypair, &path).unwrap();
+    let network_config = NetworkConfig {
+        upgrade_authority: keypair.pubkey().to_string(),
+        upgrade_authority_keypair: Some(CaptainPath(path.clone())),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        network_config.upgrade_authority_signer(),
+        Some(SignerSource::File(path))
+    );
+    network_config
+        .check_upgrade_authority_keypair(&Network::Devnet, Path::new("solana"))
+        .unwrap();
+}
+
+#[test]
+fn upgrade_authority_keypair_must_match_address() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("authority.json");
+    solana_sdk::signer::keypair::write_keypair_file(&Keypair::new(), &path).unwrap();
+    let network_config = NetworkConfig {
+        upgrade_authority: Keypair::new().pubkey().to_string(),
+        upgrade_authority_keypair: Some(CaptainPath(path)),
+        ..Default::default()
+    };
+
+    let err = network_config
+        .check_upgrade_authority_keypair(&Network::Devnet, Path::new("solana"))
+        .unwrap_err();
+
+    assert!(
+        err.to_string().contains("not the upgrade authority"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn upgrade_authority_address_has_no_signer() {
+    let network_config = NetworkConfig {
+        upgrade_authority: Keypair::new().pubkey().to_string(),
+        ..Default::default()
+    };
+
+    assert_eq!(network_config.upgrade_authority_signer(), None);
+}
+
+#[test]
+fn networks_are_written_sorted_by_name() {
+    let mut config = Config::default();
+    for network in &[
+        Network::Testnet,
+        Network::Mainnet,
+        Network::Localnet,
+        Network::Devnet,
+    ] {
+        config
+            .networks
+            .insert(network.clone(), NetworkConfig::default());
+    }
+
+    let written = toml::to_string(&config).unwrap();
+
+    let tables: Vec<&str> = written
+        .lines()
+        .filter(|line| line.starts_with("[networks."))
+        .collect();
+    assert_eq!(
+        tables,
+        vec![
+            "[networks.devnet]",
+            "[networks.localnet]",
+            "[networks.mainnet]",
+            "[networks.testnet]",
+        ]
+    );
+}
+
+#[test]
+fn networks_resolve_from_aliases() {
+    let config: Config = format!(
+        "{}\n[aliases]\nstaging = \"devnet\"\ndev = \"localnet\"\n",
+        CONFIG
+    )
+    .parse()
+    .unwrap();
+    let resolve = |name: &str| config.resolve_network(&NetworkName(name.to_string()));
+
+    assert_eq!(resolve("prod").unwrap(), Network::Mainnet);
+    assert_eq!(resolve("staging").unwrap(), Network::Devnet);
+    // configured aliases take precedence over built-in ones
+    assert_eq!(resolve("dev").unwrap(), Network::Localnet);
+    assert_eq!(resolve("devnet").unwrap(), Network::Devnet);
+    let err = resolve("nope").unwrap_err();
+    assert!(err.to_string().contains("staging"), "{}", err);
+}
+
+#[test]
+fn networks_table_rejects_aliases() {
+    let config = format!(
+        "{}\n[networks.prod]\nupgrade_authority = \"~/.config/solana/id.json\"\n",
+        CONFIG
+    );
+
+    assert!(config.parse::<Config>().is_err());
+}
+
+#[test]
+fn aliases_cannot_shadow_networks() {
+    let config = format!("{}\n[aliases]\ndevnet = \"mainnet\"\n", CONFIG);
+
+    let err = config.parse::<Config>().unwrap_err();
+
+    assert!(err.to_string().contains("aliases.devnet"), "{}", err);
+}
+
+#[test]
+fn env_vars_are_expanded() {
+    std::env::set_var("CAPTAIN_TEST_RPC_HOST", "rpc.example.com");
+    let config: Config = format!(
+        "{}\n[networks.testnet]\nupgrade_authority = \"~/.config/solana/id.json\"\nurl = \"https://${{CAPTAIN_TEST_RPC_HOST}}/$CAPTAIN_TEST_RPC_HOST\"\n",
+        CONFIG
+    )
+    .parse()
+    .unwrap();
+
+    let testnet = config.network_config(&Network::Testnet).unwrap();
+    assert_eq!(
+        testnet.url.as_deref(),
+        Some("https://rpc.example.com/rpc.example.com")
+    );
+}
+
+#[test]
+fn unset_env_var_is_an_error() {
+    std::env::remove_var("CAPTAIN_TEST_UNSET");
+    let config = format!(
+        "{}\n[networks.testnet]\nupgrade_authority = \"~/.config/solana/id.json\"\nurl = \"$CAPTAIN_TEST_UNSET\"\n",
+        CONFIG
+    );
+
+    let err = config.parse::<Config>().unwrap_err();
+
+    assert!(err.to_string().contains("networks.testnet.url"), "{}", err);
+}
+
+#[test]
+fn unset_env_var_uses_default() {
+    std::env::remove_var("CAPTAIN_TEST_UNSET_WITH_DEFAULT");
+    let config: Config = format!(
+        "{}\n[networks.testnet]\nupgrade_authority = \"~/.config/solana/id.json\"\nurl = \"${{CAPTAIN_TEST_UNSET_WITH_DEFAULT:-http://127.0.0.1:8899}}\"\n",
+        CONFIG
+    )
+    .parse()
+    .unwrap();
+
+    let testnet = config.network_config(&Network::Testnet).unwrap();
+    assert_eq!(testnet.url.as_deref(), Some("http://127.0.0.1:8899"));
+}
+
+#[test]
+fn double_dollar_is_a_literal_dollar() {
+    let config: Config = format!(
+        "{}\n[networks.testnet]\nupgrade_authority = \"~/.config/solana/id.json\"\nurl = \"https://rpc.example.com/$$key\"\n",
+        CONFIG
+    )
+    .parse()
+    .unwrap();
+
+    let testnet = config.network_config(&Network::Testnet).unwrap();
+    assert_eq!(testnet.url.as_deref(), Some("https://rpc.example.com/$key"));
+}
+
+#[test]
+fn networks_include_expands_env_vars() {
+    let dir = TempDir::new().unwrap();
+    let shared = dir.path().join("shared");
+    std::fs::create_dir(&shared).unwrap();
+    std::fs::write(
+        shared.join("networks.toml"),
+        "[networks.testnet]\nupgrade_authority = \"~/.config/solana/id.json\"\n",
+    )
+    .unwrap();
+    std::env::set_var("CAPTAIN_TEST_SHARED_DIR", &shared);
+    std::fs::write(
+        dir.path().join("Captain.toml"),
+        format!(
+            "networks_include = \"$CAPTAIN_TEST_SHARED_DIR/networks.toml\"\n{}",
+            CONFIG
+        ),
+    )
+    .unwrap();
+
+    let config = Config::load(dir.path(), None).unwrap();
+
+    assert!(config.network_config(&Network::Testnet).is_ok());
+}
+
+#[test]
+fn post_deploy_command_is_not_expanded() {
+    std::env::remove_var("CAPTAIN_PROGRAM");
+    let config: Config = format!(
+        "{}\n[hooks]\npost_deploy_command = \"echo $CAPTAIN_PROGRAM ${{CAPTAIN_VERSION}}\"\n",
+        CONFIG
+    )
+    .parse()
+    .unwrap();
+
+    assert_eq!(
+        config.hooks.post_deploy_command.as_deref(),
+        Some("echo $CAPTAIN_PROGRAM ${CAPTAIN_VERSION}")
+    );
+}
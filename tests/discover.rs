@@ -0,0 +1,60 @@
+//! Config discovery reads the current directory, so these tests get a
+//! process of their own.
+use captain::config::Network;
+use captain::LoadOptions;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+const CONFIG: &str = r#"
+networks_include = "networks.toml"
+
+[paths]
+artifacts = "./.captain/artifacts/"
+program_keypairs = "./.captain/program_keypairs"
+program_dir = "programs"
+"#;
+
+const MANIFEST: &str = r#"
+[package]
+name = "my_program"
+version = "0.1.0"
+"#;
+
+#[test]
+fn load_options_override_discovered_config() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    fs::write(root.join("Captain.toml"), CONFIG).unwrap();
+    fs::write(root.join("Cargo.toml"), MANIFEST).unwrap();
+    fs::create_dir(root.join("src")).unwrap();
+    fs::write(root.join("src").join("lib.rs"), "").unwrap();
+    fs::write(
+        root.join("networks.toml"),
+        "[networks.devnet]\nupgrade_authority = \"~/.config/solana/id.json\"\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("shared.toml"),
+        "[networks.testnet]\nupgrade_authority = \"~/.config/solana/id.json\"\n",
+    )
+    .unwrap();
+    env::set_current_dir(root).unwrap();
+
+    let (config, _, found) = LoadOptions::default().discover().unwrap();
+    assert_eq!(found, env::current_dir().unwrap());
+    assert_eq!(config.programs_dir(&found), found.join("programs"));
+    assert!(config.network_config(&Network::Devnet).is_ok());
+    assert!(config.network_config(&Network::Testnet).is_err());
+
+    let options = LoadOptions {
+        programs_dir: Some(PathBuf::from("onchain")),
+        network_config_file: Some(root.join("shared.toml")),
+        ..Default::default()
+    };
+    let (config, _, found) = options.discover().unwrap();
+    assert_eq!(config.programs_dir(&found), found.join("onchain"));
+    assert!(config.network_config(&Network::Devnet).is_err());
+    assert!(config.network_config(&Network::Testnet).is_ok());
+}
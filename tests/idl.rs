@@ -0,0 +1,15 @@
+use captain::idl;
+use serde_json::json;
+
+#[test]
+fn idl_account_len_grows_with_the_idl() {
+    let small = json!({ "version": "0.1.0", "name": "my_program", "instructions": [] });
+    let instructions: Vec<_> = (0..200)
+        .map(|i| json!({ "name": format!("instruction_{}", i), "accounts": [], "args": [] }))
+        .collect();
+    let large = json!({ "version": "0.2.0", "name": "my_program", "instructions": instructions });
+
+    let small_len = idl::account_len(&small).unwrap();
+    assert!(small_len > 8 + 32 + 4);
+    assert!(idl::account_len(&large).unwrap() > small_len);
+}
@@ -0,0 +1,37 @@
+use captain::keypair::SignerSource;
+use std::path::PathBuf;
+
+#[test]
+fn parses_signer_uris() {
+    assert_eq!(
+        SignerSource::parse("prompt://"),
+        SignerSource::Uri("prompt://".to_string())
+    );
+    assert_eq!(
+        SignerSource::parse("usb://ledger?key=0"),
+        SignerSource::Uri("usb://ledger?key=0".to_string())
+    );
+}
+
+#[test]
+fn parses_stdin() {
+    assert_eq!(SignerSource::parse("stdin"), SignerSource::Stdin);
+    assert_eq!(SignerSource::parse("stdin://"), SignerSource::Stdin);
+}
+
+#[test]
+fn parses_keypair_files() {
+    assert_eq!(
+        SignerSource::parse("file:///tmp/deployer.json"),
+        SignerSource::File(PathBuf::from("/tmp/deployer.json"))
+    );
+    assert_eq!(
+        SignerSource::parse("./.captain/deployers/devnet/deployer.json"),
+        SignerSource::File(PathBuf::from("./.captain/deployers/devnet/deployer.json"))
+    );
+    let home = shellexpand::tilde("~").to_string();
+    assert_eq!(
+        SignerSource::parse("~/.config/solana/id.json"),
+        SignerSource::File(PathBuf::from(home).join(".config/solana/id.json"))
+    );
+}
@@ -0,0 +1,77 @@
+use captain::config::{Config, Network, CONFIG_VERSION};
+use captain::migrate::migrate;
+
+#[test]
+fn migrates_unversioned_config() {
+    let migration = migrate(
+        r#"
+[networks.mainnet-beta]
+url = "https://rpc.example.com"
+ws-url = "wss://rpc.example.com"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(migration.from_version, 0);
+    assert_eq!(migration.to_version, CONFIG_VERSION);
+    for change in &[
+        "Renamed networks.mainnet-beta to networks.mainnet",
+        "Renamed networks.mainnet.ws-url to networks.mainnet.ws_url",
+        "Set networks.mainnet.upgrade_authority to \"~/.config/solana/id.json\"",
+        "Set paths.artifacts to \"./.captain/artifacts/\"",
+    ] {
+        assert!(
+            migration.changes.contains(&change.to_string()),
+            "{:?}",
+            migration.changes
+        );
+    }
+    assert_eq!(
+        migration.changes.last().unwrap(),
+        &format!("Set version to {}", CONFIG_VERSION)
+    );
+    assert!(migration.unknown_keys.is_empty());
+
+    let config: Config = migration.contents.parse().unwrap();
+    assert_eq!(config.version, Some(CONFIG_VERSION));
+    let mainnet = config.network_config(&Network::Mainnet).unwrap();
+    assert_eq!(mainnet.url.as_deref(), Some("https://rpc.example.com"));
+    assert_eq!(mainnet.ws_url.as_deref(), Some("wss://rpc.example.com"));
+    assert_eq!(mainnet.upgrade_authority, "~/.config/solana/id.json");
+}
+
+#[test]
+fn current_config_is_left_as_is() {
+    let migration = migrate(&format!(
+        r#"
+version = {}
+
+[paths]
+artifacts = "./.captain/artifacts/"
+program_keypairs = "./.captain/program_keypairs"
+
+[networks.devnet]
+upgrade_authority = "~/.config/solana/id.json"
+"#,
+        CONFIG_VERSION
+    ))
+    .unwrap();
+
+    assert_eq!(migration.from_version, CONFIG_VERSION);
+    assert_eq!(migration.to_version, CONFIG_VERSION);
+    assert!(migration.changes.is_empty(), "{:?}", migration.changes);
+    let config: Config = migration.contents.parse().unwrap();
+    assert_eq!(config.version, Some(CONFIG_VERSION));
+    assert!(config.network_config(&Network::Devnet).is_ok());
+}
+
+#[test]
+fn newer_config_is_rejected() {
+    let err = migrate(&format!("version = {}\n", CONFIG_VERSION + 1)).unwrap_err();
+
+    assert!(
+        err.to_string().contains("Upgrade Captain"),
+        "unexpected error: {}",
+        err
+    );
+}
@@ -0,0 +1,75 @@
+use anyhow::anyhow;
+use captain::schedule;
+use std::collections::BTreeMap;
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn depends_on(edges: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+    edges
+        .iter()
+        .map(|(program, dependencies)| (program.to_string(), names(dependencies)))
+        .collect()
+}
+
+#[test]
+fn orders_programs_after_their_dependencies() {
+    let programs = names(&["proxy", "pool", "registry"]);
+    let depends_on = depends_on(&[("proxy", &["pool"]), ("pool", &["registry", "external"])]);
+
+    assert_eq!(
+        schedule::order(&programs, &depends_on).unwrap(),
+        names(&["registry", "pool", "proxy"])
+    );
+}
+
+#[test]
+fn rejects_dependency_cycles() {
+    let programs = names(&["a", "b", "c"]);
+    let depends_on = depends_on(&[("a", &["b"]), ("b", &["c"]), ("c", &["b"])]);
+
+    let err = schedule::order(&programs, &depends_on).unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "Programs depend on each other in a cycle: b -> c -> b"
+    );
+}
+
+#[test]
+fn skips_dependents_of_failed_programs() {
+    let programs = names(&["registry", "pool", "proxy", "other"]);
+    let depends_on = depends_on(&[("pool", &["registry"]), ("proxy", &["pool"])]);
+    let mut results = vec![];
+
+    let timings = schedule::run(
+        &programs,
+        &depends_on,
+        2,
+        |program| {
+            if program == "registry" {
+                Err(anyhow!("boom"))
+            } else {
+                Ok(())
+            }
+        },
+        |program, result| {
+            results.push((program.to_string(), result.is_ok()));
+            Ok(true)
+        },
+    )
+    .unwrap();
+
+    results.sort();
+    assert_eq!(
+        results,
+        vec![
+            ("other".to_string(), true),
+            ("pool".to_string(), false),
+            ("proxy".to_string(), false),
+            ("registry".to_string(), false),
+        ]
+    );
+    assert_eq!(timings.len(), 2);
+}
@@ -0,0 +1,32 @@
+use captain::signing;
+use solana_sdk::signature::{Keypair, Signer};
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn verifies_signed_binaries() {
+    let dir = TempDir::new().unwrap();
+    let key = dir.path().join("signing.json");
+    let keypair = Keypair::new();
+    solana_sdk::signer::keypair::write_keypair_file(&keypair, &key).unwrap();
+    let bin = dir.path().join("program.so");
+    fs::write(&bin, vec![1u8; 100]).unwrap();
+
+    assert_eq!(signing::sign(&bin, &key).unwrap(), keypair.pubkey());
+    assert!(signing::signature_path(&bin).exists());
+    assert_eq!(
+        signing::verify(&bin, &keypair.pubkey()).unwrap(),
+        keypair.pubkey()
+    );
+
+    let err = signing::verify(&bin, &Keypair::new().pubkey()).unwrap_err();
+    assert!(
+        err.to_string().contains("not the expected signer"),
+        "{}",
+        err
+    );
+
+    fs::write(&bin, vec![2u8; 100]).unwrap();
+    let err = signing::verify(&bin, &keypair.pubkey()).unwrap_err();
+    assert!(err.to_string().contains("does not match"), "{}", err);
+}
@@ -0,0 +1,26 @@
+use captain::config::{CaptainPath, Config, KeypairScheme};
+use captain::status;
+use solana_sdk::signature::{Keypair, Signer};
+use tempfile::TempDir;
+
+#[test]
+fn programs_groups_keypairs_newest_first() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.paths.program_keypairs = CaptainPath(dir.path().to_path_buf());
+    config.paths.keypair_scheme = KeypairScheme::PerVersion;
+    let write = |name: &str| {
+        let keypair = Keypair::new();
+        solana_sdk::signer::keypair::write_keypair_file(&keypair, dir.path().join(name)).unwrap();
+        keypair.pubkey()
+    };
+    let v1_9 = write("my-program-1.9.0.json");
+    let v1_10 = write("my-program-1.10.0.json");
+    let other = write("other-0.1.0-beta.1.json");
+
+    let programs = status::programs(&config).unwrap();
+
+    assert_eq!(programs.len(), 2);
+    assert_eq!(programs["my-program"].as_ref().unwrap(), &vec![v1_10, v1_9]);
+    assert_eq!(programs["other"].as_ref().unwrap(), &vec![other]);
+}
@@ -0,0 +1,485 @@
+use captain::command::RecordingRunner;
+use captain::config::{ArtifactPaths, Config, NetworkConfig};
+use captain::error::CaptainError;
+use captain::receipt::Action;
+use captain::store::LocalStore;
+use captain::workspace::{self, DeployOptions, ProgramPaths, UpgradeOptions, Workspace};
+use captain::Network;
+use semver::Version;
+use solana_sdk::signature::{Keypair, Signer};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+const UPGRADE_AUTHORITY: &str = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+
+fn workspace(dir: &Path, runner: &RecordingRunner) -> Workspace {
+    let deployer_path = dir.join("deployer.json");
+    solana_sdk::signer::keypair::write_keypair_file(&Keypair::new(), &deployer_path).unwrap();
+    let program_kp_path = dir.join("my_program-1.x.json");
+    let program_kp = Keypair::new();
+    solana_sdk::signer::keypair::write_keypair_file(&program_kp, &program_kp_path).unwrap();
+    let bin = dir.join("my_program.so");
+    fs::write(&bin, vec![1u8; 1000]).unwrap();
+    let idl = dir.join("my_program.json");
+    fs::write(&idl, "{}").unwrap();
+
+    let network_config = NetworkConfig {
+        deployer: Some(deployer_path.to_str().unwrap().parse().unwrap()),
+        upgrade_authority: UPGRADE_AUTHORITY.to_string(),
+        ..Default::default()
+    };
+    let mut config = Config::default();
+    config
+        .networks
+        .insert(Network::Localnet, network_config.clone());
+
+    Workspace {
+        program: "my_program".to_string(),
+        root: dir.to_path_buf(),
+        network: Network::Localnet,
+        deployer_path,
+        deploy_version: Version::new(1, 0, 0),
+        program_paths: ProgramPaths {
+            bin: bin.clone(),
+            idl: Some(idl),
+            id: Some(program_kp_path),
+        },
+        config,
+        network_config,
+        artifact_paths: ArtifactPaths {
+            root: dir.to_path_buf(),
+            bin: bin.clone(),
+            idl: dir.join("idl.json"),
+        },
+        artifact_store: Box::new(LocalStore),
+        deploy_bin: bin,
+        program_key: program_kp.pubkey(),
+        decrypted_program_keypair: None,
+        stdin_deployer_keypair: None,
+        runner: Box::new(runner.clone()),
+    }
+}
+
+/// The recorded commands without the
+/// `solana --url <url> --ws <url> --keypair <path> --commitment <level>`
+/// prefix.
+fn subcommands(runner: &RecordingRunner) -> Vec<String> {
+    runner
+        .calls()
+        .iter()
+        .map(|call| {
+            assert_eq!(call[0], "solana");
+            assert_eq!(call[1], "--url");
+            assert_eq!(call[3], "--ws");
+            assert_eq!(call[5], "--keypair");
+            assert_eq!(call[7..9], ["--commitment", "confirmed"]);
+            call[9..].join(" ")
+        })
+        .collect()
+}
+
+fn funded(runner: &RecordingRunner) {
+    runner
+        .respond(" balance ", true, "100000000000 lamports")
+        .respond(" rent ", true, r#"{"rentExemptMinimumLamports": 1000000}"#);
+}
+
+#[test]
+fn deploy_runs_expected_commands() {
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    funded(&runner);
+    runner.respond("program show", false, "");
+    let workspace = workspace(dir.path(), &runner);
+
+    let receipt = workspace
+        .deploy(&mut captain::Progress::new(true), &DeployOptions::default())
+        .unwrap();
+
+    let key = workspace.program_key.to_string();
+    let kp = workspace.program_paths.id.clone().unwrap();
+    assert_eq!(
+        subcommands(&runner),
+        vec![
+            format!("program show {}", key),
+            "rent 36 --lamports --output json".to_string(),
+            "rent 2045 --lamports --output json".to_string(),
+            "rent 1037 --lamports --output json".to_string(),
+            format!(
+                "balance {} --lamports",
+                deployer_pubkey(&workspace.deployer_path)
+            ),
+            format!(
+                "program deploy {} --program-id {}",
+                workspace.deploy_bin.display(),
+                kp.display()
+            ),
+            format!(
+                "program set-upgrade-authority {} --new-upgrade-authority {}",
+                key, UPGRADE_AUTHORITY
+            ),
+            format!("program show {}", key),
+        ]
+    );
+    assert_eq!(receipt.action, Action::Deployed);
+    assert!(workspace.receipt_path().exists());
+}
+
+#[test]
+fn deploy_skips_deployed_program() {
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    let workspace = workspace(dir.path(), &runner);
+
+    let receipt = workspace
+        .deploy(&mut captain::Progress::new(true), &DeployOptions::default())
+        .unwrap();
+
+    assert_eq!(
+        subcommands(&runner),
+        vec![format!("program show {}", workspace.program_key)]
+    );
+    assert_eq!(receipt.action, Action::AlreadyDeployed);
+}
+
+#[test]
+fn deploy_skip_show_skips_existence_check() {
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    funded(&runner);
+    let workspace = workspace(dir.path(), &runner);
+
+    let receipt = workspace
+        .deploy(
+            &mut captain::Progress::new(true),
+            &DeployOptions {
+                skip_show: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let calls = subcommands(&runner);
+    assert!(calls[0].starts_with("rent "), "{:?}", calls);
+    assert!(
+        calls.iter().any(|call| call.starts_with("program deploy ")),
+        "{:?}",
+        calls
+    );
+    assert_eq!(receipt.action, Action::Deployed);
+}
+
+#[test]
+fn deploy_skips_authority_transfer_to_deployer() {
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    funded(&runner);
+    runner.respond("program show", false, "");
+    let mut workspace = workspace(dir.path(), &runner);
+    workspace.network_config.upgrade_authority = deployer_pubkey(&workspace.deployer_path);
+
+    let receipt = workspace
+        .deploy(&mut captain::Progress::new(true), &DeployOptions::default())
+        .unwrap();
+
+    let calls = subcommands(&runner);
+    assert!(
+        !calls
+            .iter()
+            .any(|call| call.starts_with("program set-upgrade-authority")),
+        "{:?}",
+        calls
+    );
+    assert_eq!(receipt.action, Action::Deployed);
+}
+
+/// Whether any recorded command is an `anchor idl <subcommand>`.
+fn ran_anchor_idl(runner: &RecordingRunner, subcommand: &str) -> bool {
+    runner.calls().iter().any(|call| {
+        call[0] == "anchor" && call[1] == "idl" && call[2..].iter().any(|arg| arg == subcommand)
+    })
+}
+
+#[test]
+fn deploy_hands_idl_authority_to_upgrade_authority() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("Anchor.toml"), "").unwrap();
+    let runner = RecordingRunner::new();
+    funded(&runner);
+    runner
+        .respond("program show", false, "")
+        .respond(" fetch ", false, "");
+    let workspace = workspace(dir.path(), &runner);
+
+    let receipt = workspace
+        .deploy(&mut captain::Progress::new(true), &DeployOptions::default())
+        .unwrap();
+
+    assert!(ran_anchor_idl(&runner, "set-authority"));
+    assert_eq!(receipt.idl_authority.as_deref(), Some(UPGRADE_AUTHORITY));
+}
+
+#[test]
+fn deploy_keeps_idl_authority() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("Anchor.toml"), "").unwrap();
+    let runner = RecordingRunner::new();
+    funded(&runner);
+    runner
+        .respond("program show", false, "")
+        .respond(" fetch ", false, "");
+    let workspace = workspace(dir.path(), &runner);
+
+    let receipt = workspace
+        .deploy(
+            &mut captain::Progress::new(true),
+            &DeployOptions {
+                keep_idl_authority: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert!(ran_anchor_idl(&runner, "init"));
+    assert!(!ran_anchor_idl(&runner, "set-authority"));
+    assert_eq!(
+        receipt.upgrade_authority.as_deref(),
+        Some(UPGRADE_AUTHORITY)
+    );
+    assert_eq!(
+        receipt.idl_authority,
+        Some(deployer_pubkey(&workspace.deployer_path))
+    );
+}
+
+#[test]
+fn deploy_stops_without_enough_balance() {
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    runner
+        .respond("program show", false, "")
+        .respond(" balance ", true, "10 lamports")
+        .respond(" rent ", true, r#"{"rentExemptMinimumLamports": 1000000}"#);
+    let workspace = workspace(dir.path(), &runner);
+
+    let err = workspace
+        .deploy(&mut captain::Progress::new(true), &DeployOptions::default())
+        .unwrap_err();
+
+    assert!(err.to_string().contains("needs about"), "{}", err);
+    assert!(!subcommands(&runner)
+        .iter()
+        .any(|call| call.starts_with("program deploy")));
+}
+
+#[test]
+fn upgrade_runs_expected_commands() {
+    std::env::set_var("UPGRADE_AUTHORITY_KEYPAIR", "usb://ledger");
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    funded(&runner);
+    runner.respond("--output json", true, r#"{"dataLen": 2000}"#);
+    let workspace = workspace(dir.path(), &runner);
+
+    let receipt = workspace
+        .upgrade(
+            &mut captain::Progress::new(true),
+            &UpgradeOptions::default(),
+        )
+        .unwrap();
+
+    let calls = runner.calls();
+    let commands: Vec<String> = calls.iter().map(|call| call[9..11].join(" ")).collect();
+    assert_eq!(
+        commands,
+        vec![
+            "program show".to_string(),
+            "program show".to_string(),
+            "rent 1037".to_string(),
+            format!("balance {}", deployer_pubkey(&workspace.deployer_path)),
+            "program write-buffer".to_string(),
+            "program set-buffer-authority".to_string(),
+            "program deploy".to_string(),
+            "program show".to_string(),
+        ]
+    );
+    // the buffer is switched to with the upgrade authority
+    let buffer = receipt.buffer.clone().unwrap();
+    assert_eq!(calls[6][6], "usb://ledger");
+    assert_eq!(
+        calls[6][9..].join(" "),
+        format!(
+            "program deploy --buffer {} --program-id {}",
+            buffer, workspace.program_key
+        )
+    );
+    assert_eq!(receipt.action, Action::Upgraded);
+}
+
+#[test]
+fn upgrade_write_only_is_finalized_from_buffer() {
+    std::env::set_var("UPGRADE_AUTHORITY_KEYPAIR", "usb://ledger");
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    funded(&runner);
+    runner.respond(
+        "--output json",
+        true,
+        &format!(
+            r#"{{"dataLen": 2000, "authority": "{}"}}"#,
+            UPGRADE_AUTHORITY
+        ),
+    );
+    let workspace = workspace(dir.path(), &runner);
+
+    let written = workspace
+        .upgrade(
+            &mut captain::Progress::new(true),
+            &UpgradeOptions {
+                write_only: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert_eq!(written.action, Action::BufferWritten);
+    let calls = subcommands(&runner);
+    assert!(calls
+        .last()
+        .unwrap()
+        .starts_with("program set-buffer-authority"));
+    assert!(!calls.iter().any(|call| call.starts_with("program deploy")));
+    let pending = dir
+        .path()
+        .join(".captain/state/my_program-1.0.0-localnet-buffer.json");
+    assert!(pending.exists());
+
+    let upgraded = workspace
+        .upgrade(
+            &mut captain::Progress::new(true),
+            &UpgradeOptions {
+                buffer: Some(written.buffer.clone().unwrap().parse().unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert_eq!(upgraded.action, Action::Upgraded);
+    assert_eq!(upgraded.buffer, written.buffer);
+    assert!(!pending.exists());
+}
+
+#[test]
+fn upgrade_fails_if_program_not_deployed() {
+    std::env::set_var("UPGRADE_AUTHORITY_KEYPAIR", "usb://ledger");
+    let dir = TempDir::new().unwrap();
+    let runner = RecordingRunner::new();
+    runner.respond("program show", false, "");
+    let workspace = workspace(dir.path(), &runner);
+
+    let err = workspace
+        .upgrade(
+            &mut captain::Progress::new(true),
+            &UpgradeOptions::default(),
+        )
+        .unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref::<CaptainError>(),
+        Some(CaptainError::ProgramNotFound)
+    ));
+    assert_eq!(subcommands(&runner).len(), 1);
+}
+
+#[test]
+fn program_version_inherits_workspace_version() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    fs::write(
+        root.join("Cargo.toml"),
+        r#"
+[workspace]
+members = ["programs/*"]
+
+[workspace.package]
+version = "1.2.3"
+"#,
+    )
+    .unwrap();
+    let program_dir = root.join("programs").join("my-program");
+    fs::create_dir_all(&program_dir).unwrap();
+    fs::write(
+        program_dir.join("Cargo.toml"),
+        r#"
+[package]
+name = "my-program"
+version.workspace = true
+"#,
+    )
+    .unwrap();
+
+    let version =
+        workspace::get_program_version("my_program", root, &root.join("programs")).unwrap();
+
+    assert_eq!(version, Version::new(1, 2, 3));
+}
+
+#[test]
+fn missing_program_crate_names_programs_dir() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    let programs_dir = root.join("onchain");
+
+    let err = workspace::get_program_version("my_program", root, &programs_dir).unwrap_err();
+
+    assert!(err.to_string().contains(&format!(
+        "Programs are looked up in {}",
+        programs_dir.display()
+    )));
+}
+
+#[test]
+fn finds_target_output_with_dashed_name() {
+    let dir = TempDir::new().unwrap();
+    let bin = dir.path().join("my-program.so");
+    fs::write(&bin, b"").unwrap();
+
+    let found = workspace::find_target_output(dir.path(), "my_program", "so").unwrap();
+
+    assert_eq!(found, bin);
+}
+
+#[test]
+fn finds_target_output_with_underscored_name() {
+    let dir = TempDir::new().unwrap();
+    let idl = dir.path().join("my_program.json");
+    fs::write(&idl, "{}").unwrap();
+
+    let found = workspace::find_target_output(dir.path(), "my-program", "json").unwrap();
+
+    assert_eq!(found, idl);
+}
+
+#[test]
+fn missing_target_output_lists_tried_paths() {
+    let dir = TempDir::new().unwrap();
+
+    let err = workspace::find_target_output(dir.path(), "my_program", "so").unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "my_program.so not found at paths {}, {}",
+            dir.path().join("my_program.so").display(),
+            dir.path().join("my-program.so").display()
+        )
+    );
+}
+
+fn deployer_pubkey(path: &Path) -> String {
+    solana_sdk::signer::keypair::read_keypair_file(path)
+        .unwrap()
+        .pubkey()
+        .to_string()
+}